@@ -0,0 +1,665 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::credentials::Credentials;
+
+/// Lifetime assumed for tokens that don't say, and the minimum the token
+/// spec lets a registry advertise.
+const MIN_TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Tokens are renewed this long before they expire, so a request is never
+/// sent with one that runs out in flight.
+const REFRESH_MARGIN: Duration = Duration::from_secs(10);
+
+/// A `WWW-Authenticate` challenge from a registry's `401` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Challenge {
+    Basic {
+        realm: Option<String>,
+    },
+    Bearer {
+        realm: String,
+        service: Option<String>,
+        scope: Option<String>,
+    },
+}
+
+impl Challenge {
+    /// Parses a `WWW-Authenticate` value, which may hold several challenges
+    /// (`Bearer realm="...", Basic realm="..."`), skipping schemes other
+    /// than Basic and Bearer.
+    pub fn parse_all(header: &str) -> Result<Vec<Self>> {
+        // Split on commas outside quoted strings; an item whose first word
+        // is not a `key=value` starts the next challenge.
+        let mut items = Vec::new();
+        let mut item = String::new();
+        let mut quoted = false;
+        let mut chars = header.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '\\' if quoted => {
+                    item.push(c);
+                    item.extend(chars.next());
+                    continue;
+                }
+                ',' if !quoted => {
+                    items.push(std::mem::take(&mut item));
+                    continue;
+                }
+                _ => {}
+            }
+            item.push(c);
+        }
+        items.push(item);
+
+        let mut challenges: Vec<(String, Vec<String>)> = Vec::new();
+        for item in items
+            .iter()
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+        {
+            let first_word = item.split_whitespace().next().unwrap_or_default();
+            match challenges.last_mut() {
+                Some((_, params)) if first_word.contains('=') => params.push(item.to_string()),
+                _ => {
+                    let first_param = item[first_word.len()..].trim();
+                    let params = if first_param.is_empty() {
+                        Vec::new()
+                    } else {
+                        vec![first_param.to_string()]
+                    };
+                    challenges.push((first_word.to_string(), params));
+                }
+            }
+        }
+
+        let mut parsed = Vec::new();
+        for (scheme, params) in challenges {
+            if !matches!(scheme.to_ascii_lowercase().as_str(), "basic" | "bearer") {
+                tracing::debug!("Skipping unsupported authentication scheme {}", scheme);
+                continue;
+            }
+            parsed.push(format!("{} {}", scheme, params.join(", ")).parse()?);
+        }
+        Ok(parsed)
+    }
+
+    /// Parses the comma-separated `key=value` auth-params after the scheme;
+    /// values may be quoted, with `\` escapes.
+    fn parse_params(params: &str) -> Result<HashMap<String, String>> {
+        let mut parsed = HashMap::new();
+        let mut chars = params.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+            if chars.peek().is_none() {
+                return Ok(parsed);
+            }
+
+            let key: String =
+                std::iter::from_fn(|| chars.next_if(|c| *c != '=' && *c != ',')).collect();
+            if chars.next() != Some('=') {
+                return Err(anyhow!("Missing value for auth parameter '{}'", key.trim()));
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            let mut value = String::new();
+            if chars.next_if_eq(&'"').is_some() {
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err(anyhow!("Unterminated quoted auth parameter")),
+                    }
+                }
+            } else {
+                value.extend(std::iter::from_fn(|| chars.next_if(|c| *c != ',')));
+                value = value.trim().to_string();
+            }
+
+            parsed.insert(key.trim().to_ascii_lowercase(), value);
+        }
+    }
+}
+
+impl FromStr for Challenge {
+    type Err = anyhow::Error;
+
+    fn from_str(header: &str) -> Result<Self> {
+        let header = header.trim();
+        let (scheme, params) = header.split_once(' ').unwrap_or((header, ""));
+        let mut params = Self::parse_params(params)
+            .with_context(|| format!("Invalid WWW-Authenticate header: {}", header))?;
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "basic" => Ok(Challenge::Basic {
+                realm: params.remove("realm"),
+            }),
+            "bearer" => Ok(Challenge::Bearer {
+                realm: params
+                    .remove("realm")
+                    .ok_or_else(|| anyhow!("Bearer challenge without a realm: {}", header))?,
+                service: params.remove("service"),
+                scope: params.remove("scope"),
+            }),
+            _ => Err(anyhow!("Unsupported authentication scheme: {}", scheme)),
+        }
+    }
+}
+
+/// Client id sent with identity-token grants, which the OAuth2 token
+/// endpoint requires.
+const CLIENT_ID: &str = "spacejar";
+
+/// Token endpoint response; registries answer with `token`, the OAuth2
+/// style `access_token`, or both.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug)]
+struct BearerToken {
+    value: String,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+enum Scheme {
+    Anonymous,
+    /// base64 of `username:password`
+    Basic(String),
+    Bearer {
+        client: Client,
+        realm: String,
+        service: Option<String>,
+        scope: String,
+        credentials: Option<Credentials>,
+        token: Mutex<Option<BearerToken>>,
+    },
+}
+
+/// Authorization for requests to one repository. Bearer tokens are fetched
+/// from the realm the registry advertised and renewed when they expire, so
+/// a long pull or push keeps working past the token's lifetime.
+#[derive(Debug)]
+pub struct RegistryAuth {
+    scheme: Scheme,
+}
+
+impl RegistryAuth {
+    pub fn anonymous() -> Self {
+        Self {
+            scheme: Scheme::Anonymous,
+        }
+    }
+
+    /// Answers `challenge` for `scope` (e.g. `repository:library/python:pull`)
+    /// with `credentials`, or anonymously when there are none. The first
+    /// token is fetched straight away so bad credentials or scopes fail
+    /// before any transfer starts.
+    pub async fn from_challenge(
+        client: &Client,
+        challenge: Challenge,
+        scope: String,
+        credentials: Option<Credentials>,
+    ) -> Result<Self> {
+        match challenge {
+            Challenge::Basic { realm } => match credentials {
+                Some(Credentials::Password { username, password }) => Ok(Self {
+                    scheme: Scheme::Basic(STANDARD.encode(format!("{}:{}", username, password))),
+                }),
+                Some(Credentials::IdentityToken(_)) => Err(anyhow!(
+                    "Registry requires Basic authentication, which an identity token cannot answer"
+                )),
+                None => Err(anyhow!(
+                    "Registry{} requires Basic authentication, but no credentials are configured",
+                    realm.map(|r| format!(" realm '{}'", r)).unwrap_or_default()
+                )),
+            },
+            Challenge::Bearer { realm, service, .. } => {
+                let auth = Self {
+                    scheme: Scheme::Bearer {
+                        client: client.clone(),
+                        realm,
+                        service,
+                        scope,
+                        credentials,
+                        token: Mutex::new(None),
+                    },
+                };
+                auth.bearer_token().await?;
+                Ok(auth)
+            }
+        }
+    }
+
+    /// Adds the `Authorization` header to `request`, renewing an expiring
+    /// token first.
+    pub async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        match &self.scheme {
+            Scheme::Anonymous => Ok(request),
+            Scheme::Basic(encoded) => {
+                Ok(request.header("Authorization", format!("Basic {}", encoded)))
+            }
+            Scheme::Bearer { .. } => Ok(request.bearer_auth(self.bearer_token().await?)),
+        }
+    }
+
+    async fn bearer_token(&self) -> Result<String> {
+        let Scheme::Bearer {
+            client,
+            realm,
+            service,
+            scope,
+            credentials,
+            token,
+        } = &self.scheme
+        else {
+            return Err(anyhow!("Not using bearer authentication"));
+        };
+
+        let mut token = token.lock().await;
+        let fresh = token
+            .as_ref()
+            .is_some_and(|t| Instant::now() + REFRESH_MARGIN < t.expires_at);
+        if !fresh {
+            if token.is_some() {
+                tracing::debug!("Refreshing registry token for {}", scope);
+            }
+            *token = Some(
+                Self::fetch_token(
+                    client,
+                    realm,
+                    service.as_deref(),
+                    scope,
+                    credentials.as_ref(),
+                )
+                .await?,
+            );
+        }
+
+        Ok(token.as_ref().map(|t| t.value.clone()).unwrap_or_default())
+    }
+
+    async fn fetch_token(
+        client: &Client,
+        realm: &str,
+        service: Option<&str>,
+        scope: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<BearerToken> {
+        let mut url =
+            Url::parse(realm).with_context(|| format!("Invalid token realm: {}", realm))?;
+
+        // Identity tokens go through the OAuth2 refresh-token grant; anything
+        // else is the token spec's GET, with Basic credentials if we have them.
+        let request = match credentials {
+            Some(Credentials::IdentityToken(refresh_token)) => {
+                let mut form = vec![
+                    ("grant_type", "refresh_token"),
+                    ("refresh_token", refresh_token.as_str()),
+                    ("client_id", CLIENT_ID),
+                    ("scope", scope),
+                ];
+                form.extend(service.map(|service| ("service", service)));
+                client.post(url).form(&form)
+            }
+            credentials => {
+                {
+                    let mut query = url.query_pairs_mut();
+                    if let Some(service) = service {
+                        query.append_pair("service", service);
+                    }
+                    query.append_pair("scope", scope);
+                }
+                let request = client.get(url);
+                match credentials {
+                    Some(Credentials::Password { username, password }) => {
+                        request.basic_auth(username, Some(password))
+                    }
+                    _ => request,
+                }
+            }
+        };
+
+        let requested_at = Instant::now();
+        let response = request
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .context("Failed to send authentication request")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Authentication failed: {} - {}", status, text));
+        }
+
+        let body: TokenResponse = response
+            .json()
+            .await
+            .context("Failed to parse authentication response")?;
+
+        let value = body
+            .token
+            .or(body.access_token)
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| anyhow!("Authentication response contains no token"))?;
+        let lifetime = body
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(MIN_TOKEN_LIFETIME)
+            .max(MIN_TOKEN_LIFETIME);
+
+        Ok(BearerToken {
+            value,
+            expires_at: requested_at + lifetime,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex as StdMutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Header, then the challenges parsed from it
+    type ChallengeCase<'a> = (&'a str, Vec<Challenge>);
+
+    fn bearer(realm: &str, service: Option<&str>, scope: Option<&str>) -> Challenge {
+        Challenge::Bearer {
+            realm: realm.to_string(),
+            service: service.map(str::to_string),
+            scope: scope.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn parses_challenges() {
+        let cases: Vec<ChallengeCase> = vec![
+            (
+                r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/python:pull""#,
+                vec![bearer(
+                    "https://auth.docker.io/token",
+                    Some("registry.docker.io"),
+                    Some("repository:library/python:pull"),
+                )],
+            ),
+            // Commas inside quoted values, several scopes in one
+            (
+                r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:org/app:pull,push""#,
+                vec![bearer(
+                    "https://ghcr.io/token",
+                    Some("ghcr.io"),
+                    Some("repository:org/app:pull,push"),
+                )],
+            ),
+            // Case-insensitive scheme and keys, spacing, escapes, unquoted values
+            (
+                r#"bearer  Realm = "https://auth.example/t\"ok\"" , service=registry.example"#,
+                vec![bearer(
+                    "https://auth.example/t\"ok\"",
+                    Some("registry.example"),
+                    None,
+                )],
+            ),
+            (
+                r#"Basic realm="Registry Realm""#,
+                vec![Challenge::Basic {
+                    realm: Some("Registry Realm".to_string()),
+                }],
+            ),
+            ("Basic", vec![Challenge::Basic { realm: None }]),
+            // Several challenges in one header; unknown schemes are skipped
+            (
+                r#"Negotiate, Basic realm="a,b", Bearer realm="https://auth.example/token",service="x""#,
+                vec![
+                    Challenge::Basic {
+                        realm: Some("a,b".to_string()),
+                    },
+                    bearer("https://auth.example/token", Some("x"), None),
+                ],
+            ),
+            (r#"Digest realm="x", qop="auth""#, vec![]),
+        ];
+
+        for (header, expected) in cases {
+            assert_eq!(
+                Challenge::parse_all(header).unwrap(),
+                expected,
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_challenges() {
+        for header in [
+            r#"Bearer service="registry.example""#,
+            r#"Bearer realm="https://auth.example/token"#,
+            r#"Bearer realm"#,
+            r#"Basic realm="x", Bearer scope="repository:app:pull""#,
+        ] {
+            assert!(
+                Challenge::parse_all(header).is_err(),
+                "{} should not parse",
+                header
+            );
+        }
+
+        let error = "Negotiate".parse::<Challenge>().unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Unsupported authentication scheme"));
+        let error = r#"Bearer service="x""#.parse::<Challenge>().unwrap_err();
+        assert!(error.to_string().contains("without a realm"), "{}", error);
+    }
+
+    #[derive(Debug, Clone)]
+    struct TokenRequest {
+        method: String,
+        target: String,
+        authorization: Option<String>,
+        body: String,
+    }
+
+    /// A token endpoint answering every request with `token-<n>`, valid for
+    /// `expires_in` seconds.
+    async fn token_server(expires_in: u64) -> (String, Arc<StdMutex<Vec<TokenRequest>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let realm = format!("http://{}/token", listener.local_addr().unwrap());
+        let requests = Arc::new(StdMutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).await.unwrap();
+                let mut parts = request_line.split_whitespace();
+                let (method, target) = (parts.next().unwrap(), parts.next().unwrap());
+
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(':') else {
+                        break;
+                    };
+                    headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |l| l.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).await.unwrap();
+
+                let count = {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(TokenRequest {
+                        method: method.to_string(),
+                        target: target.to_string(),
+                        authorization: headers.get("authorization").cloned(),
+                        body: String::from_utf8(body).unwrap(),
+                    });
+                    seen.len()
+                };
+
+                let response = format!(
+                    r#"{{"token":"token-{}","expires_in":{}}}"#,
+                    count, expires_in
+                );
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.len()
+                );
+                let stream = reader.get_mut();
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.ok();
+            }
+        });
+
+        (realm, requests)
+    }
+
+    async fn authorization(auth: &RegistryAuth) -> String {
+        let client = Client::new();
+        let request = auth
+            .authorize(client.get("http://registry.example/v2/"))
+            .await
+            .unwrap()
+            .build()
+            .unwrap();
+        request.headers()["authorization"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Moves the cached token's expiry to `from_now`.
+    async fn expire_in(auth: &RegistryAuth, from_now: Duration) {
+        let Scheme::Bearer { token, .. } = &auth.scheme else {
+            panic!("not a bearer scheme");
+        };
+        token.lock().await.as_mut().unwrap().expires_at = Instant::now() + from_now;
+    }
+
+    #[tokio::test]
+    async fn refreshes_tokens_before_they_expire() {
+        let (realm, requests) = token_server(300).await;
+        let challenge = bearer(&realm, Some("registry.example"), None);
+        let auth = RegistryAuth::from_challenge(
+            &Client::new(),
+            challenge,
+            "repository:app:pull".to_string(),
+            Some(Credentials::new("user", "secret")),
+        )
+        .await
+        .unwrap();
+
+        // The first token is fetched up front and reused while fresh
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(authorization(&auth).await, "Bearer token-1");
+        expire_in(&auth, REFRESH_MARGIN * 3).await;
+        assert_eq!(authorization(&auth).await, "Bearer token-1");
+
+        // Within the margin it is renewed, before the registry would reject it
+        expire_in(&auth, REFRESH_MARGIN / 2).await;
+        assert_eq!(authorization(&auth).await, "Bearer token-2");
+        assert_eq!(authorization(&auth).await, "Bearer token-2");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for request in requests.iter() {
+            assert_eq!(request.method, "GET");
+            assert_eq!(
+                request.target,
+                "/token?service=registry.example&scope=repository%3Aapp%3Apull"
+            );
+            assert_eq!(
+                request.authorization.as_deref(),
+                Some(format!("Basic {}", STANDARD.encode("user:secret")).as_str())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_identity_tokens_and_clamps_short_lifetimes() {
+        let (realm, requests) = token_server(5).await;
+        let requested_at = Instant::now();
+        let auth = RegistryAuth::from_challenge(
+            &Client::new(),
+            bearer(&realm, Some("registry.example"), None),
+            "repository:app:pull,push".to_string(),
+            Some(Credentials::IdentityToken("refresh-me".to_string())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(authorization(&auth).await, "Bearer token-1");
+
+        let Scheme::Bearer { token, .. } = &auth.scheme else {
+            panic!("not a bearer scheme");
+        };
+        let expires_at = token.lock().await.as_ref().unwrap().expires_at;
+        assert!(expires_at >= requested_at + MIN_TOKEN_LIFETIME);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].target, "/token");
+        assert_eq!(requests[0].authorization, None);
+        assert_eq!(
+            requests[0].body,
+            "grant_type=refresh_token&refresh_token=refresh-me&client_id=spacejar\
+             &scope=repository%3Aapp%3Apull%2Cpush&service=registry.example"
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_basic_challenges_with_the_password() {
+        let auth = RegistryAuth::from_challenge(
+            &Client::new(),
+            Challenge::Basic { realm: None },
+            "repository:app:pull".to_string(),
+            Some(Credentials::new("user", "secret")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            authorization(&auth).await,
+            format!("Basic {}", STANDARD.encode("user:secret"))
+        );
+
+        let error = RegistryAuth::from_challenge(
+            &Client::new(),
+            Challenge::Basic {
+                realm: Some("Registry".to_string()),
+            },
+            "repository:app:pull".to_string(),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("realm 'Registry'"), "{}", error);
+    }
+}
@@ -1,27 +1,53 @@
 use anyhow::{format_err, Context, Result};
+use bytes::Bytes;
 use futures::future::try_join_all;
-use oci_spec::image::Config as OCIConfig;
-use reqwest::Client;
+use oci_spec::image::{
+    Config as OCIConfig, History, HistoryBuilder, ImageConfiguration, RootFsBuilder,
+};
+use reqwest::{Certificate, Client, Identity, Response, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env::consts::ARCH;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::process::Command;
 
-use crate::cache::{Cache, LayerMetadata, LayerType};
-use crate::fs::{copy_dir_all, remove_matching_files};
+use crate::auth::{Challenge, RegistryAuth};
+use crate::cache::{Cache, CacheStats, LayerMetadata, LayerType};
+use crate::credentials::Credentials;
+use crate::dependencies::ProjectDependencies;
+use crate::fs::{copy_dir_all, hash_dir};
 use crate::image::ImageConfig;
-use crate::layer::Layer;
+use crate::layer::{source_date_epoch, Compression, Layer, LayerOptions};
+use crate::layout::{sha256_digest, ImageLayout};
+use crate::lockfile::LockFile;
 use crate::manifest::Manifest;
+use crate::python::TargetPython;
+use crate::reference::Reference;
+use crate::registries::{Registries, RegistryOptions};
+use crate::wheel::{InstallScheme, Installer, Wheel, Wheelhouse};
+
+/// Media types of a multi-platform index; the first is OCI's, the second
+/// Docker's manifest list.
+const INDEX_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+
+/// Media types of a single-platform image manifest.
+const MANIFEST_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
 
 #[derive(Debug, Deserialize)]
 struct ManifestIndex {
     #[serde(rename = "schemaVersion")]
     schema_version: u8,
-    #[serde(rename = "mediaType")]
-    media_type: String,
+    /// Optional for OCI indexes; Docker manifest lists always set it
+    #[serde(rename = "mediaType", default)]
+    media_type: Option<String>,
     manifests: Vec<IndexManifest>,
 }
 
@@ -49,8 +75,9 @@ struct Platform {
 struct ManifestV2Schema2 {
     #[serde(rename = "schemaVersion")]
     schema_version: u8,
-    #[serde(rename = "mediaType")]
-    media_type: String,
+    /// Optional for OCI manifests; Docker's always set it
+    #[serde(rename = "mediaType", default)]
+    media_type: Option<String>,
     config: ManifestLayer,
     layers: Vec<ManifestLayer>,
 }
@@ -61,24 +88,13 @@ struct ManifestLayer {
     media_type: String,
     size: u64,
     digest: String,
+    /// Where a foreign (non-distributable) layer is hosted
     #[serde(default)]
     urls: Vec<String>,
 }
 
-// We'll also add this helper struct to handle registry errors
-#[derive(Debug, Deserialize)]
-struct RegistryError {
-    errors: Vec<RegistryErrorDetail>,
-}
-
-#[derive(Debug, Deserialize)]
-struct RegistryErrorDetail {
-    code: String,
-    message: String,
-    #[serde(default)]
-    detail: Option<serde_json::Value>,
-}
-
+/// Legacy Docker schema 1 manifest, which we only read to explain why the
+/// image can't be pulled.
 #[derive(Debug, Deserialize)]
 struct ManifestV1 {
     #[serde(rename = "schemaVersion")]
@@ -86,52 +102,98 @@ struct ManifestV1 {
     name: String,
     tag: String,
     architecture: String,
-    fsLayers: Vec<ManifestFsLayer>,
+    #[serde(rename = "fsLayers")]
+    fs_layers: Vec<ManifestFsLayer>,
     history: Vec<ManifestHistory>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ManifestFsLayer {
-    blobSum: String,
+    #[serde(rename = "blobSum")]
+    blob_sum: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct ManifestHistory {
-    v1Compatibility: String,
+    #[serde(rename = "v1Compatibility")]
+    v1_compatibility: String,
 }
 
+// We'll also add this helper struct to handle registry errors
 #[derive(Debug, Deserialize)]
-struct RegistryAuth {
-    token: String,
-    expires_in: u64,
+struct RegistryError {
+    errors: Vec<RegistryErrorDetail>,
 }
 
 #[derive(Debug, Deserialize)]
-struct ManifestResponse {
-    schema_version: u8,
-    media_type: String,
-    config: ManifestLayer,
-    layers: Vec<ManifestLayer>,
+struct RegistryErrorDetail {
+    code: String,
+    message: String,
+    #[serde(default)]
+    detail: Option<serde_json::Value>,
 }
 
+/// Registries commonly cap the size of a single upload request, so larger
+/// blobs are sent as a sequence of `PATCH` chunks of this size.
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Names left out of the application layer wherever they appear in the
+/// project tree.
+const APP_EXCLUDES: [&str; 9] = [
+    "venv",
+    ".venv",
+    "__pycache__",
+    "*.pyc",
+    "*.pyo",
+    ".git",
+    ".pytest_cache",
+    ".mypy_cache",
+    ".ruff_cache",
+];
+
 #[derive(Debug)]
 struct BuildOutput {
     layer: Layer,
     config: ImageConfig,
+    /// Recorded as the layer's `created_by` in the image config history
+    created_by: String,
+    /// Content key the layer is cached under
+    cache_key: String,
+    /// Metadata to record in the cache when the layer was built rather
+    /// than taken from it
+    built: Option<LayerMetadata>,
+}
+
+/// What the dependencies layer is installed from.
+enum DependencySpec {
+    Locked(LockFile),
+    Declared(ProjectDependencies),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct BaseImage {
-    layer: Layer,
-    config: ImageConfig,
+    layers: Vec<Layer>,
+    config: ImageConfiguration,
 }
 
 pub struct PythonImageBuilder {
     project_path: PathBuf,
     output_path: PathBuf,
-    base_image: String,
+    base_image: Reference,
     config: ImageConfig,
     cache: Cache,
+    tag: String,
+    layer_options: LayerOptions,
+    push_target: Option<String>,
+    wheelhouse: Option<PathBuf>,
+    installer: Installer,
+    /// `os/arch[/variant]` to build for; the host's architecture when unset
+    platform: Option<String>,
+    /// Explicit login, used instead of any configured credentials for the
+    /// push target's registry, or the base image's when not pushing
+    credentials: Option<Credentials>,
+    registries: Registries,
+    cache_stats: CacheStats,
 }
 
 impl PythonImageBuilder {
@@ -164,9 +226,18 @@ impl PythonImageBuilder {
             }
         }
 
-        if base_image.is_empty() || base_image.contains(['/', '\\']) {
-            return Err(anyhow::anyhow!("Invalid base image name: {}", base_image));
-        }
+        let base_image: Reference = base_image.parse()?;
+
+        let (uid, gid) = config.owner.unwrap_or((0, 0));
+        let layer_options = LayerOptions::builder()
+            .compression(config.compression.unwrap_or_default())
+            .uid(uid)
+            .gid(gid)
+            .build();
+        let wheelhouse = config.wheelhouse.clone();
+        let installer = config.installer.unwrap_or_default();
+        let platform = config.platform.clone();
+        let tag = config.tag.clone().unwrap_or_else(|| "latest".to_string());
 
         Ok(Self {
             project_path,
@@ -174,12 +245,143 @@ impl PythonImageBuilder {
             base_image,
             config,
             cache,
+            tag,
+            layer_options,
+            push_target: None,
+            wheelhouse,
+            installer,
+            platform,
+            credentials: None,
+            registries: Registries::default(),
+            cache_stats: CacheStats::default(),
         })
     }
 
+    /// Sets the `org.opencontainers.image.ref.name` the image is recorded
+    /// under in the layout's `index.json`.
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = tag.into();
+        self
+    }
+
+    /// Overrides the layer compression configured in `[tool.spacejar]`.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.layer_options.compression = compression;
+        self
+    }
+
+    /// Pushes the built image to `reference` (e.g. `localhost:5000/app:1.0`)
+    /// once the local OCI layout has been written.
+    pub fn with_push_target(mut self, reference: impl Into<String>) -> Self {
+        self.push_target = Some(reference.into());
+        self
+    }
+
+    /// Installs dependencies only from the wheels in `dir`, never from an
+    /// index (overrides `[tool.spacejar] wheelhouse`).
+    pub fn with_wheelhouse(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.wheelhouse = Some(dir.into());
+        self
+    }
+
+    /// Overrides the dependency installer configured in `[tool.spacejar]`.
+    pub fn with_installer(mut self, installer: Installer) -> Self {
+        self.installer = installer;
+        self
+    }
+
+    /// Builds for `platform` (e.g. `linux/arm64`) instead of the host's
+    /// architecture (overrides `[tool.spacejar] platform`).
+    pub fn with_platform(mut self, platform: impl Into<String>) -> Self {
+        self.platform = Some(platform.into());
+        self
+    }
+
+    /// Logs in with `credentials` rather than those found in the Docker
+    /// config, for the push target's registry (or the base image's when
+    /// not pushing).
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Connects to registries with the per-host `registries` settings
+    /// (plain HTTP, extra CAs, client certificates).
+    pub fn with_registries(mut self, registries: Registries) -> Self {
+        self.registries = registries;
+        self
+    }
+
+    /// Pushes the image tagged `tag` in the OCI layout at `layout_dir` to
+    /// `target`, as `build` does with a push target, logging in with
+    /// `credentials` when given.
+    pub async fn push_layout(
+        layout_dir: &Path,
+        tag: &str,
+        target: &str,
+        credentials: Option<&Credentials>,
+        registries: &Registries,
+    ) -> Result<()> {
+        let layout = ImageLayout::new(layout_dir);
+        let (_, manifest_json) = layout.read_manifest(tag).await?;
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest_json).context("Failed to parse image manifest")?;
+
+        let config_json = layout
+            .read_blob(&manifest.config.digest, Some(manifest.config.size))
+            .await?;
+        let config = ImageConfiguration::from_reader(config_json.as_slice())
+            .context("Failed to parse image config")?;
+
+        let diff_ids = config.rootfs().diff_ids();
+        if diff_ids.len() != manifest.layers.len() {
+            return Err(anyhow::anyhow!(
+                "Image config lists {} diff_ids for {} layers",
+                diff_ids.len(),
+                manifest.layers.len()
+            ));
+        }
+
+        let mut layers = Vec::with_capacity(manifest.layers.len());
+        for (descriptor, diff_id) in manifest.layers.iter().zip(diff_ids) {
+            let data = layout
+                .read_blob(&descriptor.digest, Some(descriptor.size))
+                .await?;
+            layers.push(Layer {
+                media_type: descriptor.media_type.clone(),
+                digest: descriptor.digest.clone(),
+                size: descriptor.size,
+                compressed_size: descriptor.size,
+                data,
+                diff_id: diff_id.clone(),
+                annotations: descriptor.annotations.clone().unwrap_or_default(),
+            });
+        }
+
+        Self::push_image(
+            target,
+            Bytes::from(config_json),
+            &manifest,
+            layers,
+            credentials,
+            registries,
+        )
+        .await
+        .with_context(|| format!("Failed to push image to {}", target))
+    }
+
     pub async fn build(&mut self) -> Result<()> {
         tracing::info!("Starting build process for Python project");
 
+        // Checked up front so a misconfiguration fails before the base image
+        // is pulled rather than halfway through the build.
+        if self.installer == Installer::Native && self.wheelhouse.is_none() {
+            return Err(format_err!(
+                "The native installer needs a wheelhouse to install from; \
+                 pass --wheelhouse or set [tool.spacejar] wheelhouse"
+            ));
+        }
+
         let build_dir =
             tempfile::TempDir::new().context("Failed to create temporary build directory")?;
 
@@ -188,127 +390,520 @@ impl PythonImageBuilder {
             .await
             .context("Failed to pull base image")?;
 
+        let target = self.target_python(&base_image.config)?;
+        tracing::info!(
+            "Targeting Python {} ({}) on {}",
+            target.version,
+            target.abi,
+            target.platforms.join(", ")
+        );
+
         let (venv_layer, deps_layer, app_layer) = tokio::try_join!(
-            self.create_venv_layer(build_dir.path()),
-            self.create_deps_layer(build_dir.path()),
+            self.create_venv_layer(build_dir.path(), &target),
+            self.create_deps_layer(build_dir.path(), &target),
             self.create_app_layer(build_dir.path())
         )?;
 
-        self.verify_layers(&[
-            &base_image.layer,
-            &venv_layer.layer,
-            &deps_layer.layer,
-            &app_layer.layer,
-        ])
-        .await?;
+        let mut outputs = [venv_layer, deps_layer, app_layer];
+        for output in &mut outputs {
+            self.cache_stats.record(output.built.is_none());
+            if let Some(metadata) = output.built.take() {
+                self.store_built_layer(output, metadata).await?;
+            }
+        }
+        let [venv_layer, deps_layer, app_layer] = outputs;
+
+        let history: Vec<String> = [&venv_layer, &deps_layer, &app_layer]
+            .iter()
+            .map(|output| output.created_by.clone())
+            .collect();
+
+        let layers: Vec<Layer> = base_image
+            .layers
+            .into_iter()
+            .chain([venv_layer.layer, deps_layer.layer, app_layer.layer])
+            .collect();
+
+        self.verify_layers(&layers.iter().collect::<Vec<_>>())
+            .await?;
 
         // Generate final config
-        let config = self.generate_config(&[
+        let config = self.generate_config(
             &base_image.config,
-            &venv_layer.config,
-            &deps_layer.config,
-            &app_layer.config,
-        ])?;
+            &[&venv_layer.config, &deps_layer.config, &app_layer.config],
+        )?;
+
+        let image_config =
+            self.generate_image_configuration(&base_image.config, config, &layers, &history)?;
 
         // Create manifest
-        let manifest = self.create_manifest(
-            config.clone(),
-            vec![
-                base_image.layer,
-                venv_layer.layer,
-                deps_layer.layer,
-                app_layer.layer,
-            ],
-        )?;
+        let config_json = serde_json::to_vec(&image_config)?;
+        let manifest = self.create_manifest(&config_json, &layers)?;
 
         // Write image
-        self.write_image(config.clone(), manifest).await?;
+        self.write_image(&config_json, &manifest, &layers).await?;
+
+        if let Some(target) = &self.push_target {
+            Self::push_image(
+                target,
+                Bytes::from(config_json),
+                &manifest,
+                layers,
+                self.credentials.as_ref(),
+                &self.registries,
+            )
+            .await
+            .with_context(|| format!("Failed to push image to {}", target))?;
+        }
 
         if let Err(e) = build_dir.close() {
             tracing::warn!("Failed to cleanup temporary directory: {}", e);
         }
 
+        tracing::info!("Layer cache: {}", self.cache_stats);
+        match self.cache.cleanup().await {
+            Ok(report) if report.entries > 0 || report.blobs > 0 => tracing::info!(
+                "Evicted {} cache entries, freeing {} bytes in {} blobs",
+                report.entries,
+                report.bytes,
+                report.blobs
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to clean up cache: {:#}", e),
+        }
         tracing::info!("Build completed successfully");
         Ok(())
     }
 
-    async fn create_venv_layer(&self, build_dir: &Path) -> Result<BuildOutput> {
-        tracing::debug!("Creating virtual environment layer");
+    /// Cache key for one of our layers: the layer's own `inputs` plus the
+    /// layer options and timestamp that shape the tarball bytes.
+    fn layer_cache_key(&self, kind: &str, inputs: &[String]) -> String {
+        let mut key = format!(
+            "{}\0{}\0{}",
+            kind,
+            self.layer_options.cache_key(),
+            source_date_epoch().unwrap_or(0)
+        );
+        for input in inputs {
+            key.push('\0');
+            key.push_str(input);
+        }
+        sha256_digest(key.as_bytes())
+    }
 
-        let venv_path = build_dir.join("venv");
+    /// The layer cached under `key` with the history entry it was built
+    /// with, if both are present.
+    async fn cached_layer(&self, key: &str, layer_type: LayerType) -> Option<(Layer, String)> {
+        let layer = match layer_type {
+            LayerType::Dependencies => self.cache.get_dependency_layer(key).await,
+            _ => self.cache.get_layer(key).await,
+        }?;
+        let created_by = self.cache.layer_metadata(key)?.created_by.clone()?;
+        tracing::debug!("Using cached {:?} layer {}", layer_type, layer.digest);
+        Some((layer, created_by))
+    }
 
-        // Create virtual environment with system packages
-        let output = tokio::process::Command::new("python")
-            .args([
-                "-m",
-                "venv",
-                "--system-site-packages",
-                venv_path
-                    .to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Invalid venv path"))?,
-            ])
-            .output()
-            .await
-            .context("Failed to create virtual environment")?;
+    async fn store_built_layer(
+        &mut self,
+        output: &BuildOutput,
+        metadata: LayerMetadata,
+    ) -> Result<()> {
+        let stored = match metadata.layer_type {
+            LayerType::Dependencies => {
+                self.cache
+                    .store_dependency_layer(&output.cache_key, &output.layer, metadata)
+                    .await
+            }
+            _ => {
+                self.cache
+                    .store_layer(&output.cache_key, &output.layer, metadata)
+                    .await
+            }
+        };
+        stored.context("Failed to store layer in cache")
+    }
 
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(format_err!(
-                "Failed to create virtual environment: {}",
-                error
-            ));
+    /// Works out which interpreter the dependencies must be built for from
+    /// `[tool.spacejar]` and the base image.
+    fn target_python(&self, base: &ImageConfiguration) -> Result<TargetPython> {
+        let tag = self.base_image.tag.as_deref().unwrap_or_default();
+        let base_env = base
+            .config()
+            .as_ref()
+            .and_then(|c| c.env().clone())
+            .unwrap_or_default();
+
+        TargetPython::resolve(
+            self.config.python_version.as_deref(),
+            self.config.python_platform.as_deref(),
+            tag,
+            &base_env,
+            &self.architecture()?,
+        )
+    }
+
+    /// The image architecture (`amd64`, `arm64`, ...) from the configured
+    /// platform, or the host's.
+    fn architecture(&self) -> Result<String> {
+        let Some(platform) = &self.platform else {
+            return Ok(Self::get_docker_arch());
+        };
+
+        let mut parts = platform.split('/');
+        match (parts.next(), parts.next()) {
+            (Some("linux"), Some(arch)) if !arch.is_empty() => Ok(arch.to_string()),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported platform '{}', expected linux/<arch>",
+                platform
+            )),
         }
+    }
+
+    /// The architecture variant (`v7`, `v8`, ...) when the configured
+    /// platform names one, e.g. `linux/arm/v7`.
+    fn variant(&self) -> Option<&str> {
+        self.platform
+            .as_deref()
+            .and_then(|platform| platform.split('/').nth(2))
+            .filter(|variant| !variant.is_empty())
+    }
 
-        // Secure path construction for pip upgrade
-        let activate_path = venv_path.join("bin").join("activate");
-        if !activate_path.exists() {
-            return Err(anyhow::anyhow!("Activation script not found"));
+    /// Lays out a virtualenv for the image's interpreter without running the
+    /// host Python: `pyvenv.cfg`, interpreter symlinks into the base image,
+    /// an empty `site-packages` and a POSIX `activate` script.
+    async fn create_venv_layer(
+        &self,
+        build_dir: &Path,
+        target: &TargetPython,
+    ) -> Result<BuildOutput> {
+        tracing::debug!("Creating virtual environment layer");
+
+        let cache_key =
+            self.layer_cache_key("venv", &[target.version.clone(), target.home.clone()]);
+        if let Some((layer, created_by)) =
+            self.cached_layer(&cache_key, LayerType::VirtualEnv).await
+        {
+            return Ok(BuildOutput {
+                layer,
+                config: self.venv_config()?,
+                created_by,
+                cache_key,
+                built: None,
+            });
         }
 
-        // Upgrade pip with input validation
-        let pip_upgrade = Command::new("bash")
-            .arg("-c")
-            .arg(format!(
-                "source {} && pip install --upgrade pip",
-                activate_path
-                    .to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Invalid activate path"))?
-            ))
-            .output()
-            .await
-            .context("Failed to upgrade pip")?;
+        let venv_path = build_dir.join("venv");
+        let bin_path = venv_path.join("bin");
+
+        tokio::fs::create_dir_all(&bin_path).await?;
+        tokio::fs::create_dir_all(venv_path.join(target.site_packages())).await?;
 
-        if !pip_upgrade.status.success() {
-            let error = String::from_utf8_lossy(&pip_upgrade.stderr);
-            return Err(anyhow::anyhow!("Pip upgrade failed: {}", error));
+        // Isolated from the base image's site-packages, like `python -m venv`
+        tokio::fs::write(
+            venv_path.join("pyvenv.cfg"),
+            format!(
+                "home = {}\ninclude-system-site-packages = false\nversion = {}\n",
+                target.home, target.version
+            ),
+        )
+        .await
+        .context("Failed to write pyvenv.cfg")?;
+
+        tokio::fs::symlink(format!("{}/python3", target.home), bin_path.join("python"))
+            .await
+            .context("Failed to link venv interpreter")?;
+        for alias in ["python3".to_string(), target.interpreter()] {
+            tokio::fs::symlink("python", bin_path.join(alias)).await?;
         }
 
-        let layer = Layer::from_dir(&venv_path).await?;
+        tokio::fs::write(
+            bin_path.join("activate"),
+            "# This file must be used with \". bin/activate\"\n\
+             VIRTUAL_ENV=/venv\n\
+             export VIRTUAL_ENV\n\
+             PATH=\"$VIRTUAL_ENV/bin:$PATH\"\n\
+             export PATH\n\
+             unset PYTHONHOME\n",
+        )
+        .await
+        .context("Failed to write activation script")?;
+
+        let layer = Layer::from_dir(&venv_path, "/venv", &self.layer_options).await?;
         self.verify_layer_digest(&layer)?;
 
+        let created_by = format!("virtualenv /venv for {}", target.interpreter());
         Ok(BuildOutput {
             layer,
             config: self.venv_config()?,
+            built: Some(LayerMetadata {
+                layer_type: LayerType::VirtualEnv,
+                source_hash: cache_key.clone(),
+                dependencies: Vec::new(),
+                created_by: Some(created_by.clone()),
+            }),
+            created_by,
+            cache_key,
         })
     }
 
-    async fn create_deps_layer(&self, build_dir: &Path) -> Result<BuildOutput> {
+    async fn create_deps_layer(
+        &self,
+        build_dir: &Path,
+        target: &TargetPython,
+    ) -> Result<BuildOutput> {
         tracing::debug!("Creating dependencies layer");
 
-        let requirements = self.project_path.join("requirements.txt");
-        if !requirements.exists() {
-            return Err(format_err!("requirements.txt not found"));
+        let wheelhouse = self
+            .wheelhouse
+            .as_deref()
+            .map(Wheelhouse::scan)
+            .transpose()?;
+
+        let spec = match LockFile::detect(&self.project_path)? {
+            Some(lock) => DependencySpec::Locked(lock),
+            None => DependencySpec::Declared(ProjectDependencies::load(
+                &self.project_path,
+                &self.config.extras,
+            )?),
+        };
+
+        // Locked installs are keyed on the lock file itself; anything else
+        // on the declared requirements.
+        let declared = match &spec {
+            DependencySpec::Locked(lock) => format!(
+                "{}:{}",
+                lock.kind,
+                sha256_digest(lock.contents().as_bytes())
+            ),
+            DependencySpec::Declared(dependencies) => dependencies
+                .requirements
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        let wheels = match &wheelhouse {
+            Some(wheelhouse) => wheelhouse.fingerprints()?.join(","),
+            None => String::new(),
+        };
+        let cache_key = self.layer_cache_key(
+            "deps",
+            &[
+                declared,
+                self.config.extras.join(","),
+                target.version.clone(),
+                target.abi.clone(),
+                target.platforms.join(","),
+                self.installer.to_string(),
+                wheels,
+            ],
+        );
+        if let Some((layer, created_by)) =
+            self.cached_layer(&cache_key, LayerType::Dependencies).await
+        {
+            return Ok(BuildOutput {
+                layer,
+                config: self.deps_config()?,
+                created_by,
+                cache_key,
+                built: None,
+            });
         }
 
         let deps_path = build_dir.join("deps");
+        tokio::fs::create_dir_all(&deps_path).await?;
+
+        let (created_by, installed) = match &spec {
+            DependencySpec::Locked(lock) => {
+                self.install_locked(lock, wheelhouse.as_ref(), build_dir, &deps_path, target)
+                    .await?
+            }
+            DependencySpec::Declared(dependencies) => {
+                self.install_unlocked(
+                    dependencies,
+                    wheelhouse.as_ref(),
+                    build_dir,
+                    &deps_path,
+                    target,
+                )
+                .await?
+            }
+        };
+
+        let layer = Layer::from_dir(&deps_path, "/app/deps", &self.layer_options).await?;
+        self.verify_layer_digest(&layer)?;
+
+        Ok(BuildOutput {
+            layer,
+            config: self.deps_config()?,
+            built: Some(LayerMetadata {
+                layer_type: LayerType::Dependencies,
+                source_hash: cache_key.clone(),
+                dependencies: installed,
+                created_by: Some(created_by.clone()),
+            }),
+            created_by,
+            cache_key,
+        })
+    }
+
+    /// Installs exactly the versions pinned by `lock`, with hash checking
+    /// enforced. Returns the history entry describing the install and the
+    /// installed packages.
+    async fn install_locked(
+        &self,
+        lock: &LockFile,
+        wheelhouse: Option<&Wheelhouse>,
+        build_dir: &Path,
+        deps_path: &Path,
+        target: &TargetPython,
+    ) -> Result<(String, Vec<String>)> {
+        lock.ensure_fresh(&self.project_path, &self.config.extras)?;
+        let packages = lock.resolve(&self.project_path, &self.config.extras, target)?;
+        tracing::info!(
+            "Installing {} packages locked by {}",
+            packages.len(),
+            lock.kind
+        );
+
+        if packages.is_empty() {
+            return Ok(("no dependencies to install".to_string(), Vec::new()));
+        }
+
+        let installed = packages
+            .iter()
+            .map(|package| format!("{}=={}", package.name, package.version))
+            .collect();
+        let created_by = match self.installer {
+            Installer::Pip => {
+                if let Some(wheelhouse) = wheelhouse {
+                    wheelhouse.locked(&packages, target)?;
+                }
+                let requirements = lock.requirements_file(&packages, build_dir)?;
+                self.pip_install(
+                    &requirements,
+                    &["--require-hashes", "--no-deps"],
+                    wheelhouse,
+                    deps_path,
+                    target,
+                )
+                .await?;
+
+                format!(
+                    "pip install --target /app/deps {} --require-hashes --no-deps{} -r {}",
+                    target.pip_args().join(" "),
+                    if wheelhouse.is_some() {
+                        " --no-index"
+                    } else {
+                        ""
+                    },
+                    lock.kind
+                )
+            }
+            Installer::Native => {
+                let wheels = Self::native_wheelhouse(wheelhouse)?.locked(&packages, target)?;
+                Self::native_install(&wheels, deps_path, target)?
+            }
+        };
+
+        Ok((created_by, installed))
+    }
+
+    /// Installs the declared dependencies, letting pip (or the wheelhouse)
+    /// pick versions. Returns the history entry describing the install and
+    /// the requirements it installed.
+    async fn install_unlocked(
+        &self,
+        dependencies: &ProjectDependencies,
+        wheelhouse: Option<&Wheelhouse>,
+        build_dir: &Path,
+        deps_path: &Path,
+        target: &TargetPython,
+    ) -> Result<(String, Vec<String>)> {
+        tracing::info!(
+            "Installing {} dependencies from {}",
+            dependencies.requirements.len(),
+            dependencies.source
+        );
+
+        if dependencies.requirements.is_empty() {
+            return Ok(("no dependencies to install".to_string(), Vec::new()));
+        }
 
-        let output = tokio::process::Command::new("pip")
+        let installed = dependencies
+            .requirements
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let created_by = match self.installer {
+            Installer::Pip => {
+                if let Some(wheelhouse) = wheelhouse {
+                    wheelhouse.resolve(&dependencies.requirements, target)?;
+                }
+                let requirements = dependencies.requirements_file(build_dir)?;
+                self.pip_install(&requirements, &[], wheelhouse, deps_path, target)
+                    .await?;
+
+                format!(
+                    "pip install --target /app/deps {}{} -r requirements.txt",
+                    target.pip_args().join(" "),
+                    if wheelhouse.is_some() {
+                        " --no-index"
+                    } else {
+                        ""
+                    }
+                )
+            }
+            Installer::Native => {
+                let wheels = Self::native_wheelhouse(wheelhouse)?
+                    .resolve(&dependencies.requirements, target)?;
+                Self::native_install(&wheels, deps_path, target)?
+            }
+        };
+
+        Ok((created_by, installed))
+    }
+
+    /// Runs the host's pip, restricted to wheels for `target` and, with a
+    /// wheelhouse, to the wheels in it.
+    async fn pip_install(
+        &self,
+        requirements: &Path,
+        extra_args: &[&str],
+        wheelhouse: Option<&Wheelhouse>,
+        deps_path: &Path,
+        target: &TargetPython,
+    ) -> Result<()> {
+        let mut index_args = Vec::new();
+        if let Some(wheelhouse) = wheelhouse {
+            tracing::info!(
+                "Installing dependencies from wheelhouse {}",
+                wheelhouse.dir().display()
+            );
+
+            index_args.push("--no-index".to_string());
+            index_args.push("--find-links".to_string());
+            index_args.push(
+                wheelhouse
+                    .dir()
+                    .to_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid wheelhouse path"))?
+                    .to_string(),
+            );
+        }
+
+        let output = Command::new("pip")
             .args([
                 "install",
                 "--target",
                 deps_path
                     .to_str()
                     .ok_or_else(|| anyhow::anyhow!("Invalid deps path"))?,
+            ])
+            .args(target.pip_args())
+            .args(&index_args)
+            .args(extra_args)
+            .args([
                 "-r",
                 requirements
                     .to_str()
@@ -320,16 +915,66 @@ impl PythonImageBuilder {
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
-            return Err(format_err!("Failed to install dependencies: {}", error));
+            return Err(Self::pip_install_error(&error, target));
         }
 
-        let layer = Layer::from_dir(&deps_path).await?;
-        self.verify_layer_digest(&layer)?;
+        Ok(())
+    }
 
-        Ok(BuildOutput {
-            layer,
-            config: self.deps_config()?,
-        })
+    fn native_wheelhouse(wheelhouse: Option<&Wheelhouse>) -> Result<&Wheelhouse> {
+        wheelhouse
+            .ok_or_else(|| format_err!("The native installer needs a wheelhouse to install from"))
+    }
+
+    /// Unpacks `wheels` straight into `deps_path`, so no Python is needed on
+    /// the build host. Returns the history entry describing the install.
+    fn native_install(
+        wheels: &[&Wheel],
+        deps_path: &Path,
+        target: &TargetPython,
+    ) -> Result<String> {
+        let scheme = InstallScheme::target(deps_path);
+        let interpreter = format!("/venv/bin/{}", target.interpreter());
+        for wheel in wheels {
+            tracing::debug!("Installing {} {}", wheel.name, wheel.version);
+            wheel
+                .install(&scheme, &interpreter)
+                .with_context(|| format!("Failed to install {}", wheel.path.display()))?;
+        }
+
+        let mut installed: Vec<String> = wheels
+            .iter()
+            .map(|wheel| format!("{}=={}", wheel.name, wheel.version))
+            .collect();
+        installed.sort();
+        Ok(format!(
+            "install wheels --target /app/deps {}",
+            installed.join(" ")
+        ))
+    }
+
+    /// Turns pip's "no matching distribution" failure, which is what
+    /// `--only-binary=:all:` produces for packages that only ship an sdist,
+    /// into an error naming the package and the target it lacks a wheel for.
+    fn pip_install_error(stderr: &str, target: &TargetPython) -> anyhow::Error {
+        let missing: Vec<&str> = stderr
+            .lines()
+            .filter_map(|line| line.split("No matching distribution found for ").nth(1))
+            .map(str::trim)
+            .collect();
+
+        if missing.is_empty() {
+            return format_err!("Failed to install dependencies: {}", stderr);
+        }
+
+        format_err!(
+            "No pre-built wheel of {} is available for Python {} ({}) on {}; \
+             building it from source would compile for the build host instead of the image",
+            missing.join(", "),
+            target.version,
+            target.abi,
+            target.platforms.join(", ")
+        )
     }
 
     async fn create_app_layer(&self, build_dir: &Path) -> Result<BuildOutput> {
@@ -339,39 +984,81 @@ impl PythonImageBuilder {
 
         tokio::fs::create_dir(&app_path).await?;
 
-        copy_dir_all(self.project_path.clone(), app_path.clone()).await?;
+        // An output layout inside the project (the default) is not part of it
+        let output = match (
+            self.project_path.canonicalize(),
+            self.output_path.canonicalize(),
+        ) {
+            (Ok(project), Ok(output)) => output
+                .strip_prefix(&project)
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+            _ => None,
+        };
+        let patterns = APP_EXCLUDES
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        copy_dir_all(self.project_path.clone(), app_path.clone(), move |path| {
+            output.as_deref() == Some(path)
+                || path.file_name().is_some_and(|name| {
+                    let name = name.to_string_lossy();
+                    patterns.iter().any(|pattern| pattern.matches(&name))
+                })
+        })
+        .await?;
 
-        for pattern in &[
-            "venv",
-            "__pycache__",
-            "*.pyc",
-            "*.pyo",
-            ".git",
-            ".pytest_cache",
-        ] {
-            remove_matching_files(&app_path, pattern).await?;
+        let source_hash = hash_dir(&app_path)?;
+        let cache_key = self.layer_cache_key("app", std::slice::from_ref(&source_hash));
+        if let Some((layer, created_by)) =
+            self.cached_layer(&cache_key, LayerType::Application).await
+        {
+            return Ok(BuildOutput {
+                layer,
+                config: self.app_config()?,
+                created_by,
+                cache_key,
+                built: None,
+            });
         }
 
-        let layer = Layer::from_dir(&app_path).await?;
+        let layer = Layer::from_dir(&app_path, "/app", &self.layer_options).await?;
         self.verify_layer_digest(&layer)?;
 
+        let created_by = format!("COPY {} /app", self.project_path.display());
         Ok(BuildOutput {
             layer,
             config: self.app_config()?,
+            built: Some(LayerMetadata {
+                layer_type: LayerType::Application,
+                source_hash,
+                dependencies: Vec::new(),
+                created_by: Some(created_by.clone()),
+            }),
+            created_by,
+            cache_key,
         })
     }
 
-    fn generate_config(&self, configs: &[&ImageConfig]) -> Result<OCIConfig> {
-        let mut final_config = OCIConfig::default();
-        let mut env = Vec::new();
+    /// Layers `configs` on top of the base image's runtime config, so the
+    /// base `User`, `Labels`, `Env` and so on carry over unless overridden.
+    fn generate_config(
+        &self,
+        base: &ImageConfiguration,
+        configs: &[&ImageConfig],
+    ) -> Result<OCIConfig> {
+        let mut final_config = base.config().clone().unwrap_or_default();
+        let mut env = final_config.env().clone().unwrap_or_default();
+        let mut labels = final_config.labels().clone().unwrap_or_default();
 
         for config in configs {
-            let config_env = &config.env;
-
-            if !config_env.is_empty() {
-                env.extend(config_env.clone());
+            for var in &config.env {
+                Self::merge_env(&mut env, var);
             }
 
+            labels.extend(config.labels.clone());
+
             if !config.working_dir.is_empty() {
                 final_config.set_working_dir(Some(config.working_dir.clone()));
             }
@@ -381,17 +1068,20 @@ impl PythonImageBuilder {
             }
         }
 
-        env.push("PYTHONUNBUFFERED=1".to_string());
-        env.push("PYTHONDONTWRITEBYTECODE=1".to_string());
-        env.push("PYTHONPATH=/app/deps:/app".to_string());
+        Self::merge_env(&mut env, "PYTHONUNBUFFERED=1");
+        Self::merge_env(&mut env, "PYTHONDONTWRITEBYTECODE=1");
+        Self::merge_env(&mut env, "PYTHONPATH=/app/deps:/app");
 
         final_config.set_env(Some(env));
+        if !labels.is_empty() {
+            final_config.set_labels(Some(labels));
+        }
 
         final_config.set_entrypoint(Some(vec![
             "/bin/sh".to_string(),
             "-c".to_string(),
             format!(
-                "source /venv/bin/activate && python /app/{}",
+                ". /venv/bin/activate && python /app/{}",
                 self.config.entrypoint.join(" "),
             ),
         ]));
@@ -399,6 +1089,151 @@ impl PythonImageBuilder {
         Ok(final_config)
     }
 
+    /// Wraps the runtime `config` in a full image configuration derived from
+    /// the base image: `rootfs.diff_ids` lists every layer in manifest order
+    /// and `history` gains one entry per layer we built on top of it.
+    fn generate_image_configuration(
+        &self,
+        base: &ImageConfiguration,
+        config: OCIConfig,
+        layers: &[Layer],
+        created_by: &[String],
+    ) -> Result<ImageConfiguration> {
+        let created = Self::rfc3339(
+            source_date_epoch()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap_or_else(SystemTime::now),
+        );
+
+        // Some builders squash or omit history; keep the base's as it is and
+        // only append entries for our layers
+        let base_layers = base.rootfs().diff_ids().len();
+        let base_history_layers = base
+            .history()
+            .iter()
+            .filter(|h| !h.empty_layer().unwrap_or(false))
+            .count();
+        if base_history_layers != base_layers {
+            tracing::warn!(
+                "Base image history describes {} layers but its rootfs has {}",
+                base_history_layers,
+                base_layers
+            );
+        }
+
+        let mut history: Vec<History> = base.history().clone();
+        for step in created_by {
+            history.push(
+                HistoryBuilder::default()
+                    .created(created.clone())
+                    .created_by(step.clone())
+                    .comment("python-oci-packager")
+                    .build()?,
+            );
+        }
+
+        let rootfs = RootFsBuilder::default()
+            .typ("layers")
+            .diff_ids(
+                layers
+                    .iter()
+                    .map(|layer| layer.diff_id.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .build()?;
+
+        let mut image_config = base.clone();
+        image_config.set_created(Some(created));
+        image_config.set_config(Some(config));
+        image_config.set_rootfs(rootfs);
+        image_config.set_history(history);
+
+        Ok(image_config)
+    }
+
+    /// Formats `time` as an RFC 3339 UTC timestamp (e.g. `2024-01-31T12:00:00Z`).
+    fn rfc3339(time: SystemTime) -> String {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+        // Civil-from-days conversion, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            secs_of_day / 3_600,
+            secs_of_day % 3_600 / 60,
+            secs_of_day % 60
+        )
+    }
+
+    /// Sets `var` (`KEY=value`) in `env`, replacing any earlier value of
+    /// `KEY` and expanding `$NAME`/`${NAME}` references against the values
+    /// already set, the way a Dockerfile `ENV` instruction does.
+    fn merge_env(env: &mut Vec<String>, var: &str) {
+        let Some((key, value)) = var.split_once('=') else {
+            return;
+        };
+
+        let lookup = |name: &str| {
+            env.iter()
+                .filter_map(|e| e.split_once('='))
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.to_string())
+                .unwrap_or_default()
+        };
+
+        let mut expanded = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(pos) = rest.find('$') {
+            expanded.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+
+            let (name, remainder) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => ("", rest),
+                }
+            } else {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            };
+
+            if name.is_empty() {
+                expanded.push('$');
+            } else {
+                expanded.push_str(&lookup(name));
+            }
+            rest = remainder;
+        }
+        expanded.push_str(rest);
+
+        let entry = format!("{}={}", key, expanded);
+        match env
+            .iter_mut()
+            .find(|e| e.split_once('=').map(|(k, _)| k) == Some(key))
+        {
+            Some(existing) => *existing = entry,
+            None => env.push(entry),
+        }
+    }
+
     fn verify_layer_digest(&self, layer: &Layer) -> Result<()> {
         if !layer.digest.starts_with("sha256:") {
             return Err(anyhow::anyhow!("Invalid digest format"));
@@ -443,67 +1278,66 @@ impl PythonImageBuilder {
         })
     }
 
-    fn create_manifest(&self, config: OCIConfig, layers: Vec<Layer>) -> Result<Manifest> {
+    fn create_manifest(&self, config_json: &[u8], layers: &[Layer]) -> Result<Manifest> {
         // Calculate config JSON size and digest
-        let config_json = serde_json::to_vec(&config)?;
         let mut hasher = Sha256::new();
-        hasher.update(&config_json);
+        hasher.update(config_json);
         let config_digest = format!("sha256:{:x}", hasher.finalize());
 
-        Manifest::new(config, layers, config_json.len() as u64, config_digest)
+        Manifest::new(layers, config_json.len() as u64, config_digest)
     }
 
-    async fn write_image(&self, config: OCIConfig, manifest: Manifest) -> Result<()> {
-        // Create output directory structure
-        let blobs_dir = self.output_path.join("blobs/sha256");
-        tokio::fs::create_dir_all(&blobs_dir).await?;
+    async fn write_image(
+        &self,
+        config_json: &[u8],
+        manifest: &Manifest,
+        layers: &[Layer],
+    ) -> Result<()> {
+        let layout = ImageLayout::new(&self.output_path);
 
         // Write config
-        let config_json = serde_json::to_vec_pretty(&config)?;
-        let mut hasher = Sha256::new();
-        hasher.update(&config_json);
-        let config_digest = format!("{:x}", hasher.finalize());
-        tokio::fs::write(blobs_dir.join(&config_digest), config_json).await?;
-
-        // Write layers
-        for layer in &manifest.layers {
-            let digest = layer.digest.trim_start_matches("sha256:");
-            if let Some(data) = &layer.data {
-                tokio::fs::write(blobs_dir.join(digest), data).await?;
-            } else {
-                return Err(format_err!("Layer data is missing"));
+        let config_digest = layout.write_blob(config_json).await?;
+        if config_digest != manifest.config.digest {
+            return Err(format_err!(
+                "Config digest mismatch: expected {}, got {}",
+                manifest.config.digest,
+                config_digest
+            ));
+        }
+
+        // Write layers; an image may list the same layer more than once,
+        // but its blob is only stored once
+        let mut written = HashSet::new();
+        for layer in layers {
+            if written.insert(&layer.digest) {
+                layout.write_blob(&layer.data).await?;
             }
         }
 
-        // Write manifest
-        let manifest_json = serde_json::to_vec_pretty(&manifest)?;
-        tokio::fs::write(self.output_path.join("manifest.json"), manifest_json).await?;
+        // Write manifest and reference it from index.json
+        let manifest_json = manifest.to_bytes()?;
+        let manifest_digest = layout.write_blob(&manifest_json).await?;
+        layout
+            .write_index(&manifest_digest, manifest_json.len() as u64, &self.tag)
+            .await?;
 
-        // Write OCI layout file
-        let layout = serde_json::json!({
-            "imageLayoutVersion": "1.0.0"
-        });
-        tokio::fs::write(
-            self.output_path.join("oci-layout"),
-            serde_json::to_vec_pretty(&layout)?,
-        )
-        .await?;
+        layout
+            .validate()
+            .await
+            .context("Written image layout failed validation")?;
+
+        tracing::info!(
+            "Wrote image {} ({}) to {}",
+            self.tag,
+            manifest_digest,
+            self.output_path.display()
+        );
 
         Ok(())
     }
 
-    /// Verifies the integrity of all layers in the image
-    async fn verify_layers(&self, layers: &[&Layer]) -> Result<()> {
-        let mut seen_digests = std::collections::HashSet::new();
-        for layer in layers {
-            if !seen_digests.insert(&layer.digest) {
-                return Err(anyhow::anyhow!(
-                    "Duplicate layer detected with digest: {}",
-                    layer.digest
-                ));
-            }
-        }
-
+    /// Verifies the integrity of all layers in the image
+    async fn verify_layers(&self, layers: &[&Layer]) -> Result<()> {
         let verification_futures: Vec<_> = layers
             .iter()
             .map(|layer| self.verify_single_layer(layer))
@@ -571,9 +1405,12 @@ impl PythonImageBuilder {
 
     /// Validates if a media type is compliant with OCI specification
     fn is_valid_media_type(media_type: &str) -> bool {
-        const VALID_MEDIA_TYPES: [&str; 2] = [
+        const VALID_MEDIA_TYPES: [&str; 4] = [
             "application/vnd.oci.image.layer.v1.tar",
             "application/vnd.oci.image.layer.v1.tar+gzip",
+            "application/vnd.oci.image.layer.v1.tar+zstd",
+            // Base images pulled from Docker registries keep their original layer type
+            "application/vnd.docker.image.rootfs.diff.tar.gzip",
         ];
 
         VALID_MEDIA_TYPES.contains(&media_type)
@@ -582,42 +1419,35 @@ impl PythonImageBuilder {
     async fn pull_base_image(&mut self) -> Result<BaseImage> {
         tracing::info!("Pulling base image: {}", self.base_image);
 
-        if let Some(cached_layer) = self.cache.get_layer(&self.base_image).await {
-            tracing::debug!("Found base image layer in cache: {}", self.base_image);
-
-            // Get cached config or use default if not found
-            let config = self
-                .cache
-                .get_config(&self.base_image)
-                .await
-                .unwrap_or_default();
-
-            return Ok(BaseImage {
-                layer: cached_layer,
-                config,
-            });
-        }
-
-        tracing::debug!("Cache miss for base image: {}", self.base_image);
+        let registry = self.base_image.registry.clone();
+        let repository = self.base_image.repository.clone();
+        let manifest_reference = self.base_image.manifest_reference().to_string();
 
-        let (registry, repository, tag) = self.parse_image_reference(&self.base_image)?;
+        let client = Self::http_client(self.registries.options(&registry))?;
 
-        let client = Client::builder()
-            .use_rustls_tls() // Use rustls instead of OpenSSL
-            .timeout(Duration::from_secs(300))
-            .connect_timeout(Duration::from_secs(60))
-            .pool_idle_timeout(Duration::from_secs(90))
-            .pool_max_idle_per_host(5)
-            .build()
-            .context("Failed to create HTTP client")?;
+        // An explicit login is for the push target, so when pushing it only
+        // applies to the base image if that lives in the same registry.
+        let credentials = self.credentials.as_ref().filter(|_| {
+            self.push_target.as_ref().is_none_or(|target| {
+                target
+                    .parse::<Reference>()
+                    .is_ok_and(|target| target.registry == registry)
+            })
+        });
 
-        let auth_token = self
-            .authenticate_registry(&client, &registry, &repository)
-            .await
-            .context("Failed to authenticate with registry")?;
+        let auth = Self::authenticate_registry(
+            &client,
+            self.registries.options(&registry),
+            &registry,
+            &repository,
+            "pull",
+            credentials,
+        )
+        .await
+        .context("Failed to authenticate with registry")?;
 
         let manifest = self
-            .fetch_manifest(&client, &registry, &repository, &tag, &auth_token)
+            .fetch_manifest(&client, &registry, &repository, &manifest_reference, &auth)
             .await
             .context("Failed to fetch image manifest")?;
 
@@ -628,146 +1458,177 @@ impl PythonImageBuilder {
             ));
         }
 
-        let layer = self
-            .download_and_process_layers(&client, &registry, &repository, &manifest, &auth_token)
+        let base_config = self
+            .fetch_base_config(&client, &registry, &repository, &manifest, &auth)
+            .await?;
+
+        let layers = self
+            .download_and_process_layers(
+                &client,
+                &registry,
+                &repository,
+                &manifest,
+                base_config.rootfs().diff_ids(),
+                &auth,
+            )
             .await
             .context("Failed to download and process layers")?;
 
-        let metadata = LayerMetadata {
-            layer_type: LayerType::Application, // Base images are treated as application layers
-            source_hash: layer.digest.clone(),  // Use layer digest as source hash
-            dependencies: Vec::new(),           // Base images have no dependencies
-        };
+        Ok(BaseImage {
+            layers,
+            config: base_config,
+        })
+    }
 
-        self.cache
-            .store_layer(&self.base_image, &layer, metadata)
+    /// Fetches the base image's config blob, keyed in the cache by its digest.
+    async fn fetch_base_config(
+        &mut self,
+        client: &Client,
+        registry: &str,
+        repository: &str,
+        manifest: &ManifestV2Schema2,
+        auth: &RegistryAuth,
+    ) -> Result<ImageConfiguration> {
+        let digest = &manifest.config.digest;
+
+        if let Some(config) = self.cache.get_config(digest).await {
+            tracing::debug!("Found base image config in cache: {}", digest);
+            return Ok(config);
+        }
+
+        let config_blob = self
+            .download_blob(client, registry, repository, digest, auth)
             .await
-            .context("Failed to store layer in cache")?;
+            .context("Failed to download base image config")?;
+
+        let calculated_digest = sha256_digest(&config_blob);
+        if &calculated_digest != digest {
+            return Err(anyhow::anyhow!(
+                "Base image config digest mismatch: expected {}, calculated {}",
+                digest,
+                calculated_digest
+            ));
+        }
+
+        let config = ImageConfiguration::from_reader(config_blob.as_slice())
+            .context("Failed to parse base image config")?;
 
-        let config = ImageConfig::default();
         self.cache
-            .store_config(&self.base_image, &config)
+            .store_config(digest, &config_blob)
             .await
             .context("Failed to store config in cache")?;
 
-        Ok(BaseImage { layer, config })
+        Ok(config)
     }
 
-    fn parse_image_reference(&self, reference: &str) -> Result<(String, String, String)> {
-        let parts: Vec<&str> = reference.split('/').collect();
-
-        match parts.len() {
-            1 => {
-                let (repo, tag) = self.split_tag(parts[0])?;
-                // Use Docker Hub as default registry
-                Ok((
-                    "registry-1.docker.io".to_string(),
-                    format!("library/{}", repo), // Add 'library/' prefix for official images
-                    tag,
-                ))
-            }
-            2 => {
-                // Check if first part looks like a registry
-                if parts[0].contains('.') || parts[0].contains(':') {
-                    let (repo, tag) = self.split_tag(parts[1])?;
-                    Ok((parts[0].to_string(), repo.to_string(), tag))
-                } else {
-                    // Assume Docker Hub with organization
-                    let (repo, tag) = self.split_tag(parts[1])?;
-                    Ok((
-                        "registry-1.docker.io".to_string(),
-                        format!("{}/{}", parts[0], repo),
-                        tag,
-                    ))
-                }
-            }
-            3 => {
-                let (repo, tag) = self.split_tag(parts[2])?;
-                Ok((parts[0].to_string(), format!("{}/{}", parts[1], repo), tag))
+    /// Client for one registry, trusting its extra CAs and presenting its
+    /// client certificate, if configured.
+    fn http_client(options: &RegistryOptions) -> Result<Client> {
+        let mut builder = Client::builder()
+            .use_rustls_tls() // Use rustls instead of OpenSSL
+            .timeout(Duration::from_secs(300))
+            .connect_timeout(Duration::from_secs(60))
+            .pool_idle_timeout(Duration::from_secs(90))
+            .pool_max_idle_per_host(5);
+
+        for bundle in &options.ca {
+            let pem = std::fs::read(bundle)
+                .with_context(|| format!("Failed to read CA bundle {}", bundle.display()))?;
+            for certificate in Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Invalid CA bundle {}", bundle.display()))?
+            {
+                builder = builder.add_root_certificate(certificate);
             }
-            _ => Err(anyhow::anyhow!(
-                "Invalid image reference format: {}",
-                reference
-            )),
         }
-    }
 
-    fn split_tag(&self, repo_tag: &str) -> Result<(String, String)> {
-        let parts: Vec<&str> = repo_tag.split(':').collect();
-        match parts.len() {
-            1 => Ok((parts[0].to_string(), "latest".to_string())),
-            2 => Ok((parts[0].to_string(), parts[1].to_string())),
-            _ => Err(anyhow::anyhow!("Invalid repository:tag format")),
+        if let Some(cert) = &options.client_cert {
+            let mut pem = std::fs::read(cert)
+                .with_context(|| format!("Failed to read client certificate {}", cert.display()))?;
+            if let Some(key) = &options.client_key {
+                pem.push(b'\n');
+                pem.extend(
+                    std::fs::read(key)
+                        .with_context(|| format!("Failed to read client key {}", key.display()))?,
+                );
+            }
+            let identity = Identity::from_pem(&pem)
+                .with_context(|| format!("Invalid client certificate {}", cert.display()))?;
+            builder = builder.identity(identity);
         }
+
+        builder.build().context("Failed to create HTTP client")
     }
 
+    /// Negotiates access for `actions` (e.g. `pull` or `pull,push`) on
+    /// `repository`, answering whatever challenge the registry's `/v2/`
+    /// endpoint returns. Without explicit `credentials`, any configured for
+    /// the registry in the Docker config are used.
     async fn authenticate_registry(
-        &self,
         client: &Client,
+        options: &RegistryOptions,
         registry: &str,
         repository: &str,
-    ) -> Result<String> {
-        // Try anonymous pull first
-        let manifest_url = format!("https://{}/v2/{}/manifests/latest", registry, repository);
-
-        let anonymous_response = client.get(&manifest_url).send().await?;
-
-        // If we get a 401, we need to authenticate
-        if anonymous_response.status() == reqwest::StatusCode::UNAUTHORIZED {
-            // Proceed with authentication as before
-            let auth_url = if registry == "registry-1.docker.io" {
-                format!(
-                    "https://auth.docker.io/token?service=registry.docker.io&scope=repository:{}:pull",
-                    repository
-                )
-            } else {
-                format!(
-                    "https://{}/token?service={}&scope=repository:{}:pull",
-                    registry, registry, repository
-                )
-            };
+        actions: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<RegistryAuth> {
+        let ping_url = format!("{}://{}/v2/", options.scheme(registry), registry);
+        let response = client
+            .get(&ping_url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach registry {}", registry))?;
 
-            let response = client
-                .get(&auth_url)
-                .header("Accept", "application/json")
-                .send()
-                .await
-                .context("Failed to send authentication request")?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(RegistryAuth::anonymous());
+        }
 
-            if !response.status().is_success() {
-                let status = response.status();
-                let text = response.text().await.unwrap_or_default();
-                return Err(anyhow::anyhow!(
-                    "Authentication failed: {} - {}",
-                    status,
-                    text
-                ));
-            }
+        // A registry may offer several schemes; prefer a token over sending
+        // the password with every request.
+        let mut challenges = Vec::new();
+        for header in response
+            .headers()
+            .get_all(reqwest::header::WWW_AUTHENTICATE)
+        {
+            let header = header.to_str().context("Invalid WWW-Authenticate header")?;
+            challenges.extend(Challenge::parse_all(header)?);
+        }
+        if challenges.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Registry {} returned 401 without a Basic or Bearer challenge",
+                registry
+            ));
+        }
+        let preferred = challenges
+            .iter()
+            .position(|challenge| matches!(challenge, Challenge::Bearer { .. }))
+            .unwrap_or(0);
+        let challenge = challenges.swap_remove(preferred);
 
-            let auth: RegistryAuth = response
-                .json()
-                .await
-                .context("Failed to parse authentication response")?;
+        let credentials = match credentials {
+            Some(credentials) => Some(credentials.clone()),
+            None => Credentials::lookup(registry).await?,
+        };
 
-            Ok(auth.token)
-        } else {
-            // No authentication needed
-            Ok(String::new())
-        }
+        RegistryAuth::from_challenge(
+            client,
+            challenge,
+            format!("repository:{}:{}", repository, actions),
+            credentials,
+        )
+        .await
     }
 
-    fn get_registry_endpoint(&self, registry: &str, repository: &str) -> String {
-        if registry == "registry-1.docker.io" {
-            // Docker Hub requires 'library/' prefix for official images
-            let repo = if !repository.contains('/') {
-                format!("library/{}", repository)
-            } else {
-                repository.to_string()
-            };
-            format!("https://{}/v2/{}", registry, repo)
-        } else {
-            format!("https://{}/v2/{}", registry, repository)
-        }
+    fn get_registry_endpoint(
+        options: &RegistryOptions,
+        registry: &str,
+        repository: &str,
+    ) -> String {
+        format!(
+            "{}://{}/v2/{}",
+            options.scheme(registry),
+            registry,
+            repository
+        )
     }
 
     fn get_docker_arch() -> String {
@@ -788,17 +1649,18 @@ impl PythonImageBuilder {
         client: &Client,
         registry: &str,
         repository: &str,
-        tag: &str,
-        token: &str,
+        reference: &str,
+        auth: &RegistryAuth,
     ) -> Result<ManifestV2Schema2> {
-        let base_url = self.get_registry_endpoint(registry, repository);
-        let manifest_url = format!("{}/manifests/{}", base_url, tag);
+        let base_url =
+            Self::get_registry_endpoint(self.registries.options(registry), registry, repository);
+        let manifest_url = format!("{}/manifests/{}", base_url, reference);
 
         tracing::debug!("Fetching manifest from: {}", manifest_url);
 
-        let response = client
-            .get(&manifest_url)
-            .header("Authorization", format!("Bearer {}", token))
+        let response = auth
+            .authorize(client.get(&manifest_url))
+            .await?
             .header(
                 "Accept",
                 "application/vnd.docker.distribution.manifest.v2+json, \
@@ -833,18 +1695,47 @@ impl PythonImageBuilder {
             ));
         }
 
-        let response_text = response.text().await?;
+        let response_body = response.bytes().await?;
         tracing::debug!("Parsing manifest index");
-        tracing::debug!("Response text: {}", response_text);
+        tracing::debug!("Response text: {}", String::from_utf8_lossy(&response_body));
+
+        // A digest-pinned pull must get exactly the manifest (or index) it names
+        if reference.contains(':') {
+            Self::verify_manifest_digest(reference, &response_body)?;
+        }
 
-        if content_type.contains("index") {
+        if INDEX_MEDIA_TYPES
+            .iter()
+            .any(|media_type| content_type.starts_with(media_type))
+        {
             let index: ManifestIndex =
-                serde_json::from_str(&response_text).context("Failed to parse manifest index")?;
+                serde_json::from_slice(&response_body).context("Failed to parse manifest index")?;
+
+            if index.schema_version != 2 {
+                return Err(anyhow::anyhow!(
+                    "Unsupported manifest index schema version: {}",
+                    index.schema_version
+                ));
+            }
+            if let Some(media_type) = index
+                .media_type
+                .as_deref()
+                .filter(|media_type| !INDEX_MEDIA_TYPES.contains(media_type))
+            {
+                return Err(anyhow::anyhow!(
+                    "Manifest index declares media type {}",
+                    media_type
+                ));
+            }
 
-            let target_arch = Self::get_docker_arch();
+            let target_arch = self.architecture()?;
+            let target_variant = self.variant();
             tracing::debug!(
-                "Looking for manifest matching architecture: {}",
-                target_arch
+                "Looking for manifest matching architecture: {}{}",
+                target_arch,
+                target_variant
+                    .map(|v| format!("/{}", v))
+                    .unwrap_or_default()
             );
 
             let manifest = index
@@ -854,20 +1745,39 @@ impl PythonImageBuilder {
                     !m.annotations.values().any(|v| v.contains("attestation"))
                         && m.platform.architecture == target_arch
                         && m.platform.os == "linux"
+                        && target_variant
+                            .is_none_or(|variant| m.platform.variant.as_deref() == Some(variant))
                 })
                 .ok_or_else(|| {
-                    anyhow::anyhow!("No manifest found for architecture: {}", target_arch)
+                    anyhow::anyhow!(
+                        "No manifest found for architecture: {}{}",
+                        target_arch,
+                        target_variant
+                            .map(|v| format!("/{}", v))
+                            .unwrap_or_default()
+                    )
                 })?;
 
-            tracing::debug!("Found matching manifest with digest: {}", manifest.digest);
+            tracing::debug!(
+                "Found matching manifest with digest: {} ({}/{}{})",
+                manifest.digest,
+                manifest.platform.os,
+                manifest.platform.architecture,
+                manifest
+                    .platform
+                    .variant
+                    .as_deref()
+                    .map(|v| format!("/{}", v))
+                    .unwrap_or_default()
+            );
 
             // Fetch the specific manifest with updated URL
             let specific_manifest_url = format!("{}/manifests/{}", base_url, manifest.digest);
             tracing::debug!("Fetching specific manifest from: {}", specific_manifest_url);
 
-            let manifest_response = client
-                .get(specific_manifest_url)
-                .header("Authorization", format!("Bearer {}", token))
+            let manifest_response = auth
+                .authorize(client.get(specific_manifest_url))
+                .await?
                 .header("Accept", &manifest.media_type)
                 .send()
                 .await
@@ -883,64 +1793,184 @@ impl PythonImageBuilder {
                 ));
             }
 
-            let manifest_text = manifest_response.text().await?;
-            tracing::debug!("Received specific manifest: {}", manifest_text);
+            let manifest_body = manifest_response.bytes().await?;
+            tracing::debug!(
+                "Received specific manifest: {}",
+                String::from_utf8_lossy(&manifest_body)
+            );
+            if manifest_body.len() as u64 != manifest.size {
+                return Err(anyhow::anyhow!(
+                    "Manifest size mismatch for {}: index says {}, got {}",
+                    manifest.digest,
+                    manifest.size,
+                    manifest_body.len()
+                ));
+            }
+            Self::verify_manifest_digest(&manifest.digest, &manifest_body)?;
 
-            serde_json::from_str(&manifest_text)
+            Self::parse_image_manifest(&manifest_body)
                 .context("Failed to parse architecture-specific manifest")
         } else {
-            serde_json::from_str(&response_text).context("Failed to parse direct manifest")
+            Self::parse_image_manifest(&response_body).context("Failed to parse direct manifest")
+        }
+    }
+
+    fn parse_image_manifest(body: &[u8]) -> Result<ManifestV2Schema2> {
+        if let Ok(legacy) = serde_json::from_slice::<ManifestV1>(body) {
+            return Err(Self::schema1_error(&legacy));
+        }
+
+        let manifest: ManifestV2Schema2 = serde_json::from_slice(body)?;
+
+        if let Some(media_type) = manifest
+            .media_type
+            .as_deref()
+            .filter(|media_type| !MANIFEST_MEDIA_TYPES.contains(media_type))
+        {
+            return Err(anyhow::anyhow!(
+                "Unsupported manifest media type: {}",
+                media_type
+            ));
         }
+
+        Ok(manifest)
+    }
+
+    fn schema1_error(manifest: &ManifestV1) -> anyhow::Error {
+        for layer in &manifest.fs_layers {
+            tracing::debug!("Schema 1 layer: {}", layer.blob_sum);
+        }
+
+        // The newest history entry carries the image's creation time
+        let created = manifest
+            .history
+            .first()
+            .and_then(|h| serde_json::from_str::<serde_json::Value>(&h.v1_compatibility).ok())
+            .and_then(|v| v.get("created")?.as_str().map(String::from));
+
+        anyhow::anyhow!(
+            "{}:{} ({}, {} layers{}) only has a schema {} manifest, which is not supported; \
+             re-push it with a current Docker or registry client",
+            manifest.name,
+            manifest.tag,
+            manifest.architecture,
+            manifest.fs_layers.len(),
+            created
+                .map(|c| format!(", created {}", c))
+                .unwrap_or_default(),
+            manifest.schema_version
+        )
+    }
+
+    fn verify_manifest_digest(expected: &str, body: &[u8]) -> Result<()> {
+        if !expected.starts_with("sha256:") {
+            return Err(anyhow::anyhow!(
+                "Unsupported manifest digest algorithm: {}",
+                expected
+            ));
+        }
+
+        let calculated = sha256_digest(body);
+        if calculated != expected {
+            return Err(anyhow::anyhow!(
+                "Manifest digest mismatch: expected {}, calculated {}",
+                expected,
+                calculated
+            ));
+        }
+
+        Ok(())
     }
 
+    /// Fetches each base image layer as its own blob, keeping the registry's
+    /// digest and media type and pairing it with the matching `diff_id` from
+    /// the base image config. Layers already in the cache are not downloaded.
     async fn download_and_process_layers(
-        &self,
+        &mut self,
         client: &Client,
         registry: &str,
         repository: &str,
         manifest: &ManifestV2Schema2,
-        token: &str,
-    ) -> Result<Layer> {
-        let mut combined_data =
-            Vec::with_capacity(manifest.layers.iter().map(|l| l.size as usize).sum());
-        let mut total_size = 0;
+        diff_ids: &[String],
+        auth: &RegistryAuth,
+    ) -> Result<Vec<Layer>> {
+        if diff_ids.len() != manifest.layers.len() {
+            return Err(anyhow::anyhow!(
+                "Base image config lists {} diff_ids for {} layers",
+                diff_ids.len(),
+                manifest.layers.len()
+            ));
+        }
+
+        let mut layers = Vec::with_capacity(manifest.layers.len());
+
+        for (descriptor, diff_id) in manifest.layers.iter().zip(diff_ids) {
+            let cached = self.cache.get_layer(&descriptor.digest).await;
+            self.cache_stats.record(cached.is_some());
+            if let Some(cached_layer) = cached {
+                tracing::debug!("Found base image layer in cache: {}", descriptor.digest);
+                layers.push(cached_layer);
+                continue;
+            }
 
-        for layer in &manifest.layers {
-            tracing::debug!("Downloading layer: {}", layer.digest);
+            tracing::debug!("Downloading layer: {}", descriptor.digest);
 
             let layer_data = self
-                .download_blob(client, registry, repository, &layer.digest, token)
+                .download_blob(client, registry, repository, &descriptor.digest, auth)
                 .await
-                .with_context(|| format!("Failed to download layer: {}", layer.digest))?;
+                .with_context(|| Self::layer_download_error(descriptor))?;
+
+            if layer_data.len() != descriptor.size as usize {
+                return Err(anyhow::anyhow!(
+                    "Layer size mismatch for {}: expected {}, got {}",
+                    descriptor.digest,
+                    descriptor.size,
+                    layer_data.len()
+                ));
+            }
+
+            let layer = Layer {
+                media_type: descriptor.media_type.clone(),
+                digest: descriptor.digest.clone(),
+                size: descriptor.size,
+                compressed_size: descriptor.size,
+                data: layer_data,
+                diff_id: diff_id.clone(),
+                annotations: Default::default(),
+            };
+            self.verify_layer_digest(&layer)?;
+
+            let metadata = LayerMetadata {
+                layer_type: LayerType::Base,
+                source_hash: layer.diff_id.clone(),
+                dependencies: Vec::new(), // Base layers have no dependencies
+                created_by: None,
+            };
 
-            // Verify layer size
-            // if layer_data.len() != layer.size as usize {
-            //     return Err(anyhow::anyhow!(
-            //         "Layer size mismatch for {}: expected {}, got {}",
-            //         layer.digest,
-            //         layer.size,
-            //         layer_data.len()
-            //     ));
-            // }
+            self.cache
+                .store_layer(&layer.digest, &layer, metadata)
+                .await
+                .context("Failed to store layer in cache")?;
 
-            total_size += layer_data.len();
-            combined_data.extend(layer_data);
+            layers.push(layer);
         }
 
-        // Calculate the combined layer digest
-        let mut hasher = Sha256::new();
-        hasher.update(&combined_data);
-        let digest = format!("sha256:{:x}", hasher.finalize());
-
-        Ok(Layer {
-            media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
-            digest: digest.clone(),
-            size: total_size as u64,
-            compressed_size: total_size as u64,
-            data: combined_data,
-            diff_id: digest.clone(),
-            annotations: Default::default(),
-        })
+        Ok(layers)
+    }
+
+    /// Foreign layers are only named here; fetching them from their `urls`
+    /// is not supported, so say where they live instead.
+    fn layer_download_error(layer: &ManifestLayer) -> String {
+        if layer.urls.is_empty() {
+            format!("Failed to download layer: {}", layer.digest)
+        } else {
+            format!(
+                "Failed to download foreign layer {} from the registry; \
+                 fetching it from {} is not supported",
+                layer.digest,
+                layer.urls.join(", ")
+            )
+        }
     }
 
     async fn download_blob(
@@ -949,13 +1979,17 @@ impl PythonImageBuilder {
         registry: &str,
         repository: &str,
         digest: &str,
-        token: &str,
+        auth: &RegistryAuth,
     ) -> Result<Vec<u8>> {
-        let blob_url = format!("https://{}/v2/{}/blobs/{}", registry, repository, digest);
+        let blob_url = format!(
+            "{}/blobs/{}",
+            Self::get_registry_endpoint(self.registries.options(registry), registry, repository),
+            digest
+        );
 
-        let response = client
-            .get(&blob_url)
-            .header("Authorization", format!("Bearer {}", token))
+        let response = auth
+            .authorize(client.get(&blob_url))
+            .await?
             .send()
             .await
             .context("Failed to download blob")?;
@@ -973,4 +2007,363 @@ impl PythonImageBuilder {
             .map(|b| b.to_vec())
             .context("Failed to read blob data")
     }
+
+    /// Takes the config and layers by value so their bytes are handed to
+    /// the HTTP client without being copied.
+    async fn push_image(
+        target: &str,
+        config_json: Bytes,
+        manifest: &Manifest,
+        layers: Vec<Layer>,
+        credentials: Option<&Credentials>,
+        registries: &Registries,
+    ) -> Result<()> {
+        tracing::info!("Pushing image: {}", target);
+
+        let reference: Reference = target.parse()?;
+        if reference.digest.is_some() {
+            return Err(anyhow::anyhow!(
+                "Cannot push to digest reference {}; use a tag",
+                target
+            ));
+        }
+        let Reference {
+            registry,
+            repository,
+            tag,
+            ..
+        } = reference;
+        let tag = tag.unwrap_or_else(|| "latest".to_string());
+        let options = registries.options(&registry);
+        let client = Self::http_client(options)?;
+
+        let auth = Self::authenticate_registry(
+            &client,
+            options,
+            &registry,
+            &repository,
+            "pull,push",
+            credentials,
+        )
+        .await
+        .context("Failed to authenticate with registry")?;
+
+        let base_url = Self::get_registry_endpoint(options, &registry, &repository);
+
+        Self::push_blob(
+            &client,
+            &base_url,
+            &manifest.config.digest,
+            config_json,
+            &auth,
+        )
+        .await
+        .context("Failed to push image config")?;
+
+        let mut pushed = HashSet::new();
+        for layer in layers {
+            if !pushed.insert(layer.digest.clone()) {
+                continue;
+            }
+            Self::push_blob(&client, &base_url, &layer.digest, layer.data.into(), &auth)
+                .await
+                .with_context(|| format!("Failed to push layer: {}", layer.digest))?;
+        }
+
+        Self::put_manifest(&client, &base_url, &tag, manifest, &auth)
+            .await
+            .context("Failed to push image manifest")?;
+
+        tracing::info!("Pushed {}:{} to {}", repository, tag, registry);
+        Ok(())
+    }
+
+    async fn push_blob(
+        client: &Client,
+        base_url: &str,
+        digest: &str,
+        data: Bytes,
+        auth: &RegistryAuth,
+    ) -> Result<()> {
+        if Self::blob_exists(client, base_url, digest, auth).await? {
+            tracing::debug!("Blob already present in registry: {}", digest);
+            return Ok(());
+        }
+
+        tracing::debug!("Uploading blob: {} ({} bytes)", digest, data.len());
+
+        let response = auth
+            .authorize(client.post(format!("{}/blobs/uploads/", base_url)))
+            .await?
+            .header("Content-Length", "0")
+            .send()
+            .await
+            .context("Failed to start blob upload")?;
+
+        if response.status() != StatusCode::ACCEPTED {
+            return Err(Self::registry_error("Failed to start blob upload", response).await);
+        }
+
+        let mut location = Self::upload_location(base_url, &response)?;
+
+        // Small blobs go up in a single monolithic PUT; anything larger is
+        // streamed in chunks and then committed with an empty PUT.
+        let body = if data.len() <= UPLOAD_CHUNK_SIZE {
+            data
+        } else {
+            for offset in (0..data.len()).step_by(UPLOAD_CHUNK_SIZE) {
+                let chunk = data.slice(offset..data.len().min(offset + UPLOAD_CHUNK_SIZE));
+                let end = offset + chunk.len() - 1;
+                let response = auth
+                    .authorize(client.patch(location.clone()))
+                    .await?
+                    .header("Content-Type", "application/octet-stream")
+                    .header("Content-Range", format!("{}-{}", offset, end))
+                    .header("Content-Length", chunk.len().to_string())
+                    .body(chunk)
+                    .send()
+                    .await
+                    .context("Failed to upload blob chunk")?;
+
+                if response.status() != StatusCode::ACCEPTED {
+                    return Err(
+                        Self::registry_error("Failed to upload blob chunk", response).await,
+                    );
+                }
+
+                location = Self::upload_location(base_url, &response)?;
+            }
+            Bytes::new()
+        };
+
+        location.query_pairs_mut().append_pair("digest", digest);
+
+        let response = auth
+            .authorize(client.put(location))
+            .await?
+            .header("Content-Type", "application/octet-stream")
+            .header("Content-Length", body.len().to_string())
+            .body(body)
+            .send()
+            .await
+            .context("Failed to complete blob upload")?;
+
+        if response.status() != StatusCode::CREATED {
+            return Err(Self::registry_error("Failed to complete blob upload", response).await);
+        }
+
+        Ok(())
+    }
+
+    async fn blob_exists(
+        client: &Client,
+        base_url: &str,
+        digest: &str,
+        auth: &RegistryAuth,
+    ) -> Result<bool> {
+        let response = auth
+            .authorize(client.head(format!("{}/blobs/{}", base_url, digest)))
+            .await?
+            .send()
+            .await
+            .context("Failed to check blob existence")?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(anyhow::anyhow!(
+                "Failed to check blob existence: {}",
+                status
+            )),
+        }
+    }
+
+    async fn put_manifest(
+        client: &Client,
+        base_url: &str,
+        tag: &str,
+        manifest: &Manifest,
+        auth: &RegistryAuth,
+    ) -> Result<()> {
+        let response = auth
+            .authorize(client.put(format!("{}/manifests/{}", base_url, tag)))
+            .await?
+            .header("Content-Type", &manifest.media_type)
+            .body(manifest.to_bytes()?)
+            .send()
+            .await
+            .context("Failed to send manifest")?;
+
+        if response.status() != StatusCode::CREATED {
+            return Err(Self::registry_error("Failed to push manifest", response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the `Location` header of an upload response, which registries
+    /// may return either as an absolute URL or relative to the registry root.
+    fn upload_location(base_url: &str, response: &Response) -> Result<Url> {
+        let location = response
+            .headers()
+            .get("Location")
+            .and_then(|h| h.to_str().ok())
+            .ok_or_else(|| anyhow::anyhow!("Upload response is missing a Location header"))?;
+
+        Url::parse(base_url)?
+            .join(location)
+            .with_context(|| format!("Invalid upload location: {}", location))
+    }
+
+    async fn registry_error(context: &str, response: Response) -> anyhow::Error {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+
+        match serde_json::from_str::<RegistryError>(&text) {
+            Ok(error) => {
+                let details = error
+                    .errors
+                    .iter()
+                    .map(|e| match &e.detail {
+                        Some(detail) => format!("{}: {} ({})", e.code, e.message, detail),
+                        None => format!("{}: {}", e.code, e.message),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                anyhow::anyhow!("{}: {} - {}", context, status, details)
+            }
+            Err(_) => anyhow::anyhow!("{}: {} - {}", context, status, text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn builder(project: &Path) -> PythonImageBuilder {
+        let cache = Cache::new(project.join("cache")).await.unwrap();
+        PythonImageBuilder::new(
+            project.to_path_buf(),
+            project.join("out"),
+            "python:3.12-slim".to_string(),
+            ImageConfig::from_project(project).unwrap(),
+            cache,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn isolates_the_venv_from_system_site_packages() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let builder = builder(dir.path())
+            .await
+            .with_compression(Compression::None);
+        let target = TargetPython::resolve(
+            None,
+            None,
+            "3.12-slim",
+            &["PYTHON_VERSION=3.12.1".to_string()],
+            "amd64",
+        )
+        .unwrap();
+        let build_dir = tempfile::tempdir().unwrap();
+
+        let output = builder
+            .create_venv_layer(build_dir.path(), &target)
+            .await
+            .unwrap();
+
+        let mut archive = tar::Archive::new(output.layer.data.as_slice());
+        let mut entry = archive
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|entry| entry.path().unwrap() == Path::new("venv/pyvenv.cfg"))
+            .unwrap();
+        let mut cfg = String::new();
+        entry.read_to_string(&mut cfg).unwrap();
+        assert_eq!(
+            cfg,
+            "home = /usr/local/bin\ninclude-system-site-packages = false\nversion = 3.12\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn puts_the_venv_first_on_the_base_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let builder = builder(dir.path()).await;
+
+        let mut base_config = OCIConfig::default();
+        base_config.set_env(Some(vec![
+            "PATH=/usr/local/bin:/usr/bin:/bin".to_string(),
+            "LANG=C.UTF-8".to_string(),
+        ]));
+        let mut base = ImageConfiguration::default();
+        base.set_config(Some(base_config));
+
+        let config = builder
+            .generate_config(
+                &base,
+                &[
+                    &builder.venv_config().unwrap(),
+                    &builder.deps_config().unwrap(),
+                    &builder.app_config().unwrap(),
+                ],
+            )
+            .unwrap();
+
+        let env = config.env().clone().unwrap();
+        assert!(env.contains(&"PATH=/venv/bin:/usr/local/bin:/usr/bin:/bin".to_string()));
+        assert!(env.contains(&"LANG=C.UTF-8".to_string()));
+    }
+
+    #[tokio::test]
+    async fn appends_history_to_a_base_without_any() {
+        let dir = tempfile::tempdir().unwrap();
+        let builder = builder(dir.path()).await;
+
+        // A squashed base: two layers, no history entries
+        let base: ImageConfiguration = serde_json::from_value(serde_json::json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "history": [],
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": ["sha256:base1", "sha256:base2"],
+            },
+        }))
+        .unwrap();
+        let layers: Vec<Layer> = ["sha256:base1", "sha256:base2", "sha256:app"]
+            .iter()
+            .map(|diff_id| Layer {
+                media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+                digest: diff_id.to_string(),
+                size: 0,
+                compressed_size: 0,
+                data: Vec::new(),
+                diff_id: diff_id.to_string(),
+                annotations: HashMap::new(),
+            })
+            .collect();
+
+        let image = builder
+            .generate_image_configuration(
+                &base,
+                OCIConfig::default(),
+                &layers,
+                &["COPY . /app".to_string()],
+            )
+            .unwrap();
+
+        assert_eq!(image.rootfs().diff_ids().len(), 3);
+        let created_by: Vec<_> = image
+            .history()
+            .iter()
+            .map(|h| h.created_by().clone())
+            .collect();
+        assert_eq!(created_by, [Some("COPY . /app".to_string())]);
+    }
 }
@@ -1,82 +1,289 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use oci_spec::image::ImageConfiguration;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 use tokio::fs;
 
-use crate::image::ImageConfig;
+use crate::fs::{write_atomic, write_atomic_blocking};
 use crate::layer::Layer;
+use crate::layout::{sha256_digest, ImageLayout};
 
+/// Layers and configs cached between builds. Their bytes live once each in
+/// a content-addressed `blobs/sha256` store, however many entries refer to
+/// them; `index.json` maps cache keys to blob digests and metadata.
+///
+/// Several builds may share one cache directory. Every change to the index
+/// is made under an advisory lock on `index.lock`, against the index as it
+/// is on disk at that moment, and files are replaced by rename so readers
+/// never see a partial write.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Cache {
     cache_dir: PathBuf,
     layer_index: HashMap<String, LayerCacheEntry>,
     dependency_index: HashMap<String, String>,
     config_index: HashMap<String, ConfigCacheEntry>,
+    /// Hits since the index was last saved, recorded into the entries'
+    /// `last_access` on the next save
+    #[serde(skip)]
+    layer_access: Mutex<HashMap<String, SystemTime>>,
+    #[serde(skip)]
+    config_access: Mutex<HashMap<String, SystemTime>>,
+    #[serde(skip)]
+    limits: CacheLimits,
+}
+
+/// Bounds enforced by `Cache::cleanup`; `None` means unbounded.
+#[derive(Debug, Clone, Default)]
+pub struct CacheLimits {
+    pub max_age: Option<Duration>,
+    /// Total size of the blob store in bytes
+    pub max_size: Option<u64>,
+}
+
+impl CacheLimits {
+    /// Size the cache is evicted down to after a build when none is set.
+    pub const DEFAULT_MAX_SIZE: u64 = 10 << 30;
+    /// Age past which entries are dropped after a build when none is set.
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+    /// Limits for the cleanup after a build: those given, else the defaults,
+    /// so a cache on a long-lived runner never grows without bound.
+    pub fn for_build(max_size: Option<u64>, max_age: Option<Duration>) -> Self {
+        Self {
+            max_age: Some(max_age.unwrap_or(Self::DEFAULT_MAX_AGE)),
+            max_size: Some(max_size.unwrap_or(Self::DEFAULT_MAX_SIZE)),
+        }
+    }
+}
+
+/// What a `Cache::cleanup` run removed.
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    pub entries: usize,
+    pub blobs: usize,
+    pub bytes: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ConfigCacheEntry {
-    path: PathBuf,
+    digest: String,
     timestamp: std::time::SystemTime,
+    #[serde(default)]
+    last_access: Option<SystemTime>,
 }
 
+/// A cached layer: everything in `Layer` except its bytes, which are the
+/// blob named by `digest`.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LayerCacheEntry {
     digest: String,
-    path: PathBuf,
+    media_type: String,
+    size: u64,
+    compressed_size: u64,
+    diff_id: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
     timestamp: std::time::SystemTime,
+    #[serde(default)]
+    last_access: Option<SystemTime>,
     metadata: LayerMetadata,
 }
 
+/// One layer or config entry, as shown by `cache list`.
+#[derive(Debug)]
+pub struct CacheEntryInfo {
+    pub key: String,
+    /// The `LayerType` of a layer, or `Config`
+    pub kind: String,
+    pub digest: String,
+    pub size: u64,
+    pub age: Duration,
+    pub last_used: Duration,
+    pub source_hash: Option<String>,
+}
+
+/// What a `Cache::verify` run found and removed.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub blobs: usize,
+    pub corrupt: Vec<String>,
+    pub entries: usize,
+}
+
+/// What a `Cache::import` run added.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub entries: usize,
+    pub blobs: usize,
+}
+
+/// An index entry considered for eviction.
+enum Evictable {
+    Layer(String),
+    Config(String),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LayerMetadata {
     pub layer_type: LayerType,
     pub source_hash: String,
     pub dependencies: Vec<String>,
+    /// History entry for the layer, so a cache hit reproduces the image
+    /// config a fresh build would write
+    #[serde(default)]
+    pub created_by: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum LayerType {
+    Base,
     VirtualEnv,
     Dependencies,
     Application,
 }
 
+/// Layer cache lookups made during one build.
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl CacheStats {
+    pub fn record(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lookups = self.hits + self.misses;
+        let rate = if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 * 100.0 / lookups as f64
+        };
+        write!(
+            f,
+            "{} hits, {} misses ({:.0}% hit rate)",
+            self.hits, self.misses, rate
+        )
+    }
+}
+
 impl Cache {
     pub async fn new(cache_dir: PathBuf) -> Result<Self> {
         fs::create_dir_all(&cache_dir).await?;
 
+        let cache = Self::load_index(&cache_dir).await?.unwrap_or_else(|| Self {
+            cache_dir,
+            layer_index: HashMap::new(),
+            dependency_index: HashMap::new(),
+            config_index: HashMap::new(),
+            layer_access: Mutex::default(),
+            config_access: Mutex::default(),
+            limits: CacheLimits::default(),
+        });
+
+        Ok(cache)
+    }
+
+    /// Sets the bounds `cleanup` evicts down to.
+    pub fn with_limits(mut self, limits: CacheLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reads the index currently on disk, if there is a usable one.
+    async fn load_index(cache_dir: &Path) -> Result<Option<Self>> {
         let index_path = cache_dir.join("index.json");
-        let cache = if index_path.exists() {
-            let data = fs::read(&index_path).await?;
-            serde_json::from_slice(&data)?
-        } else {
-            Self {
-                cache_dir,
-                layer_index: HashMap::new(),
-                dependency_index: HashMap::new(),
-                config_index: HashMap::new(), // Initialize the new field
+        if !index_path.exists() {
+            return Ok(None);
+        }
+
+        let data = fs::read(&index_path).await?;
+        match serde_json::from_slice::<Self>(&data) {
+            Ok(mut cache) => {
+                cache.cache_dir = cache_dir.to_path_buf();
+                Ok(Some(cache))
             }
-        };
+            Err(e) => {
+                // An index from an older cache layout (or a damaged one) only
+                // costs us the entries; the layers get rebuilt or re-pulled.
+                tracing::warn!("Ignoring unreadable cache index: {}", e);
+                Ok(None)
+            }
+        }
+    }
 
-        Ok(cache)
+    /// Takes the advisory lock that serializes index changes between
+    /// processes; it is released when the returned file is dropped.
+    async fn lock_index(&self) -> Result<std::fs::File> {
+        let lock_path = self.cache_dir.join("index.lock");
+
+        tokio::task::spawn_blocking(move || -> Result<std::fs::File> {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)?;
+            file.lock()?;
+            Ok(file)
+        })
+        .await?
+    }
+
+    /// Picks up entries other builds have written since we last read the
+    /// index, so saving ours doesn't drop theirs. Call with the lock held.
+    async fn reload_index(&mut self) -> Result<()> {
+        if let Some(on_disk) = Self::load_index(&self.cache_dir).await? {
+            self.layer_index = on_disk.layer_index;
+            self.dependency_index = on_disk.dependency_index;
+            self.config_index = on_disk.config_index;
+        }
+
+        let layer_access = std::mem::take(self.layer_access.get_mut().unwrap());
+        for (key, accessed) in layer_access {
+            if let Some(entry) = self.layer_index.get_mut(&key) {
+                entry.last_access = entry.last_access.max(Some(accessed));
+            }
+        }
+        let config_access = std::mem::take(self.config_access.get_mut().unwrap());
+        for (key, accessed) in config_access {
+            if let Some(entry) = self.config_index.get_mut(&key) {
+                entry.last_access = entry.last_access.max(Some(accessed));
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn store_config(&mut self, key: &str, config: &ImageConfig) -> Result<()> {
-        let config_path = self.cache_dir.join(format!("config_{}.json", key));
+    fn blobs(&self) -> ImageLayout {
+        ImageLayout::new(&self.cache_dir)
+    }
+
+    /// Caches a config blob exactly as fetched, so it keeps its digest.
+    pub async fn store_config(&mut self, key: &str, config_blob: &[u8]) -> Result<()> {
+        let _lock = self.lock_index().await?;
+        self.reload_index().await?;
 
-        // Serialize and store config data
-        let config_data = serde_json::to_string_pretty(config)?;
-        fs::write(&config_path, config_data).await?;
+        let digest = self.blobs().write_blob(config_blob).await?;
 
         // Update index
         self.config_index.insert(
             key.to_string(),
             ConfigCacheEntry {
-                path: config_path,
+                digest,
                 timestamp: std::time::SystemTime::now(),
+                last_access: None,
             },
         );
 
@@ -86,47 +293,56 @@ impl Cache {
         Ok(())
     }
 
-    pub async fn get_config(&self, key: &str) -> Option<ImageConfig> {
+    pub async fn get_config(&self, key: &str) -> Option<ImageConfiguration> {
         let entry = self.config_index.get(key)?;
 
-        // Check if cached config still exists
-        if !entry.path.exists() {
-            return None;
-        }
-
-        // Load and deserialize config data
-        match fs::read(&entry.path).await {
-            Ok(data) => serde_json::from_slice(&data).ok(),
-            Err(_) => None,
+        match self.blobs().read_blob(&entry.digest, None).await {
+            Ok(data) => {
+                let config = ImageConfiguration::from_reader(data.as_slice()).ok()?;
+                self.config_access
+                    .lock()
+                    .unwrap()
+                    .insert(key.to_string(), SystemTime::now());
+                Some(config)
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring cached config {}: {:#}", key, e);
+                None
+            }
         }
     }
 
+    /// Returns the layer cached under `key` once its blob has been checked
+    /// against the layer digest and size.
     pub async fn get_layer(&self, key: &str) -> Option<Layer> {
         let entry = self.layer_index.get(key)?;
 
-        // Check if cached layer still exists
-        if !entry.path.exists() {
-            return None;
-        }
-
-        // Load layer data
-        match fs::read(&entry.path).await {
-            Ok(data) => {
-                // Verify layer integrity
-                let mut hasher = Sha256::new();
-                hasher.update(&data);
-                let digest = format!("sha256:{:x}", hasher.finalize());
-                let data_u8: &[u8] = data.as_slice();
-
-                if digest == entry.digest {
-                    // Deserialize and return layer
-                    bincode::deserialize(data_u8).ok()
-                } else {
-                    None
-                }
+        let data = match self
+            .blobs()
+            .read_blob(&entry.digest, Some(entry.compressed_size))
+            .await
+        {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!("Ignoring cached layer {}: {:#}", key, e);
+                return None;
             }
-            Err(_) => None,
-        }
+        };
+
+        self.layer_access
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), SystemTime::now());
+
+        Some(Layer {
+            media_type: entry.media_type.clone(),
+            digest: entry.digest.clone(),
+            size: entry.size,
+            compressed_size: entry.compressed_size,
+            data,
+            diff_id: entry.diff_id.clone(),
+            annotations: entry.annotations.clone(),
+        })
     }
 
     pub async fn store_layer(
@@ -135,93 +351,859 @@ impl Cache {
         layer: &Layer,
         metadata: LayerMetadata,
     ) -> Result<()> {
-        // Generate path for layer file
-        let layer_path = self.cache_dir.join(format!("layer_{}.bin", layer.digest));
+        let _lock = self.lock_index().await?;
+        self.reload_index().await?;
+        self.insert_layer_locked(key, layer, metadata).await?;
+        self.save_index().await
+    }
 
-        // Serialize and store layer data
-        let layer_data = bincode::serialize(layer)?;
-        fs::write(&layer_path, &layer_data).await?;
+    /// Writes the layer's blob and indexes it under `key`; the caller holds
+    /// the index lock and saves the index.
+    async fn insert_layer_locked(
+        &mut self,
+        key: &str,
+        layer: &Layer,
+        metadata: LayerMetadata,
+    ) -> Result<()> {
+        // Blobs are named by their content, so a layer already stored under
+        // another key (or pulled for another image) is written only once
+        if !self.blobs().blob_path(&layer.digest).exists() {
+            let digest = sha256_digest(&layer.data);
+            if digest != layer.digest {
+                return Err(anyhow::anyhow!(
+                    "Layer digest mismatch: expected {}, calculated {}",
+                    layer.digest,
+                    digest
+                ));
+            }
+            self.blobs().write_blob(&layer.data).await?;
+        }
 
-        // Update index
         self.layer_index.insert(
             key.to_string(),
             LayerCacheEntry {
                 digest: layer.digest.clone(),
-                path: layer_path,
+                media_type: layer.media_type.clone(),
+                size: layer.size,
+                compressed_size: layer.compressed_size,
+                diff_id: layer.diff_id.clone(),
+                annotations: layer.annotations.clone(),
                 timestamp: std::time::SystemTime::now(),
+                last_access: None,
                 metadata,
             },
         );
 
-        // Save updated index
-        self.save_index().await?;
-
         Ok(())
     }
 
-    pub async fn get_dependency_layer(&self, requirements: &Path) -> Option<Layer> {
-        // Calculate hash of requirements.txt
-        let req_content = fs::read(requirements).await.ok()?;
-        let mut hasher = Sha256::new();
-        hasher.update(&req_content);
-        let req_hash = format!("sha256:{:x}", hasher.finalize());
-
+    /// Looks up the dependency layer stored under `key`, which covers the
+    /// lock file (or requirements) contents and the target interpreter.
+    pub async fn get_dependency_layer(&self, key: &str) -> Option<Layer> {
         // Look up layer digest
-        let layer_digest = self.dependency_index.get(&req_hash)?;
+        let layer_digest = self.dependency_index.get(key)?;
 
         // Get layer from cache
         self.get_layer(layer_digest).await
     }
 
+    /// Metadata of the layer stored under `key`, either directly or, for
+    /// dependency layers, through the dependency index.
+    pub fn layer_metadata(&self, key: &str) -> Option<&LayerMetadata> {
+        let key = self.dependency_index.get(key).map_or(key, String::as_str);
+        self.layer_index.get(key).map(|entry| &entry.metadata)
+    }
+
+    pub async fn store_dependency_layer(
+        &mut self,
+        key: &str,
+        layer: &Layer,
+        metadata: LayerMetadata,
+    ) -> Result<()> {
+        // The layer and its dependency key land in the index together, so no
+        // other process sees one without the other
+        let _lock = self.lock_index().await?;
+        self.reload_index().await?;
+        self.insert_layer_locked(&layer.digest, layer, metadata)
+            .await?;
+        self.dependency_index
+            .insert(key.to_string(), layer.digest.clone());
+        self.save_index().await
+    }
+
     async fn save_index(&self) -> Result<()> {
         let index_path = self.cache_dir.join("index.json");
         let index_data = serde_json::to_string_pretty(&self)?;
-        fs::write(index_path, index_data).await?;
-        Ok(())
+        write_atomic(&index_path, index_data.as_bytes()).await
     }
 
-    pub async fn cleanup(&mut self, max_age: std::time::Duration) -> Result<()> {
-        let now = std::time::SystemTime::now();
+    /// Evicts entries older than `max_age`, then least recently used
+    /// entries until the blob store fits in `max_size`, and deletes the blobs
+    /// no remaining entry refers to. A blob shared by several entries only
+    /// counts as freed once the last of them is gone.
+    pub async fn cleanup(&mut self) -> Result<CleanupReport> {
+        let _lock = self.lock_index().await?;
+        self.reload_index().await?;
+
+        let mut report = CleanupReport::default();
+        let now = SystemTime::now();
+        // Builders sharing the cache may have clocks a little ahead of this
+        // host's; an entry stamped in the future is brand new, not expired
+        let expired = |timestamp: SystemTime| match self.limits.max_age {
+            Some(max_age) => now.duration_since(timestamp).is_ok_and(|age| age > max_age),
+            None => false,
+        };
 
         // Remove old entries from indexes
-        self.layer_index
-            .retain(|_, entry| match now.duration_since(entry.timestamp) {
-                Ok(age) => age <= max_age,
-                Err(_) => false,
+        let before = self.layer_index.len() + self.config_index.len();
+        let expired_layers: Vec<String> = self
+            .layer_index
+            .iter()
+            .filter(|(_, entry)| expired(entry.timestamp))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let expired_configs: Vec<String> = self
+            .config_index
+            .iter()
+            .filter(|(_, entry)| expired(entry.timestamp))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired_layers {
+            self.layer_index.remove(&key);
+        }
+        for key in expired_configs {
+            self.config_index.remove(&key);
+        }
+
+        // Size the blob store by distinct blob, however many entries share it
+        let blobs_dir = self.cache_dir.join("blobs/sha256");
+        let mut blob_sizes = HashMap::new();
+        if blobs_dir.exists() {
+            let mut entries = fs::read_dir(&blobs_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Dot-files are writes still in flight
+                if !name.starts_with('.') {
+                    blob_sizes.insert(format!("sha256:{}", name), entry.metadata().await?.len());
+                }
+            }
+        }
+
+        let mut references: HashMap<String, usize> = HashMap::new();
+        for digest in self
+            .layer_index
+            .values()
+            .map(|entry| &entry.digest)
+            .chain(self.config_index.values().map(|entry| &entry.digest))
+        {
+            *references.entry(digest.clone()).or_default() += 1;
+        }
+
+        if let Some(max_size) = self.limits.max_size {
+            let mut size: u64 = references
+                .keys()
+                .filter_map(|digest| blob_sizes.get(digest))
+                .sum();
+
+            let mut candidates: Vec<(SystemTime, Evictable)> = self
+                .layer_index
+                .iter()
+                .map(|(key, entry)| {
+                    let used = entry.last_access.unwrap_or(entry.timestamp);
+                    (used, Evictable::Layer(key.clone()))
+                })
+                .chain(self.config_index.iter().map(|(key, entry)| {
+                    let used = entry.last_access.unwrap_or(entry.timestamp);
+                    (used, Evictable::Config(key.clone()))
+                }))
+                .collect();
+            candidates.sort_by_key(|(used, _)| *used);
+
+            for (_, candidate) in candidates {
+                if size <= max_size {
+                    break;
+                }
+                let digest = match candidate {
+                    Evictable::Layer(key) => self.layer_index.remove(&key).map(|e| e.digest),
+                    Evictable::Config(key) => self.config_index.remove(&key).map(|e| e.digest),
+                };
+                let Some(digest) = digest else { continue };
+
+                let count = references.get_mut(&digest).expect("counted above");
+                *count -= 1;
+                if *count == 0 {
+                    references.remove(&digest);
+                    size = size.saturating_sub(blob_sizes.get(&digest).copied().unwrap_or(0));
+                }
+            }
+        }
+
+        report.entries = before - self.layer_index.len() - self.config_index.len();
+
+        let layer_index = &self.layer_index;
+        self.dependency_index
+            .retain(|_, digest| layer_index.contains_key(digest));
+
+        // Remove blobs no remaining entry refers to
+        for (digest, size) in &blob_sizes {
+            if !references.contains_key(digest) {
+                fs::remove_file(self.blobs().blob_path(digest)).await?;
+                report.blobs += 1;
+                report.bytes += size;
+            }
+        }
+
+        self.save_index().await?;
+        Ok(report)
+    }
+
+    /// Every layer and config entry, most recently used first.
+    pub async fn entries(&self) -> Vec<CacheEntryInfo> {
+        let now = SystemTime::now();
+        let age = |timestamp: SystemTime| now.duration_since(timestamp).unwrap_or_default();
+
+        let mut entries: Vec<CacheEntryInfo> = self
+            .layer_index
+            .iter()
+            .map(|(key, entry)| CacheEntryInfo {
+                key: key.clone(),
+                kind: format!("{:?}", entry.metadata.layer_type),
+                digest: entry.digest.clone(),
+                size: entry.compressed_size,
+                age: age(entry.timestamp),
+                last_used: age(entry.last_access.unwrap_or(entry.timestamp)),
+                source_hash: Some(entry.metadata.source_hash.clone()),
+            })
+            .collect();
+
+        for (key, entry) in &self.config_index {
+            let size = fs::metadata(self.blobs().blob_path(&entry.digest))
+                .await
+                .map_or(0, |metadata| metadata.len());
+            entries.push(CacheEntryInfo {
+                key: key.clone(),
+                kind: "Config".to_string(),
+                digest: entry.digest.clone(),
+                size,
+                age: age(entry.timestamp),
+                last_used: age(entry.last_access.unwrap_or(entry.timestamp)),
+                source_hash: None,
             });
+        }
 
+        entries.sort_by_key(|entry| entry.last_used);
+        entries
+    }
+
+    /// Re-hashes every blob, deletes those whose contents no longer match
+    /// their name, and drops the entries that referred to them (or to blobs
+    /// that are missing altogether).
+    pub async fn verify(&mut self) -> Result<VerifyReport> {
+        let _lock = self.lock_index().await?;
+        self.reload_index().await?;
+
+        let mut report = VerifyReport::default();
+        let blobs_dir = self.cache_dir.join("blobs/sha256");
+        if blobs_dir.exists() {
+            let mut entries = fs::read_dir(&blobs_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') {
+                    continue;
+                }
+
+                let digest = format!("sha256:{}", name);
+                report.blobs += 1;
+                if sha256_digest(&fs::read(entry.path()).await?) != digest {
+                    tracing::warn!("Removing corrupt cache blob {}", digest);
+                    fs::remove_file(entry.path()).await?;
+                    report.corrupt.push(digest);
+                }
+            }
+        }
+
+        let before = self.layer_index.len() + self.config_index.len();
+        let blobs = self.blobs();
+        self.layer_index
+            .retain(|_, entry| blobs.blob_path(&entry.digest).exists());
         self.config_index
-            .retain(|_, entry| match now.duration_since(entry.timestamp) {
-                Ok(age) => age <= max_age,
-                Err(_) => false,
-            });
+            .retain(|_, entry| blobs.blob_path(&entry.digest).exists());
+        let layer_index = &self.layer_index;
+        self.dependency_index
+            .retain(|_, digest| layer_index.contains_key(digest));
+        report.entries = before - self.layer_index.len() - self.config_index.len();
+
+        self.save_index().await?;
+        Ok(report)
+    }
+
+    /// Writes the index and every blob it refers to into a tarball that
+    /// `import` can load into another cache directory. Blobs are mostly
+    /// compressed layers already, so the tarball itself is not compressed.
+    pub async fn export(&mut self, path: &Path) -> Result<usize> {
+        let _lock = self.lock_index().await?;
+        self.reload_index().await?;
+
+        let index_data = serde_json::to_vec_pretty(&self)?;
+        let mut digests: Vec<&String> = self
+            .layer_index
+            .values()
+            .map(|entry| &entry.digest)
+            .chain(self.config_index.values().map(|entry| &entry.digest))
+            .collect();
+        digests.sort();
+        digests.dedup();
+        let blobs: Vec<(String, PathBuf)> = digests
+            .into_iter()
+            .map(|digest| (digest.clone(), self.blobs().blob_path(digest)))
+            .collect();
+
+        // The tar crate only does blocking I/O
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<usize> {
+            let mut archive = tar::Builder::new(std::fs::File::create(&path)?);
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(index_data.len() as u64);
+            header.set_mode(0o644);
+            archive.append_data(&mut header, "index.json", index_data.as_slice())?;
+
+            let mut exported = 0;
+            for (digest, blob_path) in blobs {
+                if !blob_path.exists() {
+                    tracing::warn!("Skipping missing cache blob {}", digest);
+                    continue;
+                }
+                let name = format!("blobs/sha256/{}", digest.trim_start_matches("sha256:"));
+                archive.append_path_with_name(&blob_path, name)?;
+                exported += 1;
+            }
 
-        // Remove orphaned files
-        let mut entries = fs::read_dir(&self.cache_dir).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
-                match ext {
-                    "bin" => {
-                        let is_referenced =
-                            self.layer_index.values().any(|entry| entry.path == path);
-                        if !is_referenced {
-                            fs::remove_file(path).await?;
+            archive.into_inner()?.sync_all()?;
+            Ok(exported)
+        })
+        .await?
+    }
+
+    /// Loads a tarball written by `export`. Blobs are checked against their
+    /// digests before they are added, and entries already present here are
+    /// kept rather than overwritten.
+    pub async fn import(&mut self, path: &Path) -> Result<ImportReport> {
+        let _lock = self.lock_index().await?;
+        self.reload_index().await?;
+
+        let blobs_dir = self.cache_dir.join("blobs/sha256");
+        let archive_path = path.to_path_buf();
+        let (index_data, added) =
+            tokio::task::spawn_blocking(move || -> Result<(Option<Vec<u8>>, usize)> {
+                std::fs::create_dir_all(&blobs_dir)?;
+                let mut index_data = None;
+                let mut added = 0;
+                let mut archive = tar::Archive::new(std::fs::File::open(&archive_path)?);
+
+                for entry in archive.entries()? {
+                    let mut entry = entry?;
+                    let name = entry.path()?.to_string_lossy().into_owned();
+                    let mut data = Vec::new();
+                    std::io::Read::read_to_end(&mut entry, &mut data)?;
+
+                    if name == "index.json" {
+                        index_data = Some(data);
+                    } else if let Some(hex) = name.strip_prefix("blobs/sha256/") {
+                        let digest = format!("sha256:{}", hex);
+                        if sha256_digest(&data) != digest {
+                            tracing::warn!(
+                                "Skipping corrupt blob {} in {}",
+                                digest,
+                                archive_path.display()
+                            );
+                            continue;
                         }
-                    }
-                    "json" => {
-                        let is_referenced =
-                            self.config_index.values().any(|entry| entry.path == path);
-                        if !is_referenced {
-                            fs::remove_file(path).await?;
+                        let blob_path = blobs_dir.join(hex);
+                        if !blob_path.exists() {
+                            write_atomic_blocking(&blob_path, &data)?;
+                            added += 1;
                         }
+                    } else {
+                        tracing::warn!(
+                            "Ignoring unexpected entry {} in {}",
+                            name,
+                            archive_path.display()
+                        );
                     }
-                    _ => {}
                 }
+
+                Ok((index_data, added))
+            })
+            .await??;
+
+        let mut report = ImportReport {
+            blobs: added,
+            ..Default::default()
+        };
+        let index_data =
+            index_data.ok_or_else(|| anyhow::anyhow!("No index.json in {}", path.display()))?;
+        let imported: Self = serde_json::from_slice(&index_data).context("Invalid cache index")?;
+        let blobs = self.blobs();
+        for (key, entry) in imported.layer_index {
+            if !self.layer_index.contains_key(&key) && blobs.blob_path(&entry.digest).exists() {
+                self.layer_index.insert(key, entry);
+                report.entries += 1;
+            }
+        }
+        for (key, entry) in imported.config_index {
+            if !self.config_index.contains_key(&key) && blobs.blob_path(&entry.digest).exists() {
+                self.config_index.insert(key, entry);
+                report.entries += 1;
+            }
+        }
+        for (key, digest) in imported.dependency_index {
+            if self.layer_index.contains_key(&digest) {
+                self.dependency_index.entry(key).or_insert(digest);
             }
         }
 
         self.save_index().await?;
-        Ok(())
+        Ok(report)
+    }
+}
+
+/// Parses a size such as `512M`, `10G` or `1.5GiB` (binary units) or a
+/// plain number of bytes.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size: {}", s))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return Err(anyhow::anyhow!("Invalid size unit in {}", s)),
+    };
+
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// Parses a duration such as `90s`, `30m`, `12h`, `7d` or `2w`; a plain
+/// number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid duration: {}", s))?;
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(anyhow::anyhow!("Invalid duration unit in {}", s)),
+    };
+
+    Ok(Duration::from_secs(number * seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(data: &[u8]) -> Layer {
+        Layer {
+            media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+            digest: sha256_digest(data),
+            size: data.len() as u64,
+            compressed_size: data.len() as u64,
+            data: data.to_vec(),
+            diff_id: sha256_digest(&[data, b"uncompressed"].concat()),
+            annotations: HashMap::new(),
+        }
+    }
+
+    fn metadata() -> LayerMetadata {
+        LayerMetadata {
+            layer_type: LayerType::Application,
+            source_hash: "sha256:source".to_string(),
+            dependencies: Vec::new(),
+            created_by: None,
+        }
+    }
+
+    async fn open(dir: &Path) -> Cache {
+        Cache::new(dir.to_path_buf()).await.unwrap()
+    }
+
+    fn keys(cache: &Cache) -> Vec<&str> {
+        let mut keys: Vec<&str> = cache
+            .layer_index
+            .keys()
+            .chain(cache.config_index.keys())
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn merges_entries_written_by_other_builds() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut first = open(dir.path()).await;
+        let mut second = open(dir.path()).await;
+
+        first
+            .store_layer("first", &layer(b"one"), metadata())
+            .await
+            .unwrap();
+        // `second` read the index before `first` wrote to it
+        second
+            .store_layer("second", &layer(b"two"), metadata())
+            .await
+            .unwrap();
+        second.store_config("config", b"{}").await.unwrap();
+
+        assert_eq!(keys(&second), ["config", "first", "second"]);
+        assert_eq!(keys(&open(dir.path()).await), ["config", "first", "second"]);
+
+        // Hits are recorded on the next save, whoever else saved meanwhile
+        assert!(first.get_layer("first").await.is_some());
+        second
+            .store_layer("third", &layer(b"three"), metadata())
+            .await
+            .unwrap();
+        first
+            .store_layer("fourth", &layer(b"four"), metadata())
+            .await
+            .unwrap();
+        let reopened = open(dir.path()).await;
+        assert!(reopened.layer_index["first"].last_access.is_some());
+        assert!(reopened.layer_index["second"].last_access.is_none());
+        assert_eq!(
+            keys(&reopened),
+            ["config", "first", "fourth", "second", "third"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_builds_keep_every_entry() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let builds: Vec<_> = (0..8)
+            .map(|n| {
+                let dir = dir.path().to_path_buf();
+                tokio::spawn(async move {
+                    let mut cache = open(&dir).await;
+                    let data = format!("layer {}", n).into_bytes();
+                    cache
+                        .store_layer(&format!("layer-{}", n), &layer(&data), metadata())
+                        .await
+                        .unwrap();
+                    cache
+                        .store_dependency_layer(
+                            &format!("deps-{}", n),
+                            &layer(b"shared dependencies"),
+                            metadata(),
+                        )
+                        .await
+                        .unwrap();
+                })
+            })
+            .collect();
+        for build in builds {
+            build.await.unwrap();
+        }
+
+        let cache = open(dir.path()).await;
+        assert_eq!(cache.layer_index.len(), 9);
+        assert_eq!(cache.dependency_index.len(), 8);
+        for n in 0..8 {
+            let layer = cache.get_layer(&format!("layer-{}", n)).await.unwrap();
+            assert_eq!(layer.data, format!("layer {}", n).into_bytes());
+            assert!(cache
+                .get_dependency_layer(&format!("deps-{}", n))
+                .await
+                .is_some());
+        }
+        // One blob per distinct layer, no leftover temporary files
+        let blobs = std::fs::read_dir(dir.path().join("blobs/sha256")).unwrap();
+        assert_eq!(blobs.count(), 9);
+    }
+
+    /// Backdates each entry's `last_access` (or `timestamp` for `created`)
+    /// by the given number of hours and saves the index.
+    async fn backdate(cache: &mut Cache, used: &[(&str, u64)], created: &[(&str, u64)]) {
+        let ago = |hours: u64| SystemTime::now() - Duration::from_secs(hours * 3600);
+        for (key, hours) in used {
+            match cache.layer_index.get_mut(*key) {
+                Some(entry) => entry.last_access = Some(ago(*hours)),
+                None => cache.config_index.get_mut(*key).unwrap().last_access = Some(ago(*hours)),
+            }
+        }
+        for (key, hours) in created {
+            cache.layer_index.get_mut(*key).unwrap().timestamp = ago(*hours);
+        }
+        cache.save_index().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = open(dir.path()).await;
+        for key in ["a", "b", "c"] {
+            let data = key.repeat(100);
+            cache
+                .store_layer(key, &layer(data.as_bytes()), metadata())
+                .await
+                .unwrap();
+        }
+        cache.store_config("config", &[b'{'; 100]).await.unwrap();
+        // Least recently used first: config, b, a, c
+        backdate(
+            &mut cache,
+            &[("config", 4), ("b", 3), ("a", 2), ("c", 1)],
+            &[],
+        )
+        .await;
+
+        let mut cache = cache.with_limits(CacheLimits {
+            max_age: None,
+            max_size: Some(300),
+        });
+        let report = cache.cleanup().await.unwrap();
+        assert_eq!((report.entries, report.blobs, report.bytes), (1, 1, 100));
+        assert_eq!(keys(&cache), ["a", "b", "c"]);
+
+        // A hit makes `b` the most recently used
+        assert!(cache.get_layer("b").await.is_some());
+        cache.limits.max_size = Some(100);
+        let report = cache.cleanup().await.unwrap();
+        assert_eq!((report.entries, report.blobs, report.bytes), (2, 2, 200));
+        assert_eq!(keys(&cache), ["b"]);
+        assert_eq!(keys(&open(dir.path()).await), ["b"]);
+        assert!(cache.get_layer("b").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn builds_are_bounded_by_default() {
+        let limits = CacheLimits::for_build(None, None);
+        assert_eq!(limits.max_size, Some(CacheLimits::DEFAULT_MAX_SIZE));
+        assert_eq!(limits.max_age, Some(CacheLimits::DEFAULT_MAX_AGE));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = open(dir.path()).await;
+        for key in ["stale", "older", "newer"] {
+            cache
+                .store_layer(key, &layer(key.repeat(100).as_bytes()), metadata())
+                .await
+                .unwrap();
+        }
+        backdate(
+            &mut cache,
+            &[("older", 2), ("newer", 1)],
+            &[("stale", 24 * 31)],
+        )
+        .await;
+
+        // A configured size still gets the default age limit
+        let mut cache = cache.with_limits(CacheLimits::for_build(Some(600), None));
+        let report = cache.cleanup().await.unwrap();
+        assert_eq!(report.entries, 2);
+        assert_eq!(keys(&cache), ["newer"]);
+    }
+
+    #[tokio::test]
+    async fn keeps_entries_stamped_in_the_future() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = open(dir.path()).await;
+        for key in ["ahead", "old"] {
+            cache
+                .store_layer(key, &layer(key.as_bytes()), metadata())
+                .await
+                .unwrap();
+        }
+        // Stored by a builder whose clock runs an hour ahead
+        cache.layer_index.get_mut("ahead").unwrap().timestamp =
+            SystemTime::now() + Duration::from_secs(3600);
+        backdate(&mut cache, &[], &[("old", 48)]).await;
+
+        let mut cache = cache.with_limits(CacheLimits {
+            max_age: Some(Duration::from_secs(24 * 3600)),
+            max_size: None,
+        });
+        let report = cache.cleanup().await.unwrap();
+        assert_eq!(report.entries, 1);
+        assert_eq!(keys(&cache), ["ahead"]);
+    }
+
+    #[tokio::test]
+    async fn keeps_blobs_a_live_entry_refers_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = open(dir.path()).await;
+        let shared = layer(&[b's'; 100]);
+        cache.store_layer("old", &shared, metadata()).await.unwrap();
+        cache
+            .store_layer("live", &shared, metadata())
+            .await
+            .unwrap();
+        cache
+            .store_dependency_layer("deps", &layer(&[b'd'; 100]), metadata())
+            .await
+            .unwrap();
+        let deps_digest = cache.dependency_index["deps"].clone();
+        backdate(&mut cache, &[], &[("old", 48), (&deps_digest, 48)]).await;
+
+        let mut cache = cache.with_limits(CacheLimits {
+            max_age: Some(Duration::from_secs(24 * 3600)),
+            max_size: None,
+        });
+        let report = cache.cleanup().await.unwrap();
+
+        // `old` expired, but `live` still refers to its blob
+        assert_eq!((report.entries, report.blobs, report.bytes), (2, 1, 100));
+        assert_eq!(keys(&cache), ["live"]);
+        assert_eq!(cache.get_layer("live").await.unwrap().data, shared.data);
+        // The dependency key goes with the layer it pointed at
+        assert!(cache.dependency_index.is_empty());
+        assert!(cache.get_dependency_layer("deps").await.is_none());
+
+        // A shared blob is freed once, when its last entry goes
+        cache
+            .store_layer("other", &layer(&[b'o'; 100]), metadata())
+            .await
+            .unwrap();
+        cache
+            .store_layer("again", &shared, metadata())
+            .await
+            .unwrap();
+        backdate(&mut cache, &[("live", 3), ("again", 2), ("other", 1)], &[]).await;
+        cache.limits = CacheLimits {
+            max_age: None,
+            max_size: Some(100),
+        };
+        let report = cache.cleanup().await.unwrap();
+        assert_eq!((report.entries, report.blobs, report.bytes), (2, 1, 100));
+        assert_eq!(keys(&cache), ["other"]);
+        assert!(!cache.blobs().blob_path(&shared.digest).exists());
+    }
+
+    #[tokio::test]
+    async fn verify_drops_corrupt_and_missing_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = open(dir.path()).await;
+        let (intact, corrupt, missing) = (layer(b"intact"), layer(b"corrupt"), layer(b"missing"));
+        cache
+            .store_layer("intact", &intact, metadata())
+            .await
+            .unwrap();
+        cache
+            .store_layer("missing", &missing, metadata())
+            .await
+            .unwrap();
+        cache
+            .store_dependency_layer("deps", &corrupt, metadata())
+            .await
+            .unwrap();
+
+        std::fs::write(cache.blobs().blob_path(&corrupt.digest), b"bit rot").unwrap();
+        std::fs::remove_file(cache.blobs().blob_path(&missing.digest)).unwrap();
+        // A corrupt blob is never served, even before verify runs
+        assert!(cache.get_dependency_layer("deps").await.is_none());
+
+        let report = cache.verify().await.unwrap();
+        assert_eq!(report.blobs, 2);
+        assert_eq!(report.corrupt, std::slice::from_ref(&corrupt.digest));
+        assert_eq!(report.entries, 2);
+        assert_eq!(keys(&cache), ["intact"]);
+        assert!(cache.dependency_index.is_empty());
+        assert!(!cache.blobs().blob_path(&corrupt.digest).exists());
+        assert_eq!(keys(&open(dir.path()).await), ["intact"]);
+        assert!(cache.get_layer("intact").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn export_and_import_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        let mut source = open(&source_dir).await;
+        let (app, deps) = (layer(b"application"), layer(b"dependencies"));
+        let config =
+            br#"{"architecture":"amd64","os":"linux","history":[],"rootfs":{"type":"layers","diff_ids":[]}}"#;
+        source.store_layer("app", &app, metadata()).await.unwrap();
+        source
+            .store_dependency_layer("deps", &deps, metadata())
+            .await
+            .unwrap();
+        source.store_config("base", config).await.unwrap();
+
+        let archive = dir.path().join("cache.tar");
+        assert_eq!(source.export(&archive).await.unwrap(), 3);
+
+        // Entries already in the destination win over imported ones
+        let mut destination = open(&dir.path().join("destination")).await;
+        let local = layer(b"local application");
+        destination
+            .store_layer("app", &local, metadata())
+            .await
+            .unwrap();
+
+        let report = destination.import(&archive).await.unwrap();
+        assert_eq!((report.entries, report.blobs), (2, 3));
+        assert_eq!(keys(&destination), ["app", "base", deps.digest.as_str()]);
+        assert_eq!(destination.get_layer("app").await.unwrap().data, local.data);
+        let imported = destination.get_dependency_layer("deps").await.unwrap();
+        assert_eq!(
+            (imported.data, imported.diff_id),
+            (deps.data.clone(), deps.diff_id.clone())
+        );
+        assert!(destination.get_config("base").await.is_some());
+        assert_eq!(keys(&open(&dir.path().join("destination")).await).len(), 3);
+
+        // Importing again adds nothing
+        let report = destination.import(&archive).await.unwrap();
+        assert_eq!((report.entries, report.blobs), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn import_skips_tampered_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut source = open(&dir.path().join("source")).await;
+        let app = layer(b"application");
+        source.store_layer("app", &app, metadata()).await.unwrap();
+
+        let archive = dir.path().join("cache.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&archive).unwrap());
+        let tampered = b"tampered";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(tampered.len() as u64);
+        header.set_mode(0o644);
+        let name = format!("blobs/sha256/{}", app.digest.trim_start_matches("sha256:"));
+        builder
+            .append_data(&mut header, name, tampered.as_slice())
+            .unwrap();
+        builder
+            .append_path_with_name(dir.path().join("source/index.json"), "index.json")
+            .unwrap();
+        builder.finish().unwrap();
+
+        let mut destination = open(&dir.path().join("destination")).await;
+        let report = destination.import(&archive).await.unwrap();
+        assert_eq!((report.entries, report.blobs), (0, 0));
+        assert!(destination.get_layer("app").await.is_none());
+
+        let empty = dir.path().join("empty.tar");
+        tar::Builder::new(std::fs::File::create(&empty).unwrap())
+            .finish()
+            .unwrap();
+        let error = destination.import(&empty).await.unwrap_err().to_string();
+        assert!(error.contains("No index.json"), "{}", error);
     }
 }
@@ -0,0 +1,478 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::reference::DOCKER_HUB;
+
+/// Server address `docker login` records Docker Hub credentials under.
+const DOCKER_HUB_SERVER: &str = "https://index.docker.io/v1/";
+
+/// Username credential helpers report when the secret is an identity token.
+const IDENTITY_TOKEN_USERNAME: &str = "<token>";
+
+/// Credentials for one registry.
+#[derive(Clone)]
+pub enum Credentials {
+    Password {
+        username: String,
+        password: String,
+    },
+    /// OAuth2 refresh token, exchanged at the registry's token realm.
+    IdentityToken(String),
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Password { username, .. } => f
+                .debug_struct("Password")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Credentials::IdentityToken(_) => f.write_str("IdentityToken(..)"),
+        }
+    }
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Credentials::Password {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Looks up credentials for `registry` in `REGISTRY_AUTH_FILE`, then in
+    /// Docker's `config.json` (`$DOCKER_CONFIG` or `~/.docker`).
+    pub async fn lookup(registry: &str) -> Result<Option<Self>> {
+        for path in Self::config_files() {
+            if !path.exists() {
+                continue;
+            }
+
+            let config = DockerConfig::load(&path)?;
+            let credentials = config.credentials(registry).await.with_context(|| {
+                format!(
+                    "Failed to read credentials for {} from {}",
+                    registry,
+                    path.display()
+                )
+            })?;
+
+            if credentials.is_some() {
+                tracing::debug!("Using credentials for {} from {}", registry, path.display());
+                return Ok(credentials);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn config_files() -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = std::env::var_os("REGISTRY_AUTH_FILE")
+            .map(PathBuf::from)
+            .into_iter()
+            .collect();
+
+        let docker_dir = std::env::var_os("DOCKER_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")));
+        files.extend(docker_dir.map(|dir| dir.join("config.json")));
+
+        files
+    }
+}
+
+/// The parts of Docker's `config.json` (and the compatible
+/// `REGISTRY_AUTH_FILE`) that hold registry credentials.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(default)]
+    creds_store: Option<String>,
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AuthEntry {
+    /// base64 of `username:password`
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    identitytoken: Option<String>,
+}
+
+/// Output of `docker-credential-<helper> get`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+impl DockerConfig {
+    fn load(path: &Path) -> Result<Self> {
+        let content =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Resolves credentials as Docker does: a per-registry `credHelpers`
+    /// entry wins, then the default `credsStore`, then inline `auths`.
+    async fn credentials(&self, registry: &str) -> Result<Option<Credentials>> {
+        if let Some((_, helper)) = Self::entry(&self.cred_helpers, registry) {
+            return Self::run_helper(helper, registry).await;
+        }
+
+        if let Some(store) = &self.creds_store {
+            if let Some(credentials) = Self::run_helper(store, registry).await? {
+                return Ok(Some(credentials));
+            }
+        }
+
+        Self::entry(&self.auths, registry).map_or(Ok(None), |(key, entry)| {
+            entry
+                .credentials()
+                .with_context(|| format!("Invalid auths entry for {}", key))
+        })
+    }
+
+    /// The entry of `map` for `registry`. Several keys may name the same
+    /// registry (`docker.io` and `https://index.docker.io/v1/` after a few
+    /// `docker login`s), so the exact key wins, then the server address
+    /// `docker login` uses for Docker Hub, then the first alias by name.
+    fn entry<'a, V>(map: &'a HashMap<String, V>, registry: &str) -> Option<(&'a String, &'a V)> {
+        let server = if registry == DOCKER_HUB {
+            DOCKER_HUB_SERVER
+        } else {
+            registry
+        };
+
+        map.get_key_value(registry)
+            .or_else(|| map.get_key_value(server))
+            .or_else(|| {
+                map.iter()
+                    .filter(|(key, _)| Self::matches(key, registry))
+                    .min_by_key(|(key, _)| *key)
+            })
+    }
+
+    /// Whether a config key (a bare host or a URL such as
+    /// `https://index.docker.io/v1/`) refers to `registry`.
+    fn matches(key: &str, registry: &str) -> bool {
+        let host = key
+            .strip_prefix("https://")
+            .or_else(|| key.strip_prefix("http://"))
+            .unwrap_or(key);
+        let host = host.split('/').next().unwrap_or(host);
+
+        host == registry
+            || (registry == DOCKER_HUB
+                && matches!(
+                    host,
+                    "index.docker.io" | "docker.io" | "registry-1.docker.io"
+                ))
+    }
+
+    async fn run_helper(helper: &str, registry: &str) -> Result<Option<Credentials>> {
+        let program = format!("docker-credential-{}", helper);
+        let server = if registry == DOCKER_HUB {
+            DOCKER_HUB_SERVER
+        } else {
+            registry
+        };
+
+        let spawned = Command::new(&program)
+            .arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            // A config copied from a desktop often names a helper this host
+            // doesn't have; carry on as Docker does, without credentials.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!(
+                    "Credential helper {} is not installed; continuing without credentials for {}",
+                    program,
+                    registry
+                );
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to run credential helper {}", program))
+            }
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(server.as_bytes()).await?;
+        }
+        let output = child.wait_with_output().await?;
+
+        if !output.status.success() {
+            let message = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            if message.contains("credentials not found") {
+                return Ok(None);
+            }
+            return Err(anyhow!("{} failed: {}", program, message.trim()));
+        }
+
+        let credentials: HelperCredentials = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("Failed to parse output of {}", program))?;
+
+        Ok(Some(if credentials.username == IDENTITY_TOKEN_USERNAME {
+            Credentials::IdentityToken(credentials.secret)
+        } else {
+            Credentials::new(credentials.username, credentials.secret)
+        }))
+    }
+}
+
+impl AuthEntry {
+    fn credentials(&self) -> Result<Option<Credentials>> {
+        let non_empty = |value: &Option<String>| value.clone().filter(|v| !v.is_empty());
+
+        if let Some(token) = non_empty(&self.identitytoken) {
+            return Ok(Some(Credentials::IdentityToken(token)));
+        }
+
+        if let (Some(username), Some(password)) =
+            (non_empty(&self.username), non_empty(&self.password))
+        {
+            return Ok(Some(Credentials::new(username, password)));
+        }
+
+        let Some(auth) = non_empty(&self.auth) else {
+            return Ok(None);
+        };
+        let decoded = STANDARD
+            .decode(auth.trim())
+            .context("auth is not valid base64")?;
+        let decoded = String::from_utf8(decoded).context("auth is not valid UTF-8")?;
+        let (username, password) = decoded
+            .split_once(':')
+            .ok_or_else(|| anyhow!("auth is not of the form username:password"))?;
+
+        Ok(Some(Credentials::new(username, password)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config key, registry, then whether the key refers to the registry
+    type MatchCase<'a> = (&'a str, &'a str, bool);
+
+    fn encode(value: &str) -> String {
+        STANDARD.encode(value)
+    }
+
+    fn describe(credentials: Option<Credentials>) -> String {
+        match credentials {
+            Some(Credentials::Password { username, password }) => {
+                format!("{}:{}", username, password)
+            }
+            Some(Credentials::IdentityToken(token)) => format!("token {}", token),
+            None => "none".to_string(),
+        }
+    }
+
+    #[test]
+    fn matches_config_keys_to_registries() {
+        let cases: &[MatchCase] = &[
+            ("ghcr.io", "ghcr.io", true),
+            ("https://ghcr.io", "ghcr.io", true),
+            ("https://ghcr.io/v2/", "ghcr.io", true),
+            ("http://localhost:5000", "localhost:5000", true),
+            ("localhost:5000", "localhost", false),
+            ("ghcr.io", "quay.io", false),
+            ("ghcr.io.evil.example", "ghcr.io", false),
+            // Docker Hub's many names
+            (DOCKER_HUB_SERVER, DOCKER_HUB, true),
+            ("index.docker.io", DOCKER_HUB, true),
+            ("docker.io", DOCKER_HUB, true),
+            ("https://registry-1.docker.io/v2/", DOCKER_HUB, true),
+            (DOCKER_HUB_SERVER, "ghcr.io", false),
+            ("docker.io", "quay.io", false),
+        ];
+
+        for (key, registry, expected) in cases {
+            assert_eq!(
+                DockerConfig::matches(key, registry),
+                *expected,
+                "{} for {}",
+                key,
+                registry
+            );
+        }
+    }
+
+    #[test]
+    fn reads_auths_entries() {
+        let cases: &[(String, &str)] = &[
+            (
+                format!(r#"{{"auth": "{}"}}"#, encode("user:pa:ss")),
+                "user:pa:ss",
+            ),
+            (
+                format!(r#"{{"auth": " {}\n"}}"#, encode("user:secret")),
+                "user:secret",
+            ),
+            (
+                r#"{"username": "user", "password": "secret"}"#.to_string(),
+                "user:secret",
+            ),
+            // Explicit fields win over `auth`, an identity token over both
+            (
+                format!(
+                    r#"{{"auth": "{}", "username": "user", "password": "secret"}}"#,
+                    encode("other:other")
+                ),
+                "user:secret",
+            ),
+            (
+                format!(
+                    r#"{{"auth": "{}", "identitytoken": "refresh"}}"#,
+                    encode("user:secret")
+                ),
+                "token refresh",
+            ),
+            (
+                r#"{"username": "user", "password": "", "identitytoken": ""}"#.to_string(),
+                "none",
+            ),
+            ("{}".to_string(), "none"),
+        ];
+
+        for (entry, expected) in cases {
+            let entry: AuthEntry = serde_json::from_str(entry).unwrap();
+            assert_eq!(
+                describe(entry.credentials().unwrap()),
+                *expected,
+                "{:?}",
+                entry
+            );
+        }
+
+        for invalid in [
+            r#"{"auth": "not base64!"}"#.to_string(),
+            format!(r#"{{"auth": "{}"}}"#, encode("no-colon")),
+        ] {
+            let entry: AuthEntry = serde_json::from_str(&invalid).unwrap();
+            assert!(entry.credentials().is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_docker_hub_credentials() {
+        let config: DockerConfig = serde_json::from_str(&format!(
+            r#"{{"auths": {{
+                "{}": {{"auth": "{}"}},
+                "ghcr.io": {{"identitytoken": "refresh"}}
+            }}}}"#,
+            DOCKER_HUB_SERVER,
+            encode("hub:secret")
+        ))
+        .unwrap();
+
+        assert_eq!(
+            describe(config.credentials(DOCKER_HUB).await.unwrap()),
+            "hub:secret"
+        );
+        assert_eq!(
+            describe(config.credentials("ghcr.io").await.unwrap()),
+            "token refresh"
+        );
+        assert_eq!(
+            describe(config.credentials("quay.io").await.unwrap()),
+            "none"
+        );
+    }
+
+    #[tokio::test]
+    async fn prefers_exact_keys_over_aliases() {
+        let config: DockerConfig = serde_json::from_str(&format!(
+            r#"{{"auths": {{
+                "docker.io": {{"auth": "{}"}},
+                "{}": {{"auth": "{}"}},
+                "https://ghcr.io": {{"auth": "{}"}},
+                "ghcr.io": {{"auth": "{}"}},
+                "https://quay.io/v2/": {{"auth": "{}"}},
+                "http://quay.io": {{"auth": "{}"}}
+            }}}}"#,
+            encode("alias:secret"),
+            DOCKER_HUB_SERVER,
+            encode("hub:secret"),
+            encode("url:secret"),
+            encode("exact:secret"),
+            encode("https:secret"),
+            encode("http:secret"),
+        ))
+        .unwrap();
+
+        for _ in 0..8 {
+            assert_eq!(
+                describe(config.credentials(DOCKER_HUB).await.unwrap()),
+                "hub:secret"
+            );
+            assert_eq!(
+                describe(config.credentials("ghcr.io").await.unwrap()),
+                "exact:secret"
+            );
+            // Without an exact key, aliases are tried in name order
+            assert_eq!(
+                describe(config.credentials("quay.io").await.unwrap()),
+                "http:secret"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_credential_helpers_mean_no_credentials() {
+        let config: DockerConfig = serde_json::from_str(&format!(
+            r#"{{
+                "credsStore": "spacejar-test-missing",
+                "credHelpers": {{"registry.example": "spacejar-test-missing"}},
+                "auths": {{"ghcr.io": {{"auth": "{}"}}}}
+            }}"#,
+            encode("user:secret")
+        ))
+        .unwrap();
+
+        // A missing credsStore falls through to the inline auths
+        assert_eq!(
+            describe(config.credentials("ghcr.io").await.unwrap()),
+            "user:secret"
+        );
+        assert_eq!(
+            describe(config.credentials("quay.io").await.unwrap()),
+            "none"
+        );
+        // A registry's own helper decides alone, even when it is missing
+        assert_eq!(
+            describe(config.credentials("registry.example").await.unwrap()),
+            "none"
+        );
+    }
+}
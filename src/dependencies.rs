@@ -0,0 +1,657 @@
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::requirements::{normalize_name, parse_requirements_file, Requirement, Specifier};
+
+/// Where a project's dependencies were declared.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DependencySource {
+    /// A pip requirements file
+    RequirementsFile(PathBuf),
+    /// PEP 621 `[project.dependencies]`
+    Project,
+    /// `[tool.poetry.dependencies]`
+    Poetry,
+    /// No dependencies are declared anywhere
+    None,
+}
+
+impl fmt::Display for DependencySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencySource::RequirementsFile(path) => write!(
+                f,
+                "{}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+            DependencySource::Project => write!(f, "[project.dependencies]"),
+            DependencySource::Poetry => write!(f, "[tool.poetry.dependencies]"),
+            DependencySource::None => write!(f, "no dependency declarations"),
+        }
+    }
+}
+
+/// The dependencies to install for a project, with the selected extras
+/// already folded in.
+#[derive(Debug, Clone)]
+pub struct ProjectDependencies {
+    pub source: DependencySource,
+    pub requirements: Vec<Requirement>,
+}
+
+impl ProjectDependencies {
+    /// Reads `requirements.txt` if present, otherwise `[project.dependencies]`,
+    /// otherwise `[tool.poetry.dependencies]` from `pyproject.toml`. `extras`
+    /// selects optional dependency groups of the pyproject sources. A
+    /// `requirements.txt` next to pyproject dependencies wins, with a warning.
+    pub fn load(project_path: &Path, extras: &[String]) -> Result<Self> {
+        let requirements_txt = project_path.join("requirements.txt");
+        if requirements_txt.exists() {
+            if !extras.is_empty() {
+                return Err(anyhow!(
+                    "Extras {:?} are selected but dependencies come from requirements.txt; \
+                     declare them in pyproject.toml instead",
+                    extras
+                ));
+            }
+            if let Ok(Some(shadowed)) = Self::from_pyproject(project_path, &[]) {
+                tracing::warn!(
+                    "Both requirements.txt and {} declare dependencies; \
+                     installing from requirements.txt and ignoring {}",
+                    shadowed.source,
+                    shadowed.source
+                );
+            }
+            return Ok(Self {
+                requirements: parse_requirements_file(&requirements_txt)?,
+                source: DependencySource::RequirementsFile(requirements_txt),
+            });
+        }
+
+        if let Some(dependencies) = Self::from_pyproject(project_path, extras)? {
+            return Ok(dependencies);
+        }
+
+        if !extras.is_empty() {
+            return Err(anyhow!(
+                "Extras {:?} are selected but the project declares no dependencies",
+                extras
+            ));
+        }
+
+        Ok(Self {
+            source: DependencySource::None,
+            requirements: Vec::new(),
+        })
+    }
+
+    /// The `[project.dependencies]` or `[tool.poetry.dependencies]` of the
+    /// project's `pyproject.toml`, if it declares either.
+    pub fn from_pyproject(project_path: &Path, extras: &[String]) -> Result<Option<Self>> {
+        let pyproject_path = project_path.join("pyproject.toml");
+        if !pyproject_path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&pyproject_path)?;
+        let pyproject: toml::Value = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", pyproject_path.display()))?;
+
+        if let Some(project) = pyproject.get("project") {
+            if project.get("dependencies").is_some()
+                || project.get("optional-dependencies").is_some()
+            {
+                return Ok(Some(Self {
+                    requirements: Self::from_project_table(project, extras)?,
+                    source: DependencySource::Project,
+                }));
+            }
+        }
+
+        if let Some(poetry) = pyproject.get("tool").and_then(|t| t.get("poetry")) {
+            if poetry.get("dependencies").is_some() {
+                return Ok(Some(Self {
+                    requirements: Self::from_poetry_table(poetry, extras, project_path)?,
+                    source: DependencySource::Poetry,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// A requirements file pip can install from. The project's own
+    /// requirements file is used as-is so pip still sees options such as
+    /// `--hash` and `--index-url`; other sources are written to `dir`.
+    pub fn requirements_file(&self, dir: &Path) -> Result<PathBuf> {
+        if let DependencySource::RequirementsFile(path) = &self.source {
+            return Ok(path.clone());
+        }
+
+        let path = dir.join("requirements.txt");
+        let content: String = self
+            .requirements
+            .iter()
+            .map(|requirement| format!("{}\n", requirement))
+            .collect();
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    fn from_project_table(project: &toml::Value, extras: &[String]) -> Result<Vec<Requirement>> {
+        let parse_list = |value: Option<&toml::Value>, what: &str| -> Result<Vec<Requirement>> {
+            let Some(value) = value else {
+                return Ok(Vec::new());
+            };
+            value
+                .as_array()
+                .ok_or_else(|| anyhow!("{} must be an array of strings", what))?
+                .iter()
+                .map(|entry| {
+                    let entry = entry
+                        .as_str()
+                        .ok_or_else(|| anyhow!("{} must be an array of strings", what))?;
+                    Requirement::parse(entry).with_context(|| format!("Invalid entry in {}", what))
+                })
+                .collect()
+        };
+
+        let project_name = project
+            .get("name")
+            .and_then(|n| n.as_str())
+            .map(normalize_name);
+        let optional = project.get("optional-dependencies");
+
+        let mut requirements = parse_list(project.get("dependencies"), "[project.dependencies]")?;
+
+        // Extras may refer to other extras of the project itself, e.g.
+        // `all = ["myproject[postgres,redis]"]`.
+        let mut pending: Vec<String> = extras.iter().map(|e| normalize_name(e)).collect();
+        let mut expanded = Vec::new();
+        while let Some(extra) = pending.pop() {
+            if expanded.contains(&extra) {
+                continue;
+            }
+            let group = optional
+                .and_then(|o| o.as_table())
+                .and_then(|table| table.iter().find(|(name, _)| normalize_name(name) == extra))
+                .map(|(_, group)| group)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Unknown extra '{}'; [project.optional-dependencies] declares: {}",
+                        extra,
+                        Self::available_extras(optional)
+                    )
+                })?;
+
+            for requirement in parse_list(
+                Some(group),
+                &format!("[project.optional-dependencies] {}", extra),
+            )? {
+                if project_name.as_deref() == Some(normalize_name(&requirement.name).as_str()) {
+                    pending.extend(requirement.extras.iter().map(|e| normalize_name(e)));
+                } else {
+                    requirements.push(requirement);
+                }
+            }
+            expanded.push(extra);
+        }
+
+        Ok(requirements)
+    }
+
+    fn from_poetry_table(
+        poetry: &toml::Value,
+        extras: &[String],
+        project_path: &Path,
+    ) -> Result<Vec<Requirement>> {
+        let dependencies = poetry
+            .get("dependencies")
+            .and_then(|d| d.as_table())
+            .ok_or_else(|| anyhow!("[tool.poetry.dependencies] must be a table"))?;
+        let poetry_extras = poetry.get("extras");
+
+        // Optional dependencies are only installed when an extra that lists
+        // them is selected.
+        let mut selected_optional = Vec::new();
+        for extra in extras {
+            let members = poetry_extras
+                .and_then(|e| e.as_table())
+                .and_then(|table| {
+                    table
+                        .iter()
+                        .find(|(name, _)| normalize_name(name) == normalize_name(extra))
+                })
+                .and_then(|(_, members)| members.as_array())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Unknown extra '{}'; [tool.poetry.extras] declares: {}",
+                        extra,
+                        Self::available_extras(poetry_extras)
+                    )
+                })?;
+            selected_optional.extend(
+                members
+                    .iter()
+                    .filter_map(|m| m.as_str())
+                    .map(normalize_name),
+            );
+        }
+
+        let mut requirements = Vec::new();
+        for (name, spec) in dependencies {
+            if name == "python" {
+                continue;
+            }
+
+            let constraints = match spec {
+                toml::Value::Array(constraints) => constraints.iter().collect(),
+                spec => vec![spec],
+            };
+            for constraint in constraints {
+                let optional = constraint
+                    .get("optional")
+                    .and_then(|o| o.as_bool())
+                    .unwrap_or(false);
+                if optional && !selected_optional.contains(&normalize_name(name)) {
+                    continue;
+                }
+                requirements.push(
+                    poetry_requirement(name, constraint, project_path).with_context(|| {
+                        format!(
+                            "Invalid dependency '{}' in [tool.poetry.dependencies]",
+                            name
+                        )
+                    })?,
+                );
+            }
+        }
+
+        Ok(requirements)
+    }
+
+    fn available_extras(table: Option<&toml::Value>) -> String {
+        let names: Vec<&str> = table
+            .and_then(|t| t.as_table())
+            .map(|t| t.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+}
+
+/// Converts a Poetry dependency (a version constraint string or a table
+/// with `version`, `extras`, `markers`, `python`, `platform`, `git`, `url`
+/// or `path`, relative to the project) to a PEP 508 requirement.
+pub fn poetry_requirement(
+    name: &str,
+    spec: &toml::Value,
+    project_path: &Path,
+) -> Result<Requirement> {
+    let mut requirement = Requirement {
+        name: name.to_string(),
+        extras: Vec::new(),
+        specifiers: Vec::new(),
+        marker: None,
+        url: None,
+    };
+
+    let table = match spec {
+        toml::Value::String(constraint) => {
+            requirement.specifiers = poetry_specifiers(constraint)?;
+            return Ok(requirement);
+        }
+        toml::Value::Table(table) => table,
+        _ => return Err(anyhow!("expected a version string or a table")),
+    };
+
+    if let Some(version) = table.get("version").and_then(|v| v.as_str()) {
+        requirement.specifiers = poetry_specifiers(version)?;
+    }
+
+    if let Some(extras) = table.get("extras").and_then(|e| e.as_array()) {
+        requirement.extras = extras
+            .iter()
+            .filter_map(|e| e.as_str())
+            .map(str::to_string)
+            .collect();
+    }
+
+    if let Some(git) = table.get("git").and_then(|g| g.as_str()) {
+        let reference = ["rev", "tag", "branch"]
+            .iter()
+            .find_map(|key| table.get(*key).and_then(|r| r.as_str()));
+        requirement.url = Some(match reference {
+            Some(reference) => format!("git+{}@{}", git, reference),
+            None => format!("git+{}", git),
+        });
+    } else if let Some(url) = table.get("url").and_then(|u| u.as_str()) {
+        requirement.url = Some(url.to_string());
+    } else if let Some(path) = table.get("path").and_then(|p| p.as_str()) {
+        let path = project_path.join(path);
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+        requirement.url = Some(format!("file://{}", path.display()));
+    }
+
+    let mut markers = Vec::new();
+    if let Some(marker) = table.get("markers").and_then(|m| m.as_str()) {
+        markers.push(format!("({})", marker));
+    }
+    if let Some(python) = table.get("python").and_then(|p| p.as_str()) {
+        markers.extend(python_markers(python)?);
+    }
+    if let Some(platform) = table.get("platform").and_then(|p| p.as_str()) {
+        markers.push(format!("sys_platform == \"{}\"", platform));
+    }
+    if !markers.is_empty() {
+        requirement.marker = Some(markers.join(" and "));
+    }
+
+    Ok(requirement)
+}
+
+/// Translates a Poetry version constraint (`^1.2`, `~1.2.3`, `1.2.*`,
+/// `>=1.2,<2.0`) into PEP 440 specifiers.
+pub fn poetry_specifiers(constraint: &str) -> Result<Vec<Specifier>> {
+    let constraint = constraint.trim();
+    if constraint.contains("||") {
+        return Err(anyhow!(
+            "'||' constraints are not supported: {}",
+            constraint
+        ));
+    }
+
+    let mut clauses = Vec::new();
+    for part in constraint.split(',') {
+        // `>= 1.2 < 2.0` separates clauses with whitespace and may put a
+        // space between an operator and its version.
+        let mut pending_operator: Option<&str> = None;
+        for token in part.split_whitespace() {
+            match pending_operator.take() {
+                Some(operator) => clauses.push(format!("{}{}", operator, token)),
+                None if token
+                    .trim_start_matches(['<', '>', '=', '!', '~', '^'])
+                    .is_empty() =>
+                {
+                    pending_operator = Some(token);
+                }
+                None => clauses.push(token.to_string()),
+            }
+        }
+        if let Some(operator) = pending_operator {
+            return Err(anyhow!("Missing version after '{}'", operator));
+        }
+    }
+
+    let mut specifiers = Vec::new();
+    for clause in clauses {
+        if clause == "*" {
+            continue;
+        }
+
+        if let Some(version) = clause.strip_prefix('^') {
+            let release = release_parts(version)?;
+            let bump = release
+                .iter()
+                .position(|part| *part != 0)
+                .unwrap_or(release.len() - 1);
+            specifiers.push(specifier(">=", version));
+            specifiers.push(specifier("<", &upper_bound(&release, bump)));
+        } else if let Some(version) = clause.strip_prefix('~').filter(|v| !v.starts_with('=')) {
+            let release = release_parts(version)?;
+            let bump = if release.len() > 1 { 1 } else { 0 };
+            specifiers.push(specifier(">=", version));
+            specifiers.push(specifier("<", &upper_bound(&release, bump)));
+        } else if clause.starts_with(['<', '>', '=', '!', '~']) {
+            specifiers.push(Specifier::parse(&clause)?);
+        } else {
+            specifiers.push(specifier("==", &clause));
+        }
+    }
+
+    Ok(specifiers)
+}
+
+/// Marker clauses for a Poetry `python` constraint. `python_version` only
+/// holds `major.minor`, so bounds with a patch component compare against
+/// `python_full_version`, as Poetry does.
+fn python_markers(constraint: &str) -> Result<Vec<String>> {
+    Ok(poetry_specifiers(constraint)?
+        .into_iter()
+        .map(|specifier| {
+            let release = specifier.version.trim_end_matches(".*");
+            let variable = if release.split('.').count() > 2 {
+                "python_full_version"
+            } else {
+                "python_version"
+            };
+            format!(
+                "{} {} \"{}\"",
+                variable, specifier.operator, specifier.version
+            )
+        })
+        .collect())
+}
+
+fn specifier(operator: &str, version: &str) -> Specifier {
+    Specifier {
+        operator: operator.to_string(),
+        version: version.to_string(),
+    }
+}
+
+fn release_parts(version: &str) -> Result<Vec<u64>> {
+    let release: Vec<u64> = version
+        .split('.')
+        .map_while(|part| part.parse().ok())
+        .collect();
+    if release.is_empty() {
+        return Err(anyhow!("Invalid version '{}'", version));
+    }
+    Ok(release)
+}
+
+/// `release` truncated after `index`, with that component incremented.
+fn upper_bound(release: &[u64], index: usize) -> String {
+    let mut upper = release[..=index].to_vec();
+    upper[index] += 1;
+    upper
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Poetry constraint, then the PEP 440 specifiers it becomes
+    type SpecifierCase<'a> = (&'a str, &'a str);
+
+    /// Dependency spec as TOML, then the requirement it becomes
+    type RequirementCase<'a> = (&'a str, &'a str);
+
+    const PROJECT: &str = "[project]\nname = \"app\"\ndependencies = [\"requests>=2.31\"]\n";
+    const POETRY: &str = concat!(
+        "[tool.poetry.dependencies]\n",
+        "python = \"^3.10\"\n",
+        "flask = \"^3.0\"\n",
+        "redis = { version = \"^5.0\", optional = true }\n",
+        "\n",
+        "[tool.poetry.extras]\n",
+        "cache = [\"redis\"]\n",
+    );
+
+    fn specifiers(constraint: &str) -> String {
+        poetry_specifiers(constraint)
+            .unwrap()
+            .iter()
+            .map(|s| format!("{}{}", s.operator, s.version))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn project(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &tempfile::TempDir, extras: &[&str]) -> ProjectDependencies {
+        let extras: Vec<String> = extras.iter().map(|e| e.to_string()).collect();
+        ProjectDependencies::load(dir.path(), &extras).unwrap()
+    }
+
+    fn names(dependencies: &ProjectDependencies) -> Vec<String> {
+        dependencies
+            .requirements
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn translates_poetry_constraints() {
+        let cases: &[SpecifierCase] = &[
+            ("^1.2.3", ">=1.2.3,<2"),
+            ("^1.2", ">=1.2,<2"),
+            ("^1", ">=1,<2"),
+            ("^0.2.3", ">=0.2.3,<0.3"),
+            ("^0.0.3", ">=0.0.3,<0.0.4"),
+            ("^0", ">=0,<1"),
+            ("~1.2.3", ">=1.2.3,<1.3"),
+            ("~1.2", ">=1.2,<1.3"),
+            ("~1", ">=1,<2"),
+            // `~=` is PEP 440's compatible release, not Poetry's tilde
+            ("~=1.2", "~=1.2"),
+            ("*", ""),
+            ("", ""),
+            // Bare versions and wildcards are exact matches
+            ("1.2.3", "==1.2.3"),
+            ("1.2.*", "==1.2.*"),
+            (">=1.2,<2.0", ">=1.2,<2.0"),
+            (">=1.2, <2.0", ">=1.2,<2.0"),
+            (">= 1.2 < 2.0", ">=1.2,<2.0"),
+            (">=1.2 !=1.5", ">=1.2,!=1.5"),
+            ("==2.0", "==2.0"),
+            ("^1.2,!=1.4.1", ">=1.2,<2,!=1.4.1"),
+        ];
+
+        for (constraint, expected) in cases {
+            assert_eq!(specifiers(constraint), *expected, "{}", constraint);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_poetry_constraints() {
+        for constraint in ["^1.0 || ^2.0", ">=", ">=1.0 <", "^abc", "~x.y"] {
+            assert!(
+                poetry_specifiers(constraint).is_err(),
+                "{} should be rejected",
+                constraint
+            );
+        }
+    }
+
+    #[test]
+    fn converts_poetry_dependencies() {
+        let cases: &[RequirementCase] = &[
+            (r#""^2.31""#, "demo>=2.31,<3"),
+            (
+                r#"{ version = "^2.31", extras = ["socks"] }"#,
+                "demo[socks]>=2.31,<3",
+            ),
+            (
+                r#"{ version = "^1.0", python = "^3.8" }"#,
+                r#"demo>=1.0,<2; python_version >= "3.8" and python_version < "4""#,
+            ),
+            // A patch-level bound needs the full interpreter version
+            (
+                r#"{ version = "^1.0", python = "~3.12.1" }"#,
+                r#"demo>=1.0,<2; python_full_version >= "3.12.1" and python_version < "3.13""#,
+            ),
+            (
+                r#"{ version = "*", python = ">=3.8.1,<3.13" }"#,
+                r#"demo; python_full_version >= "3.8.1" and python_version < "3.13""#,
+            ),
+            (
+                r#"{ version = "*", python = "3.11.*" }"#,
+                r#"demo; python_version == "3.11.*""#,
+            ),
+            (
+                r#"{ version = "1.0", markers = "implementation_name == 'cpython' or os_name == 'nt'", platform = "linux" }"#,
+                r#"demo==1.0; (implementation_name == 'cpython' or os_name == 'nt') and sys_platform == "linux""#,
+            ),
+            (
+                r#"{ git = "https://github.com/org/demo.git", tag = "v1.0" }"#,
+                "demo @ git+https://github.com/org/demo.git@v1.0",
+            ),
+            (
+                r#"{ url = "https://example.com/demo-1.0.tar.gz" }"#,
+                "demo @ https://example.com/demo-1.0.tar.gz",
+            ),
+        ];
+
+        for (spec, expected) in cases {
+            let spec: toml::Value = toml::from_str(&format!("spec = {}", spec)).unwrap();
+            let requirement = poetry_requirement("demo", &spec["spec"], Path::new(".")).unwrap();
+            assert_eq!(requirement.to_string(), *expected, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn prefers_requirements_txt_then_project_then_poetry() {
+        let dir = project(&[
+            ("requirements.txt", "django==5.0\n"),
+            ("pyproject.toml", &format!("{}{}", PROJECT, POETRY)),
+        ]);
+        let dependencies = load(&dir, &[]);
+        assert_eq!(
+            dependencies.source,
+            DependencySource::RequirementsFile(dir.path().join("requirements.txt"))
+        );
+        assert_eq!(names(&dependencies), ["django==5.0"]);
+
+        let dir = project(&[("pyproject.toml", &format!("{}{}", PROJECT, POETRY))]);
+        let dependencies = load(&dir, &[]);
+        assert_eq!(dependencies.source, DependencySource::Project);
+        assert_eq!(names(&dependencies), ["requests>=2.31"]);
+
+        let dir = project(&[(
+            "pyproject.toml",
+            &format!("[project]\nname = \"app\"\n\n{}", POETRY),
+        )]);
+        let dependencies = load(&dir, &[]);
+        assert_eq!(dependencies.source, DependencySource::Poetry);
+        assert_eq!(names(&dependencies), ["flask>=3.0,<4"]);
+        assert_eq!(
+            names(&load(&dir, &["cache"])),
+            ["flask>=3.0,<4", "redis>=5.0,<6"]
+        );
+
+        let dir = project(&[("pyproject.toml", "[project]\nname = \"app\"\n")]);
+        let dependencies = load(&dir, &[]);
+        assert_eq!(dependencies.source, DependencySource::None);
+        assert!(dependencies.requirements.is_empty());
+    }
+
+    #[test]
+    fn rejects_extras_that_cannot_apply() {
+        let dir = project(&[("requirements.txt", "django==5.0\n")]);
+        assert!(ProjectDependencies::load(dir.path(), &["cache".to_string()]).is_err());
+
+        let dir = project(&[("pyproject.toml", PROJECT)]);
+        let error = ProjectDependencies::load(dir.path(), &["cache".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Unknown extra 'cache'"), "{}", error);
+    }
+}
@@ -1,55 +1,212 @@
 use anyhow::Result;
-use glob::glob;
-use std::future::Future;
-use std::path::Path;
-use std::pin::Pin;
-
-pub fn copy_dir_all(
-    src: impl AsRef<Path> + 'static,
-    dst: impl AsRef<Path> + 'static,
-) -> Pin<Box<dyn Future<Output = Result<()>>>> {
-    Box::pin(async move {
-        let src = src.as_ref();
-        let dst = dst.as_ref();
-
-        if !dst.exists() {
-            tokio::fs::create_dir_all(dst).await?;
-        }
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
-        let mut entries = tokio::fs::read_dir(src).await?;
+/// Copies the tree at `src` into `dst` as the layer archiver should see it:
+/// symlinks are recreated rather than followed, and files hardlinked to
+/// each other in `src` stay hardlinked in `dst`. Entries for which
+/// `exclude` returns true, given their path below `src`, are skipped along
+/// with everything beneath them.
+pub async fn copy_dir_all<F>(src: impl AsRef<Path>, dst: impl AsRef<Path>, exclude: F) -> Result<()>
+where
+    F: Fn(&Path) -> bool + Send + 'static,
+{
+    let src = src.as_ref().to_path_buf();
+    let dst = dst.as_ref().to_path_buf();
 
-        while let Some(entry) = entries.next_entry().await? {
-            let ty = entry.file_type().await?;
-            let src_path = entry.path();
-            let dst_path = dst.join(entry.file_name());
+    tokio::task::spawn_blocking(move || copy_dir_blocking(&src, &dst, exclude)).await?
+}
 
-            if ty.is_dir() {
-                copy_dir_all(src_path.clone(), dst_path.clone()).await?;
-            } else {
-                tokio::fs::copy(&src_path, &dst_path).await?;
-            }
-        }
+fn copy_dir_blocking(src: &Path, dst: &Path, exclude: impl Fn(&Path) -> bool) -> Result<()> {
+    // First copy of each multiply-linked inode, by (device, inode)
+    let mut copied: HashMap<(u64, u64), PathBuf> = HashMap::new();
 
-        Ok(())
-    })
-}
+    let entries = walkdir::WalkDir::new(src)
+        .into_iter()
+        .filter_entry(|entry| match entry.path().strip_prefix(src) {
+            Ok(relative) => relative.as_os_str().is_empty() || !exclude(relative),
+            Err(_) => true,
+        });
+    for entry in entries {
+        let entry = entry?;
+        let target = dst.join(entry.path().strip_prefix(src)?);
+        let file_type = entry.file_type();
 
-pub async fn remove_matching_files(dir: impl AsRef<Path>, pattern: &str) -> Result<()> {
-    let dir = dir.as_ref();
-    let pattern = format!("{}/**/{}", dir.display(), pattern);
-
-    for entry in glob(&pattern)? {
-        match entry {
-            Ok(path) => {
-                if path.is_dir() {
-                    tokio::fs::remove_dir_all(&path).await?;
-                } else {
-                    tokio::fs::remove_file(&path).await?;
+        if file_type.is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else {
+            let metadata = entry.metadata()?;
+            if metadata.nlink() > 1 {
+                let inode = (metadata.dev(), metadata.ino());
+                if let Some(first) = copied.get(&inode) {
+                    std::fs::hard_link(first, &target)?;
+                    continue;
                 }
+                copied.insert(inode, target.clone());
             }
-            Err(e) => tracing::warn!("Failed to read glob entry: {}", e),
+            std::fs::copy(entry.path(), &target)?;
         }
     }
 
     Ok(())
 }
+
+/// Writes `data` to a temporary file beside `path` and renames it into
+/// place, so readers see either the old file or the complete new one.
+pub async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let path = path.to_path_buf();
+    let data = data.to_vec();
+
+    tokio::task::spawn_blocking(move || write_atomic_blocking(&path, &data)).await?
+}
+
+/// `write_atomic` for callers already on a blocking thread.
+pub fn write_atomic_blocking(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("No parent directory: {}", path.display()))?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(data)?;
+    file.as_file()
+        .set_permissions(std::fs::Permissions::from_mode(0o644))?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    Ok(())
+}
+
+/// Hashes a directory tree's relative paths, entry types, executable bits,
+/// file contents, symlink targets and which files are hardlinks of each
+/// other in a stable order, so identical trees hash the same wherever they
+/// live and trees `Layer::from_dir` archives differently hash differently.
+pub fn hash_dir(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    // First path of each multiply-linked inode, as the archiver links to it
+    let mut linked: HashMap<(u64, u64), PathBuf> = HashMap::new();
+
+    for entry in walkdir::WalkDir::new(path)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+    {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(path)?;
+        hasher.update(relative.as_os_str().as_bytes());
+        hasher.update([0]);
+
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            hasher.update(b"l");
+            hasher.update(std::fs::read_link(entry.path())?.as_os_str().as_bytes());
+        } else if file_type.is_dir() {
+            hasher.update(b"d");
+        } else if let Some(first) = hardlink_of(&mut linked, &entry.metadata()?, relative) {
+            hasher.update(b"h");
+            hasher.update(first.as_os_str().as_bytes());
+        } else {
+            let executable = entry.metadata()?.permissions().mode() & 0o111 != 0;
+            let contents = std::fs::read(entry.path())?;
+            hasher.update(if executable { b"x" } else { b"f" });
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+        hasher.update([0]);
+    }
+
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// The path `metadata`'s inode was first seen at, when it has one; records
+/// `path` as that first path otherwise.
+fn hardlink_of(
+    linked: &mut HashMap<(u64, u64), PathBuf>,
+    metadata: &std::fs::Metadata,
+    path: &Path,
+) -> Option<PathBuf> {
+    if metadata.nlink() < 2 {
+        return None;
+    }
+    let inode = (metadata.dev(), metadata.ino());
+    match linked.get(&inode) {
+        Some(first) => Some(first.clone()),
+        None => {
+            linked.insert(inode, path.to_path_buf());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn copies_links_as_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        std::fs::create_dir_all(src.join("pkg")).unwrap();
+        std::fs::write(src.join("pkg/data.txt"), "data").unwrap();
+        std::fs::hard_link(src.join("pkg/data.txt"), src.join("linked.txt")).unwrap();
+        std::os::unix::fs::symlink("pkg", src.join("alias")).unwrap();
+        std::os::unix::fs::symlink("/etc/hostname", src.join("outside")).unwrap();
+
+        copy_dir_all(src.clone(), dst.clone(), |_| false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_link(dst.join("alias")).unwrap(),
+            Path::new("pkg")
+        );
+        assert_eq!(
+            std::fs::read_link(dst.join("outside")).unwrap(),
+            Path::new("/etc/hostname")
+        );
+        let data = std::fs::metadata(dst.join("pkg/data.txt")).unwrap();
+        let linked = std::fs::metadata(dst.join("linked.txt")).unwrap();
+        assert_eq!(data.ino(), linked.ino());
+        assert_eq!(data.nlink(), 2);
+    }
+
+    #[test]
+    fn hashes_hardlinks_apart_from_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let (copied, linked) = (dir.path().join("copied"), dir.path().join("linked"));
+        for tree in [&copied, &linked] {
+            std::fs::create_dir(tree).unwrap();
+            std::fs::write(tree.join("a.txt"), "data").unwrap();
+        }
+        std::fs::write(copied.join("b.txt"), "data").unwrap();
+        std::fs::hard_link(linked.join("a.txt"), linked.join("b.txt")).unwrap();
+
+        assert_ne!(hash_dir(&copied).unwrap(), hash_dir(&linked).unwrap());
+    }
+
+    #[tokio::test]
+    async fn skips_excluded_entries_and_their_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        std::fs::create_dir_all(src.join("pkg/__pycache__")).unwrap();
+        std::fs::create_dir_all(src.join("dist/oci/blobs")).unwrap();
+        std::fs::write(src.join("pkg/__pycache__/module.pyc"), "").unwrap();
+        std::fs::write(src.join("pkg/module.py"), "").unwrap();
+        std::fs::write(src.join("dist/oci/blobs/layer"), "").unwrap();
+        std::fs::write(src.join("dist/notes.txt"), "").unwrap();
+
+        copy_dir_all(src.clone(), dst.clone(), |path| {
+            path == Path::new("dist/oci") || path.ends_with("__pycache__")
+        })
+        .await
+        .unwrap();
+
+        assert!(dst.join("pkg/module.py").exists());
+        assert!(dst.join("dist/notes.txt").exists());
+        assert!(!dst.join("pkg/__pycache__").exists());
+        assert!(!dst.join("dist/oci").exists());
+    }
+}
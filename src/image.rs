@@ -1,7 +1,15 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::{collections::HashMap, path::Path};
+use std::time::Duration;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::cache::{parse_duration, parse_size};
+use crate::layer::Compression;
+use crate::wheel::Installer;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct ImageConfig {
@@ -12,6 +20,31 @@ pub struct ImageConfig {
     pub labels: HashMap<String, String>,
     pub exposed_ports: HashMap<String, HashMap<(), ()>>,
     pub volumes: HashMap<String, HashMap<(), ()>>,
+    pub compression: Option<Compression>,
+    /// Default `(uid, gid)` owning the files in the layers we build
+    pub owner: Option<(u64, u64)>,
+    /// Target interpreter `major.minor`; inferred from the base image when unset
+    pub python_version: Option<String>,
+    /// Wheel platform tag to install for, e.g. `manylinux2014_x86_64`
+    pub python_platform: Option<String>,
+    /// Directory of wheels to install dependencies from instead of an index
+    pub wheelhouse: Option<PathBuf>,
+    /// `pip` (default) or `native`, which unpacks wheelhouse wheels itself
+    pub installer: Option<Installer>,
+    /// Optional dependency groups to install from pyproject.toml
+    pub extras: Vec<String>,
+    /// Base image reference, e.g. `python:3.12-slim`
+    pub base_image: Option<String>,
+    /// Tag recorded for the image in the output layout
+    pub tag: Option<String>,
+    /// OCI layout directory to write, relative to the project
+    pub output: Option<PathBuf>,
+    /// `os/arch` to build for, e.g. `linux/arm64`
+    pub platform: Option<String>,
+    /// Size the layer cache is evicted down to after a build, e.g. `"10G"`
+    pub cache_max_size: Option<u64>,
+    /// Age past which cache entries are dropped after a build, e.g. `"30d"`
+    pub cache_max_age: Option<Duration>,
 }
 
 impl ImageConfig {
@@ -26,16 +59,27 @@ impl ImageConfig {
 
     fn default_config() -> Result<Self> {
         Ok(Self {
-            env: vec![
-                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string(),
-                "PYTHONUNBUFFERED=1".to_string(),
-            ],
+            // PATH comes from the base image, with the venv put in front of it
+            env: vec!["PYTHONUNBUFFERED=1".to_string()],
             cmd: vec!["python".to_string(), "main.py".to_string()],
             working_dir: "/app".to_string(),
             entrypoint: vec![],
             labels: HashMap::new(),
             exposed_ports: HashMap::new(),
             volumes: HashMap::new(),
+            compression: None,
+            owner: None,
+            python_version: None,
+            python_platform: None,
+            wheelhouse: None,
+            installer: None,
+            extras: vec![],
+            base_image: None,
+            tag: None,
+            output: None,
+            platform: None,
+            cache_max_size: None,
+            cache_max_age: None,
         })
     }
 
@@ -92,8 +136,107 @@ impl ImageConfig {
                         }
                     }
 
+                    if let Some(compression) = tool.get("compression").and_then(|c| c.as_str()) {
+                        config.compression = Some(compression.parse()?);
+                    }
+
+                    if let Some(owner) = tool.get("owner").and_then(|o| o.as_str()) {
+                        config.owner = Some(Self::parse_owner(owner)?);
+                    }
+
+                    if let Some(version) = tool.get("python_version").and_then(|v| v.as_str()) {
+                        config.python_version = Some(version.to_string());
+                    }
+
+                    if let Some(platform) = tool.get("python_platform").and_then(|p| p.as_str()) {
+                        config.python_platform = Some(platform.to_string());
+                    }
+
+                    if let Some(wheelhouse) = tool.get("wheelhouse").and_then(|w| w.as_str()) {
+                        let project_dir = path.parent().unwrap_or_else(|| Path::new("."));
+                        config.wheelhouse = Some(project_dir.join(wheelhouse));
+                    }
+
+                    if let Some(installer) = tool.get("installer").and_then(|i| i.as_str()) {
+                        config.installer = Some(installer.parse()?);
+                    }
+
+                    if let Some(extras) = tool.get("extras").and_then(|e| e.as_array()) {
+                        config.extras = extras
+                            .iter()
+                            .filter_map(|e| e.as_str())
+                            .map(String::from)
+                            .collect();
+                    }
+
+                    if let Some(base) = tool.get("base").and_then(|b| b.as_str()) {
+                        config.base_image = Some(base.to_string());
+                    }
+
+                    if let Some(tag) = tool.get("tag").and_then(|t| t.as_str()) {
+                        config.tag = Some(tag.to_string());
+                    }
+
+                    if let Some(output) = tool.get("output").and_then(|o| o.as_str()) {
+                        let project_dir = path.parent().unwrap_or_else(|| Path::new("."));
+                        config.output = Some(project_dir.join(output));
+                    }
+
+                    if let Some(platform) = tool.get("platform").and_then(|p| p.as_str()) {
+                        config.platform = Some(platform.to_string());
+                    }
+
+                    if let Some(size) = tool.get("cache_max_size").and_then(|s| s.as_str()) {
+                        config.cache_max_size = Some(parse_size(size)?);
+                    }
+
+                    if let Some(age) = tool.get("cache_max_age").and_then(|a| a.as_str()) {
+                        config.cache_max_age = Some(parse_duration(age)?);
+                    }
+
                     Ok(config)
                 },
             )
     }
+
+    /// Parses a numeric `uid:gid` (or just `uid`, using it as the gid too).
+    fn parse_owner(owner: &str) -> Result<(u64, u64)> {
+        let (uid, gid) = owner.split_once(':').unwrap_or((owner, owner));
+        match (uid.trim().parse(), gid.trim().parse()) {
+            (Ok(uid), Ok(gid)) => Ok((uid, gid)),
+            _ => Err(anyhow::anyhow!(
+                "Invalid owner '{}', expected numeric uid:gid",
+                owner
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_cache_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("pyproject.toml"),
+            "[tool.spacejar]\ncache_max_size = \"2G\"\ncache_max_age = \"7d\"\n",
+        )
+        .unwrap();
+
+        let config = ImageConfig::from_project(dir.path()).unwrap();
+        assert_eq!(config.cache_max_size, Some(2 << 30));
+        assert_eq!(
+            config.cache_max_age,
+            Some(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+
+        std::fs::write(
+            dir.path().join("pyproject.toml"),
+            "[tool.spacejar]\ncache_max_size = \"lots\"\n",
+        )
+        .unwrap();
+        assert!(ImageConfig::from_project(dir.path()).is_err());
+    }
 }
@@ -1,10 +1,61 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use flate2::write::GzEncoder;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fmt;
 use std::io::Write;
-use std::path::Path;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use typed_builder::TypedBuilder;
+
+/// How layer tarballs are compressed; also determines the layer media type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Compression::None => "application/vnd.oci.image.layer.v1.tar",
+            Compression::Gzip => "application/vnd.oci.image.layer.v1.tar+gzip",
+            Compression::Zstd => "application/vnd.oci.image.layer.v1.tar+zstd",
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(anyhow!(
+                "Unknown compression '{}', expected one of: none, gzip, zstd",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
 
 #[async_trait]
 pub trait LayerBuilder {
@@ -23,23 +74,127 @@ pub struct Layer {
     pub annotations: HashMap<String, String>,
 }
 
+/// Timestamp used for every entry in a layer tarball: `SOURCE_DATE_EPOCH`
+/// when set (see https://reproducible-builds.org/specs/source-date-epoch/).
+pub fn source_date_epoch() -> Option<u64> {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Options controlling how a directory is turned into a layer.
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct LayerOptions {
+    #[builder(default)]
+    pub compression: Compression,
+    /// Owner recorded for every entry in the layer
+    #[builder(default = 0)]
+    pub uid: u64,
+    #[builder(default = 0)]
+    pub gid: u64,
+}
+
+impl LayerOptions {
+    /// Everything about the options that shows in a layer built with them,
+    /// for keying cached layers.
+    pub fn cache_key(&self) -> String {
+        let Self {
+            compression,
+            uid,
+            gid,
+        } = self;
+        format!("{}\0{}:{}", compression, uid, gid)
+    }
+}
+
 impl Layer {
-    pub async fn from_dir(path: &Path) -> Result<Self> {
+    /// Archives the tree under `path` into a layer, rooted at `dest` inside
+    /// the image (e.g. `/venv` or `/app/deps`; `/` for the image root).
+    ///
+    /// Directories, regular files, symlinks (kept as links, not followed) and
+    /// hardlinks are all archived. The tarball only depends on names,
+    /// contents and executable bits: entries are sorted, and mtimes,
+    /// ownership and permissions are normalized so identical trees hash
+    /// identically.
+    pub async fn from_dir(path: &Path, dest: &str, options: &LayerOptions) -> Result<Self> {
         let mut archive = tar::Builder::new(Vec::new());
-        let walker = walkdir::WalkDir::new(path).min_depth(1).follow_links(true);
+        let walker = walkdir::WalkDir::new(path)
+            .min_depth(1)
+            .follow_links(false)
+            .sort_by_file_name();
+        let mtime = source_date_epoch().unwrap_or(0);
+
+        let new_header = || -> Result<tar::Header> {
+            let mut header = tar::Header::new_gnu();
+            header.set_mtime(mtime);
+            header.set_uid(options.uid);
+            header.set_gid(options.gid);
+            if options.uid == 0 && options.gid == 0 {
+                header.set_username("root")?;
+                header.set_groupname("root")?;
+            }
+            Ok(header)
+        };
+
+        // Emit the destination directory and its parents so the layer does
+        // not rely on them already existing in the image.
+        let prefix: PathBuf = dest.trim_start_matches('/').split('/').collect();
+        for ancestor in prefix.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            if ancestor.as_os_str().is_empty() {
+                continue;
+            }
+            let mut header = new_header()?;
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o755);
+            archive.append_data(&mut header, ancestor, std::io::empty())?;
+        }
+
+        // First archived path of each multiply-linked inode
+        let mut hardlinks: HashMap<(u64, u64), PathBuf> = HashMap::new();
 
         for entry in walker {
             let entry = entry.map_err(|e| anyhow!(e.to_string()))?;
-            if entry.file_type().is_file() {
-                archive.append_path_with_name(
-                    entry.path(),
-                    entry.path().strip_prefix(path).unwrap(),
-                )?;
+            let name = prefix.join(entry.path().strip_prefix(path)?);
+            let metadata = entry.metadata().map_err(|e| anyhow!(e.to_string()))?;
+            let file_type = entry.file_type();
+
+            let mut header = new_header()?;
+
+            if file_type.is_dir() {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                header.set_mode(0o755);
+                archive.append_data(&mut header, &name, std::io::empty())?;
+            } else if file_type.is_symlink() {
+                let target = std::fs::read_link(entry.path())?;
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                header.set_mode(0o777);
+                archive.append_link(&mut header, &name, target)?;
+            } else if file_type.is_file() {
+                if let Some(inode) = Self::hardlink_inode(&metadata) {
+                    if let Some(first) = hardlinks.get(&inode) {
+                        header.set_entry_type(tar::EntryType::Link);
+                        header.set_size(0);
+                        header.set_mode(Self::normalized_mode(&metadata));
+                        archive.append_link(&mut header, &name, first)?;
+                        continue;
+                    }
+                    hardlinks.insert(inode, name.clone());
+                }
+
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(metadata.len());
+                header.set_mode(Self::normalized_mode(&metadata));
+                archive.append_data(&mut header, &name, std::fs::File::open(entry.path())?)?;
+            } else {
+                tracing::warn!("Skipping special file: {}", entry.path().display());
             }
         }
 
         let data = archive.into_inner()?;
-        let compressed = Self::compress_data(&data).await?;
+        let compressed = Self::compress_data(&data, options.compression).await?;
 
         let mut hasher = Sha256::new();
         hasher.update(&compressed);
@@ -50,7 +205,7 @@ impl Layer {
         let diff_id = format!("sha256:{:x}", diff_hasher.finalize());
 
         Ok(Self {
-            media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+            media_type: options.compression.media_type().to_string(),
             digest,
             size: data.len() as u64,
             compressed_size: compressed.len() as u64,
@@ -60,9 +215,203 @@ impl Layer {
         })
     }
 
-    async fn compress_data(data: &[u8]) -> Result<Vec<u8>> {
-        let mut encoder = zstd::Encoder::new(Vec::new(), 3)?;
-        encoder.write_all(data)?;
-        Ok(encoder.finish()?)
+    /// Identifies files with more than one link so repeats can be archived as
+    /// hardlinks to the first occurrence.
+    fn hardlink_inode(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+        (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+
+    /// Collapses file permissions to `0755` for executables and `0644` otherwise.
+    fn normalized_mode(metadata: &std::fs::Metadata) -> u32 {
+        if metadata.permissions().mode() & 0o111 != 0 {
+            0o755
+        } else {
+            0o644
+        }
+    }
+
+    async fn compress_data(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+        match compression {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(Vec::new(), 3)?;
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn write_tree(root: &Path) {
+        std::fs::create_dir_all(root.join("pkg/sub")).unwrap();
+        std::fs::write(root.join("main.py"), "print('hello')\n").unwrap();
+        std::fs::write(root.join("pkg/__init__.py"), "").unwrap();
+        std::fs::write(root.join("pkg/sub/data.txt"), "some data\n").unwrap();
+    }
+
+    fn touch_all(root: &Path, time: SystemTime) {
+        for entry in walkdir::WalkDir::new(root).min_depth(1) {
+            let entry = entry.unwrap();
+            if entry.file_type().is_file() {
+                let file = std::fs::File::options()
+                    .write(true)
+                    .open(entry.path())
+                    .unwrap();
+                file.set_modified(time).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn from_dir_is_reproducible() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let first_dir = tempfile::tempdir().unwrap();
+            write_tree(first_dir.path());
+            touch_all(
+                first_dir.path(),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            );
+            let options = LayerOptions::builder().compression(compression).build();
+            let first = Layer::from_dir(first_dir.path(), "/app", &options)
+                .await
+                .unwrap();
+
+            let second_dir = tempfile::tempdir().unwrap();
+            write_tree(second_dir.path());
+            touch_all(second_dir.path(), SystemTime::now());
+            let second = Layer::from_dir(second_dir.path(), "/app", &options)
+                .await
+                .unwrap();
+
+            assert_eq!(first.digest, second.digest, "{} digest", compression);
+            assert_eq!(first.diff_id, second.diff_id, "{} diff_id", compression);
+            assert_eq!(first.data, second.data);
+        }
+    }
+
+    #[tokio::test]
+    async fn from_dir_keeps_directories_links_and_modes() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::create_dir_all(root.join("empty")).unwrap();
+        std::fs::write(root.join("bin/python3.12"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(
+            root.join("bin/python3.12"),
+            std::fs::Permissions::from_mode(0o700),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("python3.12", root.join("bin/python")).unwrap();
+        std::fs::write(root.join("data.txt"), "data\n").unwrap();
+        std::fs::hard_link(root.join("data.txt"), root.join("hardlink.txt")).unwrap();
+
+        let options = LayerOptions::builder()
+            .compression(Compression::None)
+            .uid(1000)
+            .gid(1000)
+            .build();
+        let layer = Layer::from_dir(root, "/", &options).await.unwrap();
+
+        let mut archive = tar::Archive::new(layer.data.as_slice());
+        let mut entries = HashMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let header = entry.header().clone();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            entries.insert(
+                entry
+                    .path()
+                    .unwrap()
+                    .to_string_lossy()
+                    .trim_end_matches('/')
+                    .to_string(),
+                (header, contents),
+            );
+        }
+
+        let (empty, _) = &entries["empty"];
+        assert_eq!(empty.entry_type(), tar::EntryType::Directory);
+
+        let (python, _) = &entries["bin/python"];
+        assert_eq!(python.entry_type(), tar::EntryType::Symlink);
+        assert_eq!(
+            python.link_name().unwrap().unwrap().to_str(),
+            Some("python3.12")
+        );
+
+        let (interpreter, _) = &entries["bin/python3.12"];
+        assert_eq!(interpreter.mode().unwrap(), 0o755);
+        assert_eq!(interpreter.uid().unwrap(), 1000);
+        assert_eq!(interpreter.gid().unwrap(), 1000);
+
+        let (data, contents) = &entries["data.txt"];
+        assert_eq!(data.entry_type(), tar::EntryType::Regular);
+        assert_eq!(data.mode().unwrap(), 0o644);
+        assert_eq!(contents, "data\n");
+
+        let (hardlink, _) = &entries["hardlink.txt"];
+        assert_eq!(hardlink.entry_type(), tar::EntryType::Link);
+        assert_eq!(
+            hardlink.link_name().unwrap().unwrap().to_str(),
+            Some("data.txt")
+        );
+    }
+
+    fn entry_paths(layer: &Layer) -> Vec<String> {
+        tar::Archive::new(layer.data.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn from_dir_places_entries_under_dest() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tree(dir.path());
+        let options = LayerOptions::builder()
+            .compression(Compression::None)
+            .build();
+
+        let layer = Layer::from_dir(dir.path(), "/app/deps", &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            entry_paths(&layer),
+            [
+                "app",
+                "app/deps",
+                "app/deps/main.py",
+                "app/deps/pkg",
+                "app/deps/pkg/__init__.py",
+                "app/deps/pkg/sub",
+                "app/deps/pkg/sub/data.txt",
+            ]
+        );
+
+        let layer = Layer::from_dir(dir.path(), "/", &options).await.unwrap();
+        assert_eq!(
+            entry_paths(&layer),
+            [
+                "main.py",
+                "pkg",
+                "pkg/__init__.py",
+                "pkg/sub",
+                "pkg/sub/data.txt",
+            ]
+        );
     }
 }
@@ -0,0 +1,201 @@
+use anyhow::{format_err, Context, Result};
+use oci_spec::image::{
+    DescriptorBuilder, ImageConfiguration, ImageIndex, ImageIndexBuilder, ImageManifest, MediaType,
+    OciLayout, OciLayoutBuilder, ANNOTATION_REF_NAME,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::fs::write_atomic;
+
+const IMAGE_LAYOUT_VERSION: &str = "1.0.0";
+
+/// An OCI image layout directory (`oci-layout`, `index.json` and
+/// content-addressed `blobs/sha256`).
+pub struct ImageLayout {
+    root: PathBuf,
+}
+
+impl ImageLayout {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn blobs_dir(&self) -> PathBuf {
+        self.root.join("blobs/sha256")
+    }
+
+    pub fn blob_path(&self, digest: &str) -> PathBuf {
+        self.blobs_dir().join(digest.trim_start_matches("sha256:"))
+    }
+
+    /// Writes `data` as a blob and returns its `sha256:` digest.
+    pub async fn write_blob(&self, data: &[u8]) -> Result<String> {
+        tokio::fs::create_dir_all(self.blobs_dir()).await?;
+
+        let digest = sha256_digest(data);
+        write_atomic(&self.blob_path(&digest), data)
+            .await
+            .with_context(|| format!("Failed to write blob: {}", digest))?;
+
+        Ok(digest)
+    }
+
+    /// Reads a blob and checks it against its digest and, if given, its size.
+    pub async fn read_blob(&self, digest: &str, size: Option<u64>) -> Result<Vec<u8>> {
+        let data = tokio::fs::read(self.blob_path(digest))
+            .await
+            .with_context(|| format!("Missing blob: {}", digest))?;
+
+        if let Some(size) = size {
+            if data.len() as u64 != size {
+                return Err(format_err!(
+                    "Blob size mismatch for {}: expected {}, got {}",
+                    digest,
+                    size,
+                    data.len()
+                ));
+            }
+        }
+
+        let calculated = sha256_digest(&data);
+        if calculated != digest {
+            return Err(format_err!(
+                "Blob digest mismatch: expected {}, calculated {}",
+                digest,
+                calculated
+            ));
+        }
+
+        Ok(data)
+    }
+
+    /// Finds the manifest tagged `tag` in `index.json` and returns its digest
+    /// and verified contents.
+    pub async fn read_manifest(&self, tag: &str) -> Result<(String, Vec<u8>)> {
+        let index = ImageIndex::from_file(self.root.join("index.json"))
+            .with_context(|| format!("Failed to read {}/index.json", self.root.display()))?;
+
+        let descriptor = index
+            .manifests()
+            .iter()
+            .find(|descriptor| {
+                descriptor
+                    .annotations()
+                    .as_ref()
+                    .and_then(|a| a.get(ANNOTATION_REF_NAME))
+                    .is_some_and(|name| name == tag)
+            })
+            .ok_or_else(|| format_err!("No image tagged '{}' in {}", tag, self.root.display()))?;
+
+        let digest = descriptor.digest().to_string();
+        let data = self.read_blob(&digest, Some(descriptor.size())).await?;
+        Ok((digest, data))
+    }
+
+    /// Records a manifest blob in `index.json` under `tag`, replacing any
+    /// manifest previously tagged with the same name.
+    pub async fn write_index(
+        &self,
+        manifest_digest: &str,
+        manifest_size: u64,
+        tag: &str,
+    ) -> Result<()> {
+        let index_path = self.root.join("index.json");
+
+        let mut manifests = if index_path.exists() {
+            ImageIndex::from_file(&index_path)
+                .context("Failed to read existing index.json")?
+                .manifests()
+                .clone()
+        } else {
+            Vec::new()
+        };
+
+        manifests.retain(|descriptor| {
+            descriptor
+                .annotations()
+                .as_ref()
+                .and_then(|a| a.get(ANNOTATION_REF_NAME))
+                .is_none_or(|name| name != tag)
+        });
+
+        let descriptor = DescriptorBuilder::default()
+            .media_type(MediaType::ImageManifest)
+            .digest(oci_spec::image::Digest::try_from(manifest_digest)?)
+            .size(manifest_size)
+            .annotations(HashMap::from([(
+                ANNOTATION_REF_NAME.to_string(),
+                tag.to_string(),
+            )]))
+            .build()?;
+        manifests.push(descriptor);
+
+        let index = ImageIndexBuilder::default()
+            .schema_version(2u32)
+            .media_type(MediaType::ImageIndex)
+            .manifests(manifests)
+            .build()?;
+        index.to_file_pretty(&index_path)?;
+
+        let layout = OciLayoutBuilder::default()
+            .image_layout_version(IMAGE_LAYOUT_VERSION)
+            .build()?;
+        layout.to_file_pretty(self.root.join("oci-layout"))?;
+
+        Ok(())
+    }
+
+    /// Re-reads the layout and checks that every blob referenced from
+    /// `index.json`, directly or through a manifest, exists and matches its
+    /// digest and size.
+    pub async fn validate(&self) -> Result<()> {
+        let layout = OciLayout::from_file(self.root.join("oci-layout"))
+            .context("Failed to read oci-layout")?;
+        if layout.image_layout_version() != IMAGE_LAYOUT_VERSION {
+            return Err(format_err!(
+                "Unsupported image layout version: {}",
+                layout.image_layout_version()
+            ));
+        }
+
+        let index = ImageIndex::from_file(self.root.join("index.json"))
+            .context("Failed to read index.json")?;
+
+        for descriptor in index.manifests() {
+            let digest = descriptor.digest().to_string();
+            let data = self.read_blob(&digest, Some(descriptor.size())).await?;
+
+            let manifest = ImageManifest::from_reader(data.as_slice())
+                .with_context(|| format!("Failed to parse manifest: {}", digest))?;
+
+            for blob in std::iter::once(manifest.config()).chain(manifest.layers()) {
+                self.read_blob(blob.digest().as_ref(), Some(blob.size()))
+                    .await?;
+            }
+
+            let config_data = self
+                .read_blob(manifest.config().digest().as_ref(), None)
+                .await?;
+            let config = ImageConfiguration::from_reader(config_data.as_slice())
+                .with_context(|| format!("Failed to parse image config of {}", digest))?;
+            if config.rootfs().diff_ids().len() != manifest.layers().len() {
+                return Err(format_err!(
+                    "Manifest {} has {} layers but its config lists {} diff_ids",
+                    digest,
+                    manifest.layers().len(),
+                    config.rootfs().diff_ids().len()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn sha256_digest(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("sha256:{:x}", hasher.finalize())
+}
@@ -0,0 +1,21 @@
+// Layers record Unix modes, symlinks and hardlinks, and the venv layer
+// links into the base image's interpreter, so only Unix hosts can build.
+#[cfg(not(unix))]
+compile_error!("python-oci-packager builds images on Unix hosts only");
+
+pub mod auth;
+pub mod builder;
+pub mod cache;
+pub mod credentials;
+pub mod dependencies;
+pub mod fs;
+pub mod image;
+pub mod layer;
+pub mod layout;
+pub mod lockfile;
+pub mod manifest;
+pub mod python;
+pub mod reference;
+pub mod registries;
+pub mod requirements;
+pub mod wheel;
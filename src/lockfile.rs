@@ -0,0 +1,1058 @@
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::dependencies::{poetry_requirement, ProjectDependencies};
+use crate::python::TargetPython;
+use crate::requirements::{evaluate_marker, normalize_name, parse_requirements_file, Requirement};
+
+/// The lock file formats the builder understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Uv,
+    Poetry,
+    /// A `requirements.txt` compiled by pip-tools with `--generate-hashes`
+    PipTools,
+}
+
+impl fmt::Display for LockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockKind::Uv => write!(f, "uv.lock"),
+            LockKind::Poetry => write!(f, "poetry.lock"),
+            LockKind::PipTools => write!(f, "requirements.txt"),
+        }
+    }
+}
+
+/// A package pinned by a lock file, with the hashes its artifacts must match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockedPackage {
+    /// PEP 503 normalized name
+    pub name: String,
+    pub version: String,
+    /// `sha256:<hex>` digests of the sdist and wheels the lock accepts
+    pub hashes: Vec<String>,
+}
+
+pub struct LockFile {
+    pub kind: LockKind,
+    pub path: PathBuf,
+    contents: String,
+}
+
+impl LockFile {
+    /// Finds the project's lock file, preferring `uv.lock`, then
+    /// `poetry.lock`, then a `requirements.txt` that carries `--hash` options.
+    pub fn detect(project_path: &Path) -> Result<Option<Self>> {
+        for (kind, name) in [
+            (LockKind::Uv, "uv.lock"),
+            (LockKind::Poetry, "poetry.lock"),
+            (LockKind::PipTools, "requirements.txt"),
+        ] {
+            let path = project_path.join(name);
+            if !path.exists() {
+                continue;
+            }
+
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            if kind == LockKind::PipTools && !contents.contains("--hash") {
+                continue;
+            }
+
+            return Ok(Some(Self {
+                kind,
+                path,
+                contents,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+
+    /// Fails if the lock no longer reflects the dependencies declared in
+    /// `pyproject.toml` (or `requirements.in` for pip-tools).
+    pub fn ensure_fresh(&self, project_path: &Path, extras: &[String]) -> Result<()> {
+        let stale = match self.kind {
+            LockKind::Uv => self.uv_staleness(project_path)?,
+            LockKind::Poetry => self.poetry_staleness(project_path)?,
+            LockKind::PipTools => self.pip_tools_staleness(project_path, extras)?,
+        };
+
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let relock = match self.kind {
+            LockKind::Uv => "uv lock",
+            LockKind::Poetry => "poetry lock",
+            LockKind::PipTools => "pip-compile --generate-hashes",
+        };
+        Err(anyhow!(
+            "{} is out of date; run `{}`:\n  {}",
+            self.kind,
+            relock,
+            stale.join("\n  ")
+        ))
+    }
+
+    /// The pinned packages to install for `target`, following the lock's
+    /// dependency edges from the project and its selected `extras`.
+    pub fn resolve(
+        &self,
+        project_path: &Path,
+        extras: &[String],
+        target: &TargetPython,
+    ) -> Result<Vec<LockedPackage>> {
+        let packages = match self.kind {
+            LockKind::Uv => self.resolve_uv(extras, target)?,
+            LockKind::Poetry => self.resolve_poetry(project_path, extras, target)?,
+            LockKind::PipTools => self.resolve_pip_tools(extras, target)?,
+        };
+
+        let unhashed: Vec<String> = packages
+            .iter()
+            .filter(|package| package.hashes.is_empty())
+            .map(|package| format!("{} {}", package.name, package.version))
+            .collect();
+        if !unhashed.is_empty() {
+            return Err(anyhow!(
+                "{} has no hashes for (hash checking cannot be enforced):\n  {}",
+                self.kind,
+                unhashed.join("\n  ")
+            ));
+        }
+
+        Ok(packages)
+    }
+
+    /// A requirements file pinning exactly `packages` with their hashes, for
+    /// `pip install --require-hashes`. A pip-tools lock is used as-is so pip
+    /// still sees its index options.
+    pub fn requirements_file(&self, packages: &[LockedPackage], dir: &Path) -> Result<PathBuf> {
+        if self.kind == LockKind::PipTools {
+            return Ok(self.path.clone());
+        }
+
+        let mut content = String::new();
+        for package in packages {
+            content.push_str(&format!("{}=={}", package.name, package.version));
+            for hash in &package.hashes {
+                content.push_str(&format!(" \\\n    --hash={}", hash));
+            }
+            content.push('\n');
+        }
+
+        let path = dir.join("requirements.lock.txt");
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    fn resolve_pip_tools(
+        &self,
+        extras: &[String],
+        target: &TargetPython,
+    ) -> Result<Vec<LockedPackage>> {
+        if !extras.is_empty() {
+            return Err(anyhow!(
+                "Extras {:?} are selected but requirements.txt is a compiled lock; \
+                 compile it with the extras instead",
+                extras
+            ));
+        }
+
+        let env = target.marker_env();
+        let mut packages = Vec::new();
+        for (requirement, hashes) in self.hashed_requirements()? {
+            if requirement.applies(&env)? {
+                packages.push(LockedPackage {
+                    name: normalize_name(&requirement.name),
+                    version: requirement.specifiers[0].version.clone(),
+                    hashes,
+                });
+            }
+        }
+        Ok(packages)
+    }
+
+    /// Parses a pip-tools lock, requiring every entry to be pinned with
+    /// `==` and to carry at least one `--hash`.
+    fn hashed_requirements(&self) -> Result<Vec<(Requirement, Vec<String>)>> {
+        let mut entries = Vec::new();
+        let mut invalid = Vec::new();
+
+        for line in self.contents.replace("\\\n", " ").lines() {
+            let line = match line.find(" #") {
+                Some(pos) => &line[..pos],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
+                continue;
+            }
+
+            let (requirement, options) = match line.find(" --") {
+                Some(pos) => (&line[..pos], &line[pos..]),
+                None => (line, ""),
+            };
+            let requirement = Requirement::parse(requirement)
+                .with_context(|| format!("Invalid requirement in {}", self.path.display()))?;
+
+            let mut hashes = Vec::new();
+            let mut tokens = options.split_whitespace();
+            while let Some(token) = tokens.next() {
+                if let Some(hash) = token.strip_prefix("--hash=") {
+                    hashes.push(hash.to_string());
+                } else if token == "--hash" {
+                    hashes.extend(tokens.next().map(str::to_string));
+                }
+            }
+
+            let pinned = requirement.specifiers.len() == 1
+                && matches!(requirement.specifiers[0].operator.as_str(), "==" | "===")
+                && !requirement.specifiers[0].version.contains('*');
+            if !pinned {
+                invalid.push(format!("{} (not pinned with ==)", requirement));
+            } else if hashes.is_empty() {
+                invalid.push(format!("{} (no --hash)", requirement));
+            } else {
+                entries.push((requirement, hashes));
+            }
+        }
+
+        if !invalid.is_empty() {
+            return Err(anyhow!(
+                "{} uses --hash, so every requirement must be pinned and hashed:\n  {}",
+                self.path.display(),
+                invalid.join("\n  ")
+            ));
+        }
+
+        Ok(entries)
+    }
+
+    fn pip_tools_staleness(&self, project_path: &Path, extras: &[String]) -> Result<Vec<String>> {
+        let requirements_in = project_path.join("requirements.in");
+        let inputs = if requirements_in.exists() {
+            parse_requirements_file(&requirements_in)?
+        } else {
+            match ProjectDependencies::from_pyproject(project_path, extras)? {
+                Some(dependencies) => dependencies.requirements,
+                None => return Ok(Vec::new()),
+            }
+        };
+
+        let pinned: HashMap<String, String> = self
+            .hashed_requirements()?
+            .into_iter()
+            .map(|(requirement, _)| {
+                (
+                    normalize_name(&requirement.name),
+                    requirement.specifiers[0].version.clone(),
+                )
+            })
+            .collect();
+
+        let mut stale = Vec::new();
+        for input in inputs.iter().filter(|input| input.url.is_none()) {
+            match pinned.get(&normalize_name(&input.name)) {
+                Some(version) if !input.matches_version(version) => {
+                    stale.push(format!("{} is pinned to {}", input, version));
+                }
+                None if input.marker.is_none() => {
+                    stale.push(format!("{} is not pinned", input));
+                }
+                _ => {}
+            }
+        }
+        Ok(stale)
+    }
+
+    fn parse_toml(&self) -> Result<toml::Value> {
+        toml::from_str(&self.contents).with_context(|| format!("Failed to parse {}", self.kind))
+    }
+
+    fn resolve_uv(&self, extras: &[String], target: &TargetPython) -> Result<Vec<LockedPackage>> {
+        let lock = self.parse_toml()?;
+        let packages = lock
+            .get("package")
+            .and_then(|p| p.as_array())
+            .ok_or_else(|| anyhow!("uv.lock has no packages"))?;
+        let root = Self::uv_root(packages)?;
+
+        let mut by_name: HashMap<String, Vec<&toml::Value>> = HashMap::new();
+        for package in packages {
+            by_name
+                .entry(normalize_name(toml_str(package, "name")))
+                .or_default()
+                .push(package);
+        }
+
+        let mut queue: VecDeque<&toml::Value> = toml_array(root, "dependencies").collect();
+        for extra in extras {
+            let edges = root
+                .get("optional-dependencies")
+                .and_then(|o| o.as_table())
+                .and_then(|table| {
+                    table
+                        .iter()
+                        .find(|(name, _)| normalize_name(name) == normalize_name(extra))
+                })
+                .and_then(|(_, edges)| edges.as_array())
+                .ok_or_else(|| anyhow!("Unknown extra '{}' in uv.lock", extra))?;
+            queue.extend(edges);
+        }
+
+        let env = target.marker_env();
+        let mut selected: Vec<&toml::Value> = Vec::new();
+        let mut expanded: HashSet<(String, String, String)> = HashSet::new();
+
+        while let Some(edge) = queue.pop_front() {
+            if let Some(marker) = edge.get("marker").and_then(|m| m.as_str()) {
+                if !evaluate_marker(marker, &env)? {
+                    continue;
+                }
+            }
+
+            let name = normalize_name(toml_str(edge, "name"));
+            let version = edge.get("version").and_then(|v| v.as_str());
+            let package = by_name
+                .get(&name)
+                .and_then(|candidates| {
+                    candidates.iter().copied().find(|candidate| {
+                        version.is_none_or(|version| toml_str(candidate, "version") == version)
+                    })
+                })
+                .ok_or_else(|| anyhow!("uv.lock has no entry for {}", name))?;
+            let version = toml_str(package, "version").to_string();
+
+            if expanded.insert((name.clone(), version.clone(), String::new())) {
+                selected.push(package);
+                queue.extend(toml_array(package, "dependencies"));
+            }
+            for extra in toml_array(edge, "extra").filter_map(|e| e.as_str()) {
+                if expanded.insert((name.clone(), version.clone(), normalize_name(extra))) {
+                    let edges = package
+                        .get("optional-dependencies")
+                        .and_then(|o| o.get(extra))
+                        .and_then(|e| e.as_array());
+                    queue.extend(edges.into_iter().flatten());
+                }
+            }
+        }
+
+        Ok(selected
+            .into_iter()
+            .map(|package| LockedPackage {
+                name: normalize_name(toml_str(package, "name")),
+                version: toml_str(package, "version").to_string(),
+                hashes: package
+                    .get("sdist")
+                    .into_iter()
+                    .chain(toml_array(package, "wheels"))
+                    .filter_map(|artifact| artifact.get("hash").and_then(|h| h.as_str()))
+                    .map(str::to_string)
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// The lock entry for the project itself, whose source is `.`.
+    fn uv_root(packages: &[toml::Value]) -> Result<&toml::Value> {
+        packages
+            .iter()
+            .find(|package| {
+                package.get("source").is_some_and(|source| {
+                    ["editable", "virtual"]
+                        .iter()
+                        .any(|key| source.get(*key).and_then(|s| s.as_str()) == Some("."))
+                })
+            })
+            .ok_or_else(|| anyhow!("uv.lock has no entry for the project itself"))
+    }
+
+    /// Compares the project's `requires-dist` recorded in the lock with the
+    /// dependencies `pyproject.toml` declares now.
+    fn uv_staleness(&self, project_path: &Path) -> Result<Vec<String>> {
+        let lock = self.parse_toml()?;
+        let packages = lock
+            .get("package")
+            .and_then(|p| p.as_array())
+            .ok_or_else(|| anyhow!("uv.lock has no packages"))?;
+        let root = Self::uv_root(packages)?;
+
+        let locked: HashSet<(String, String)> = root
+            .get("metadata")
+            .map(|metadata| toml_array(metadata, "requires-dist"))
+            .into_iter()
+            .flatten()
+            .map(|entry| {
+                (
+                    normalize_name(toml_str(entry, "name")),
+                    normalize_specifiers(
+                        entry
+                            .get("specifier")
+                            .and_then(|s| s.as_str())
+                            .unwrap_or(""),
+                    ),
+                )
+            })
+            .collect();
+
+        let content = std::fs::read_to_string(project_path.join("pyproject.toml"))
+            .context("uv.lock requires a pyproject.toml")?;
+        let pyproject: toml::Value = toml::from_str(&content)?;
+        let project = pyproject.get("project");
+        let declared = project
+            .map(|p| toml_array(p, "dependencies"))
+            .into_iter()
+            .flatten()
+            .chain(
+                project
+                    .and_then(|p| p.get("optional-dependencies"))
+                    .and_then(|o| o.as_table())
+                    .into_iter()
+                    .flat_map(|table| table.values())
+                    .filter_map(|group| group.as_array())
+                    .flatten(),
+            )
+            .filter_map(|entry| entry.as_str())
+            .map(Requirement::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        let declared: HashSet<(String, String)> = declared
+            .iter()
+            .map(|requirement| {
+                let specifiers: Vec<String> = requirement
+                    .specifiers
+                    .iter()
+                    .map(|s| format!("{}{}", s.operator, s.version))
+                    .collect();
+                (
+                    normalize_name(&requirement.name),
+                    normalize_specifiers(&specifiers.join(",")),
+                )
+            })
+            .collect();
+
+        let mut stale: Vec<String> =
+            declared
+                .difference(&locked)
+                .map(|(name, specifier)| format!("{}{} is not in the lock", name, specifier))
+                .chain(locked.difference(&declared).map(|(name, specifier)| {
+                    format!("{}{} is no longer declared", name, specifier)
+                }))
+                .collect();
+        stale.sort();
+        Ok(stale)
+    }
+
+    fn resolve_poetry(
+        &self,
+        project_path: &Path,
+        extras: &[String],
+        target: &TargetPython,
+    ) -> Result<Vec<LockedPackage>> {
+        let lock = self.parse_toml()?;
+        let packages = lock
+            .get("package")
+            .and_then(|p| p.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let mut by_name: HashMap<String, Vec<&toml::Value>> = HashMap::new();
+        for package in packages {
+            by_name
+                .entry(normalize_name(toml_str(package, "name")))
+                .or_default()
+                .push(package);
+        }
+
+        let roots = ProjectDependencies::from_pyproject(project_path, extras)?
+            .map(|dependencies| dependencies.requirements)
+            .unwrap_or_default();
+        let mut queue: VecDeque<(Requirement, Vec<String>)> =
+            roots.into_iter().map(|root| (root, Vec::new())).collect();
+
+        let env = target.marker_env();
+        let mut selected: Vec<&toml::Value> = Vec::new();
+        let mut expanded: HashSet<(String, String, String)> = HashSet::new();
+        let mut missing = Vec::new();
+
+        while let Some((requirement, parent_extras)) = queue.pop_front() {
+            let mut applies = parent_extras.is_empty() && requirement.applies(&env)?;
+            for extra in &parent_extras {
+                let mut env = env.clone();
+                env.insert("extra", extra.clone());
+                applies |= requirement.applies(&env)?;
+            }
+            if !applies {
+                continue;
+            }
+
+            let name = normalize_name(&requirement.name);
+            let Some(package) = by_name.get(&name).and_then(|candidates| {
+                candidates
+                    .iter()
+                    .copied()
+                    .find(|candidate| requirement.matches_version(toml_str(candidate, "version")))
+            }) else {
+                missing.push(requirement.to_string());
+                continue;
+            };
+            let version = toml_str(package, "version").to_string();
+
+            // The empty extra stands for the package's required dependencies
+            let requested: Vec<String> = std::iter::once(String::new())
+                .chain(requirement.extras.iter().map(|e| normalize_name(e)))
+                .filter(|extra| expanded.insert((name.clone(), version.clone(), extra.clone())))
+                .collect();
+            if requested.iter().any(String::is_empty) {
+                selected.push(package);
+            }
+            if requested.is_empty() {
+                continue;
+            }
+
+            // Optional dependencies are pulled in by the extras that list them
+            let optional_members: HashSet<String> = package
+                .get("extras")
+                .and_then(|e| e.as_table())
+                .into_iter()
+                .flatten()
+                .filter(|(extra, _)| requested.contains(&normalize_name(extra)))
+                .filter_map(|(_, members)| members.as_array())
+                .flatten()
+                .filter_map(|member| member.as_str())
+                .map(|member| {
+                    let end = member
+                        .find(|c: char| {
+                            !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                        })
+                        .unwrap_or(member.len());
+                    normalize_name(&member[..end])
+                })
+                .collect();
+
+            let dependencies = package.get("dependencies").and_then(|d| d.as_table());
+            for (dependency, spec) in dependencies.into_iter().flatten() {
+                let constraints = match spec {
+                    toml::Value::Array(constraints) => constraints.iter().collect(),
+                    spec => vec![spec],
+                };
+                for constraint in constraints {
+                    let optional = constraint
+                        .get("optional")
+                        .and_then(|o| o.as_bool())
+                        .unwrap_or(false);
+                    let wanted = if optional {
+                        optional_members.contains(&normalize_name(dependency))
+                    } else {
+                        requested.iter().any(String::is_empty)
+                    };
+                    if wanted {
+                        let dependency = poetry_requirement(dependency, constraint, project_path)
+                            .with_context(|| {
+                            format!("Invalid dependency of {} in poetry.lock", name)
+                        })?;
+                        queue.push_back((dependency, requested.clone()));
+                    }
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "poetry.lock does not satisfy:\n  {}",
+                missing.join("\n  ")
+            ));
+        }
+
+        Ok(selected
+            .into_iter()
+            .map(|package| LockedPackage {
+                name: normalize_name(toml_str(package, "name")),
+                version: toml_str(package, "version").to_string(),
+                hashes: toml_array(package, "files")
+                    .filter_map(|file| file.get("hash").and_then(|h| h.as_str()))
+                    .map(str::to_string)
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// Poetry records a hash of the dependency-related parts of
+    /// `pyproject.toml` in `[metadata] content-hash`.
+    fn poetry_staleness(&self, project_path: &Path) -> Result<Vec<String>> {
+        let lock = self.parse_toml()?;
+        let Some(locked_hash) = lock
+            .get("metadata")
+            .and_then(|m| m.get("content-hash"))
+            .and_then(|h| h.as_str())
+        else {
+            return Ok(vec!["poetry.lock has no content-hash".to_string()]);
+        };
+
+        let content = std::fs::read_to_string(project_path.join("pyproject.toml"))
+            .context("poetry.lock requires a pyproject.toml")?;
+        let pyproject: toml::Value = toml::from_str(&content)?;
+
+        if poetry_content_hash(&pyproject) == locked_hash {
+            Ok(Vec::new())
+        } else {
+            Ok(vec![
+                "pyproject.toml changed significantly since poetry.lock was last generated"
+                    .to_string(),
+            ])
+        }
+    }
+}
+
+/// Poetry's `content-hash`: the SHA-256 of `json.dumps(relevant, sort_keys=True)`
+/// over the dependency-related keys of `[project]` and `[tool.poetry]`.
+fn poetry_content_hash(pyproject: &toml::Value) -> String {
+    const LEGACY_KEYS: [&str; 4] = ["dependencies", "source", "extras", "dev-dependencies"];
+    const RELEVANT_KEYS: [&str; 5] = [
+        "dependencies",
+        "source",
+        "extras",
+        "dev-dependencies",
+        "group",
+    ];
+    const PROJECT_KEYS: [&str; 3] = ["requires-python", "dependencies", "optional-dependencies"];
+
+    let project = pyproject.get("project");
+    let poetry = pyproject.get("tool").and_then(|t| t.get("poetry"));
+
+    let relevant_project: Vec<(String, String)> = PROJECT_KEYS
+        .iter()
+        .filter_map(|key| {
+            let value = project?.get(*key)?;
+            Some((key.to_string(), python_json(value)))
+        })
+        .collect();
+
+    let relevant_poetry: Vec<(String, String)> = RELEVANT_KEYS
+        .iter()
+        .filter_map(|key| {
+            let value = poetry.and_then(|p| p.get(*key));
+            if value.is_none() && (!LEGACY_KEYS.contains(key) || !relevant_project.is_empty()) {
+                return None;
+            }
+            Some((
+                key.to_string(),
+                value.map_or_else(|| "null".to_string(), python_json),
+            ))
+        })
+        .collect();
+
+    let relevant = if relevant_project.is_empty() {
+        json_object(relevant_poetry)
+    } else {
+        json_object(vec![
+            ("project".to_string(), json_object(relevant_project)),
+            (
+                "tool".to_string(),
+                json_object(vec![("poetry".to_string(), json_object(relevant_poetry))]),
+            ),
+        ])
+    };
+
+    format!("{:x}", Sha256::digest(relevant.as_bytes()))
+}
+
+/// Serializes like Python's `json.dumps(value, sort_keys=True)`.
+fn python_json(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => python_json_string(s),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => format!("{:?}", f),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(d) => python_json_string(&d.to_string()),
+        toml::Value::Array(items) => format!(
+            "[{}]",
+            items.iter().map(python_json).collect::<Vec<_>>().join(", ")
+        ),
+        toml::Value::Table(table) => json_object(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), python_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn json_object(mut entries: Vec<(String, String)>) -> String {
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|(key, value)| format!("{}: {}", python_json_string(key), value))
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+/// A JSON string with Python's default `ensure_ascii` escaping.
+fn python_json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if c.is_ascii() && !c.is_ascii_control() => out.push(c),
+            c => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    out.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Canonical form of a specifier set for comparison: clauses without
+/// whitespace, sorted.
+fn normalize_specifiers(specifiers: &str) -> String {
+    let mut clauses: Vec<String> = specifiers
+        .split(',')
+        .map(|clause| clause.split_whitespace().collect::<String>())
+        .filter(|clause| !clause.is_empty())
+        .collect();
+    clauses.sort();
+    clauses.join(",")
+}
+
+fn toml_str<'a>(value: &'a toml::Value, key: &str) -> &'a str {
+    value.get(key).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+fn toml_array<'a>(value: &'a toml::Value, key: &str) -> impl Iterator<Item = &'a toml::Value> {
+    value
+        .get(key)
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POETRY_LOCK: &str = include_str!("../tests/fixtures/lockfiles/poetry.lock");
+    const POETRY_PYPROJECT: &str =
+        include_str!("../tests/fixtures/lockfiles/poetry-pyproject.toml");
+    const UV_LOCK: &str = include_str!("../tests/fixtures/lockfiles/uv.lock");
+    const UV_PYPROJECT: &str = include_str!("../tests/fixtures/lockfiles/uv-pyproject.toml");
+    const PIP_TOOLS_LOCK: &str = include_str!("../tests/fixtures/lockfiles/requirements.txt");
+    const REQUIREMENTS_IN: &str = include_str!("../tests/fixtures/lockfiles/requirements.in");
+
+    /// Packages every fixture lock pins for `requests` alone
+    const REQUESTS_CLOSURE: [&str; 5] = [
+        "certifi==2024.2.2",
+        "charset-normalizer==3.3.2",
+        "idna==3.6",
+        "requests==2.31.0",
+        "urllib3==2.2.1",
+    ];
+
+    /// Python version and extras, then the packages added to `REQUESTS_CLOSURE`
+    type ResolveCase<'a> = (&'a str, &'a [&'a str], &'a [&'a str]);
+
+    const RESOLVE_CASES: &[ResolveCase] = &[
+        ("3.12.2", &[], &[]),
+        ("3.12.2", &["cache"], &["redis==5.0.3"]),
+        // redis only needs async-timeout before 3.11.3
+        (
+            "3.11.2",
+            &["cache"],
+            &["async-timeout==4.0.3", "redis==5.0.3"],
+        ),
+    ];
+
+    fn project(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    fn target(full_version: &str) -> TargetPython {
+        let version = full_version.rsplit_once('.').unwrap().0;
+        TargetPython {
+            version: version.to_string(),
+            full_version: full_version.to_string(),
+            platforms: vec![
+                "manylinux_2_17_x86_64".to_string(),
+                "linux_x86_64".to_string(),
+            ],
+            abi: format!("cp{}", version.replace('.', "")),
+            home: "/usr/local/bin".to_string(),
+            machine: "x86_64".to_string(),
+        }
+    }
+
+    fn detect(dir: &tempfile::TempDir) -> LockFile {
+        LockFile::detect(dir.path()).unwrap().unwrap()
+    }
+
+    fn pins(packages: &[LockedPackage]) -> Vec<String> {
+        let mut pins: Vec<String> = packages
+            .iter()
+            .map(|package| format!("{}=={}", package.name, package.version))
+            .collect();
+        pins.sort();
+        pins
+    }
+
+    /// Resolves `lock` for each of `RESOLVE_CASES`, checking every package
+    /// carries the lock's hashes; returns the resolved `requests` entry.
+    fn check_resolution(lock: &LockFile, dir: &Path) -> LockedPackage {
+        for (version, extras, added) in RESOLVE_CASES {
+            let extras: Vec<String> = extras.iter().map(|e| e.to_string()).collect();
+            let packages = lock.resolve(dir, &extras, &target(version)).unwrap();
+
+            let mut expected: Vec<&str> = REQUESTS_CLOSURE.iter().chain(*added).copied().collect();
+            expected.sort();
+            assert_eq!(pins(&packages), expected, "{} {:?}", version, extras);
+            for package in &packages {
+                assert_eq!(package.hashes.len(), 2, "{}", package.name);
+                for hash in &package.hashes {
+                    assert!(hash.starts_with("sha256:"), "{}", hash);
+                    assert!(lock.contents().contains(hash.as_str()), "{}", hash);
+                }
+            }
+        }
+
+        lock.resolve(dir, &[], &target("3.12.2"))
+            .unwrap()
+            .into_iter()
+            .find(|package| package.name == "requests")
+            .unwrap()
+    }
+
+    #[test]
+    fn computes_poetry_content_hashes() {
+        // Expected values from Poetry's Locker._get_content_hash on the
+        // same files
+        let cases: &[(&str, &str)] = &[
+            (
+                POETRY_PYPROJECT,
+                "ddd167b82217c02ceded52f96934cdc23303339630c4c4217930c3332d5ad13c",
+            ),
+            (
+                UV_PYPROJECT,
+                "0641e90b9d291494c4dc00d1e9f2f38b9e2213de87738f50926387a2594f91e9",
+            ),
+        ];
+
+        for (pyproject, expected) in cases {
+            let pyproject: toml::Value = toml::from_str(pyproject).unwrap();
+            assert_eq!(poetry_content_hash(&pyproject), *expected);
+        }
+
+        let locked = POETRY_LOCK
+            .lines()
+            .find_map(|line| line.strip_prefix("content-hash = "))
+            .unwrap()
+            .trim_matches('"');
+        assert_eq!(locked, cases[0].1);
+    }
+
+    #[test]
+    fn detects_stale_poetry_locks() {
+        let dir = project(&[
+            ("pyproject.toml", POETRY_PYPROJECT),
+            ("poetry.lock", POETRY_LOCK),
+        ]);
+        let lock = detect(&dir);
+        assert_eq!(lock.kind, LockKind::Poetry);
+        lock.ensure_fresh(dir.path(), &[]).unwrap();
+
+        // Metadata outside the dependency tables doesn't count
+        let retitled = POETRY_PYPROJECT.replace("Démo service", "Demo service");
+        std::fs::write(dir.path().join("pyproject.toml"), retitled).unwrap();
+        lock.ensure_fresh(dir.path(), &[]).unwrap();
+
+        let bumped = POETRY_PYPROJECT.replace("requests = \"^2.31\"", "requests = \"^2.32\"");
+        std::fs::write(dir.path().join("pyproject.toml"), bumped).unwrap();
+        let error = lock.ensure_fresh(dir.path(), &[]).unwrap_err().to_string();
+        assert!(error.contains("run `poetry lock`"), "{}", error);
+    }
+
+    #[test]
+    fn resolves_poetry_locks() {
+        let dir = project(&[
+            ("pyproject.toml", POETRY_PYPROJECT),
+            ("poetry.lock", POETRY_LOCK),
+        ]);
+        let lock = detect(&dir);
+        let requests = check_resolution(&lock, dir.path());
+        assert_eq!(
+            requests.hashes,
+            [
+                "sha256:49c27f94ec8a3b8bcb00aa495798d1114a624af3873a8d444c2541d1c0f8bf02",
+                "sha256:a455365429e6f3fd19b1ca79671da8612354e80640ca15b531c1372adb167005",
+            ]
+        );
+
+        let requirements = lock.requirements_file(&[requests], dir.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(requirements).unwrap(),
+            "requests==2.31.0 \\\n    \
+             --hash=sha256:49c27f94ec8a3b8bcb00aa495798d1114a624af3873a8d444c2541d1c0f8bf02 \\\n    \
+             --hash=sha256:a455365429e6f3fd19b1ca79671da8612354e80640ca15b531c1372adb167005\n"
+        );
+
+        let error = lock
+            .resolve(dir.path(), &["postgres".to_string()], &target("3.12.2"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Unknown extra 'postgres'"), "{}", error);
+    }
+
+    #[test]
+    fn detects_stale_uv_locks() {
+        let dir = project(&[("pyproject.toml", UV_PYPROJECT), ("uv.lock", UV_LOCK)]);
+        let lock = detect(&dir);
+        assert_eq!(lock.kind, LockKind::Uv);
+        assert!(lock.uv_staleness(dir.path()).unwrap().is_empty());
+
+        let bumped = UV_PYPROJECT
+            .replace("requests>=2.31,<3", "requests>=2.32,<3")
+            .replace("cache = [\"redis>=5.0\"]", "");
+        std::fs::write(dir.path().join("pyproject.toml"), bumped).unwrap();
+        assert_eq!(
+            lock.uv_staleness(dir.path()).unwrap(),
+            [
+                "redis>=5.0 is no longer declared",
+                "requests<3,>=2.31 is no longer declared",
+                "requests<3,>=2.32 is not in the lock",
+            ]
+        );
+        let error = lock.ensure_fresh(dir.path(), &[]).unwrap_err().to_string();
+        assert!(error.contains("run `uv lock`"), "{}", error);
+    }
+
+    #[test]
+    fn resolves_uv_locks() {
+        let dir = project(&[("pyproject.toml", UV_PYPROJECT), ("uv.lock", UV_LOCK)]);
+        let lock = detect(&dir);
+        let requests = check_resolution(&lock, dir.path());
+        assert_eq!(
+            requests.hashes,
+            [
+                "sha256:4e2e89f22ac43def1824d791aec093cf8722ec88eab487db2ac8042e67bf0439",
+                "sha256:7adbaa86a5f505d3c6125af48e8bb96f7fa575624e9bc092f593d9d9d23b23f5",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_pip_tools_locks() {
+        let dir = project(&[
+            ("requirements.in", REQUIREMENTS_IN),
+            ("requirements.txt", PIP_TOOLS_LOCK),
+        ]);
+        let lock = detect(&dir);
+        assert_eq!(lock.kind, LockKind::PipTools);
+        lock.ensure_fresh(dir.path(), &[]).unwrap();
+
+        let entries = lock.hashed_requirements().unwrap();
+        let names: Vec<String> = entries
+            .iter()
+            .map(|(requirement, _)| requirement.to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "certifi==2024.2.2",
+                "charset-normalizer==3.3.2",
+                "colorama==0.4.6; sys_platform == \"win32\"",
+                "idna==3.6",
+                "requests==2.31.0",
+                "urllib3==2.2.1",
+            ]
+        );
+        assert!(entries.iter().all(|(_, hashes)| hashes.len() == 2));
+
+        // colorama's marker excludes it on Linux
+        let packages = lock.resolve(dir.path(), &[], &target("3.12.2")).unwrap();
+        assert_eq!(pins(&packages), REQUESTS_CLOSURE);
+        for package in &packages {
+            assert!(package
+                .hashes
+                .iter()
+                .all(|hash| PIP_TOOLS_LOCK.contains(&format!("--hash={}", hash))));
+        }
+
+        std::fs::write(
+            dir.path().join("requirements.in"),
+            REQUIREMENTS_IN.replace("requests>=2.31", "requests>=2.32\nflask"),
+        )
+        .unwrap();
+        let error = lock.ensure_fresh(dir.path(), &[]).unwrap_err().to_string();
+        assert!(
+            error.contains("requests>=2.32 is pinned to 2.31.0"),
+            "{}",
+            error
+        );
+        assert!(error.contains("flask is not pinned"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_unpinned_or_unhashed_pip_tools_entries() {
+        let dir = project(&[(
+            "requirements.txt",
+            "requests>=2.31 --hash=sha256:aaaa\nidna==3.6\nurllib3==2.* --hash=sha256:bbbb\n",
+        )]);
+        let lock = detect(&dir);
+        let error = lock.hashed_requirements().unwrap_err().to_string();
+        assert!(
+            error.contains("requests>=2.31 (not pinned with ==)"),
+            "{}",
+            error
+        );
+        assert!(error.contains("idna==3.6 (no --hash)"), "{}", error);
+        assert!(
+            error.contains("urllib3==2.* (not pinned with ==)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn prefers_uv_then_poetry_then_hashed_requirements() {
+        let dir = project(&[
+            ("requirements.txt", PIP_TOOLS_LOCK),
+            ("poetry.lock", POETRY_LOCK),
+            ("uv.lock", UV_LOCK),
+        ]);
+        assert_eq!(detect(&dir).kind, LockKind::Uv);
+
+        std::fs::remove_file(dir.path().join("uv.lock")).unwrap();
+        assert_eq!(detect(&dir).kind, LockKind::Poetry);
+
+        std::fs::remove_file(dir.path().join("poetry.lock")).unwrap();
+        assert_eq!(detect(&dir).kind, LockKind::PipTools);
+
+        // Without hashes a requirements file is just a dependency list
+        std::fs::write(dir.path().join("requirements.txt"), "requests==2.31.0\n").unwrap();
+        assert!(LockFile::detect(dir.path()).unwrap().is_none());
+    }
+}
@@ -1,23 +1,171 @@
-mod builder;
-mod cache;
-mod fs;
-mod image;
-mod layer;
-mod manifest;
-
-use crate::builder::PythonImageBuilder;
-use crate::cache::Cache;
-use crate::image::ImageConfig;
-use anyhow::{format_err, Result};
-use clap::Parser;
-use std::path::PathBuf;
+use anyhow::{format_err, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use oci_spec::image::{ImageConfiguration, ImageManifest};
+use python_oci_packager::builder::PythonImageBuilder;
+use python_oci_packager::cache::{parse_duration, parse_size, Cache, CacheLimits};
+use python_oci_packager::credentials::Credentials;
+use python_oci_packager::image::ImageConfig;
+use python_oci_packager::layer::Compression;
+use python_oci_packager::layout::ImageLayout;
+use python_oci_packager::registries::Registries;
+use python_oci_packager::wheel::Installer;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+/// Builds OCI images for Python projects without a container runtime.
 #[derive(Parser)]
+#[command(version)]
 struct Cli {
-    project_path: String,
-    output: String,
-    base_image: String,
-    cache_dir: String,
+    /// Layer cache directory [default: $XDG_CACHE_HOME/spacejar]
+    #[arg(long, global = true, env = "SPACEJAR_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+    /// Per-registry connection settings
+    /// [default: $XDG_CONFIG_HOME/spacejar/registries.toml]
+    #[arg(long, global = true, env = "SPACEJAR_REGISTRIES_CONFIG")]
+    registries_config: Option<PathBuf>,
+    /// Talk plain HTTP to this registry (`host[:port]`); repeatable
+    #[arg(
+        long = "insecure-registry",
+        global = true,
+        env = "SPACEJAR_INSECURE_REGISTRIES",
+        value_delimiter = ','
+    )]
+    insecure_registries: Vec<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build an image for a project into an OCI layout
+    Build(BuildArgs),
+    /// Push an image from an OCI layout to a registry
+    Push(PushArgs),
+    /// Show an image's manifest, config and layers
+    Inspect(InspectArgs),
+    /// Inspect and manage the layer cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List cached layers and configs, most recently used first
+    List,
+    /// Evict entries older than --max-age, then least recently used entries
+    /// until the cache fits in --max-size
+    Prune {
+        #[arg(long, value_parser = parse_duration)]
+        max_age: Option<Duration>,
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+    },
+    /// Re-hash every blob and drop corrupt ones
+    Verify,
+    /// Write the cache to a tarball
+    Export { tarball: PathBuf },
+    /// Add the entries from a tarball written by `cache export`
+    Import { tarball: PathBuf },
+}
+
+/// Flags left unset fall back to `[tool.spacejar]` in the project's
+/// pyproject.toml, then to the built-in defaults.
+#[derive(Args)]
+struct BuildArgs {
+    /// Project directory
+    #[arg(default_value = ".")]
+    project: PathBuf,
+    /// OCI layout directory to write [default: <PROJECT>/dist/oci]
+    #[arg(long, short, env = "SPACEJAR_OUTPUT")]
+    output: Option<PathBuf>,
+    /// Base image [default: python:3.9-slim]
+    #[arg(long, env = "SPACEJAR_BASE")]
+    base: Option<String>,
+    /// Tag recorded for the image in the layout's index.json [default: latest]
+    #[arg(long, short, env = "SPACEJAR_TAG")]
+    tag: Option<String>,
+    /// Platform to build for, e.g. `linux/arm64` [default: the host's]
+    #[arg(long, env = "SPACEJAR_PLATFORM")]
+    platform: Option<String>,
+    /// Layer compression: none, gzip or zstd
+    #[arg(long, env = "SPACEJAR_COMPRESSION")]
+    compression: Option<Compression>,
+    /// Push the built image to this registry reference, e.g. `localhost:5000/app:1.0`
+    #[arg(long, env = "SPACEJAR_PUSH")]
+    push: Option<String>,
+    /// Install dependencies only from this directory of wheels, without an index
+    #[arg(long, env = "SPACEJAR_WHEELHOUSE")]
+    wheelhouse: Option<PathBuf>,
+    /// Dependency installer: pip or native; native installs only from a
+    /// wheelhouse, so it requires --wheelhouse or `[tool.spacejar] wheelhouse`
+    #[arg(long, env = "SPACEJAR_INSTALLER")]
+    installer: Option<Installer>,
+    /// Evict least recently used cache entries after the build until the
+    /// cache fits in this size, e.g. `10G` [default: 10G]
+    #[arg(long, env = "SPACEJAR_CACHE_MAX_SIZE", value_parser = parse_size)]
+    cache_max_size: Option<u64>,
+    /// Drop cache entries older than this after the build, e.g. `7d`
+    /// [default: 30d]
+    #[arg(long, env = "SPACEJAR_CACHE_MAX_AGE", value_parser = parse_duration)]
+    cache_max_age: Option<Duration>,
+    #[command(flatten)]
+    login: LoginArgs,
+}
+
+#[derive(Args)]
+struct PushArgs {
+    /// OCI layout directory holding the image
+    layout: PathBuf,
+    /// Registry reference to push to, e.g. `localhost:5000/app:1.0`
+    reference: String,
+    /// Tag of the image within the layout
+    #[arg(long, short, env = "SPACEJAR_TAG", default_value = "latest")]
+    tag: String,
+    #[command(flatten)]
+    login: LoginArgs,
+}
+
+/// Logs in to the push target's registry (or the base image's when not
+/// pushing) instead of using credentials from the Docker config.
+#[derive(Args)]
+struct LoginArgs {
+    /// Registry username
+    #[arg(long, short, requires = "password_stdin")]
+    username: Option<String>,
+    /// Read the registry password from stdin
+    #[arg(long, requires = "username")]
+    password_stdin: bool,
+}
+
+impl LoginArgs {
+    fn credentials(self) -> Result<Option<Credentials>> {
+        let Some(username) = self.username else {
+            return Ok(None);
+        };
+
+        let mut password = String::new();
+        std::io::stdin()
+            .read_to_string(&mut password)
+            .context("Failed to read password from stdin")?;
+        let password = password.trim_end_matches(['\n', '\r']);
+        if password.is_empty() {
+            return Err(format_err!("No password given on stdin"));
+        }
+
+        Ok(Some(Credentials::new(username, password)))
+    }
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// OCI layout directory holding the image
+    layout: PathBuf,
+    /// Tag of the image within the layout
+    #[arg(long, short, env = "SPACEJAR_TAG", default_value = "latest")]
+    tag: String,
 }
 
 #[tokio::main]
@@ -26,35 +174,130 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let cli = Cli::parse();
+    let cache_dir = cli.cache_dir.unwrap_or_else(default_cache_dir);
+    let registries = || load_registries(cli.registries_config, cli.insecure_registries);
+
+    match cli.command {
+        Command::Build(args) => build_command(args, cache_dir, registries()?).await,
+        Command::Push(args) => {
+            let registries = registries()?;
+            let credentials = args.login.credentials()?;
+            PythonImageBuilder::push_layout(
+                &args.layout,
+                &args.tag,
+                &args.reference,
+                credentials.as_ref(),
+                &registries,
+            )
+            .await?;
+            println!("Pushed {} to {}", args.tag, args.reference);
+            Ok(())
+        }
+        Command::Inspect(args) => inspect_command(args).await,
+        Command::Cache { command } => cache_command(command, &cache_dir).await,
+    }
+}
 
-    // let output_path = PathBuf::from(&cli.output);
-    let project_path: PathBuf = PathBuf::from(&cli.project_path);
-    let cache_dir: PathBuf = PathBuf::from(&cli.cache_dir);
+/// `$XDG_CACHE_HOME/spacejar`, or `~/.cache/spacejar` when that is unset.
+fn default_cache_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("spacejar")
+}
 
-    println!("Building image for project: {}", project_path.display());
-    println!("Output image: {}", cli.output);
-    println!("Base image: {}", cli.base_image);
+/// Reads the registries config, when given or present at the default
+/// location, and marks `insecure` hosts as plain HTTP.
+fn load_registries(config: Option<PathBuf>, insecure: Vec<String>) -> Result<Registries> {
+    let registries = match config {
+        Some(path) => Registries::load(&path)?,
+        None => {
+            let path = default_config_dir().join("registries.toml");
+            if path.exists() {
+                Registries::load(&path)?
+            } else {
+                Registries::default()
+            }
+        }
+    };
 
-    let cache: Cache = Cache::new(cache_dir).await?;
+    Ok(insecure
+        .into_iter()
+        .fold(registries, Registries::with_insecure))
+}
+
+/// `$XDG_CONFIG_HOME/spacejar`, or `~/.config/spacejar` when that is unset.
+fn default_config_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("spacejar")
+}
+
+async fn build_command(cli: BuildArgs, cache_dir: PathBuf, registries: Registries) -> Result<()> {
+    let project_path = cli.project;
     let image_config: ImageConfig = ImageConfig::from_project(&project_path)?;
-    let base_image: String = match cli.base_image {
-        s if s.is_empty() => "python:3.9-slim".to_string(),
-        s => s,
-    };
 
-    let mut builder = PythonImageBuilder::new(
-        PathBuf::from(cli.project_path),
-        PathBuf::from(cli.output),
-        base_image,
-        image_config,
-        cache,
-    )
-    .map_err(|e| format_err!("Failed to create image builder: {}", e))?;
+    let output = cli
+        .output
+        .or_else(|| image_config.output.clone())
+        .unwrap_or_else(|| project_path.join("dist/oci"));
+    let base_image = cli
+        .base
+        .or_else(|| image_config.base_image.clone())
+        .unwrap_or_else(|| "python:3.9-slim".to_string());
 
-    // TODO use cache
-    // cache.get_layer()
-    // cache.store_layer()
-    // cache.get_dependency_layer()
+    println!("Building image for project: {}", project_path.display());
+    println!("Output image: {}", output.display());
+    println!("Base image: {}", base_image);
+
+    std::fs::create_dir_all(&output)
+        .with_context(|| format!("Failed to create output directory {}", output.display()))?;
+
+    let cache: Cache = Cache::new(cache_dir)
+        .await?
+        .with_limits(CacheLimits::for_build(
+            cli.cache_max_size.or(image_config.cache_max_size),
+            cli.cache_max_age.or(image_config.cache_max_age),
+        ));
+
+    let mut builder =
+        PythonImageBuilder::new(project_path, output, base_image, image_config, cache)
+            .map_err(|e| format_err!("Failed to create image builder: {}", e))?
+            .with_registries(registries);
+
+    if let Some(tag) = cli.tag {
+        builder = builder.with_tag(tag);
+    }
+
+    if let Some(platform) = cli.platform {
+        builder = builder.with_platform(platform);
+    }
+
+    if let Some(compression) = cli.compression {
+        builder = builder.with_compression(compression);
+    }
+
+    if let Some(wheelhouse) = cli.wheelhouse {
+        builder = builder.with_wheelhouse(wheelhouse);
+    }
+
+    if let Some(installer) = cli.installer {
+        builder = builder.with_installer(installer);
+    }
+
+    if let Some(credentials) = cli.login.credentials()? {
+        builder = builder.with_credentials(credentials);
+    }
+
+    if let Some(target) = cli.push {
+        println!("Push target: {}", target);
+        builder = builder.with_push_target(target);
+    }
 
     match builder.build().await {
         Ok(_) => {
@@ -67,3 +310,165 @@ async fn main() -> Result<()> {
         }
     }
 }
+
+async fn inspect_command(args: InspectArgs) -> Result<()> {
+    let layout = ImageLayout::new(&args.layout);
+    let (digest, manifest_json) = layout.read_manifest(&args.tag).await?;
+    let manifest = ImageManifest::from_reader(manifest_json.as_slice())
+        .context("Failed to parse image manifest")?;
+
+    let config_digest = manifest.config().digest().to_string();
+    let config_json = layout
+        .read_blob(&config_digest, Some(manifest.config().size()))
+        .await?;
+    let config = ImageConfiguration::from_reader(config_json.as_slice())
+        .context("Failed to parse image config")?;
+
+    println!("Image:     {}:{}", args.layout.display(), args.tag);
+    println!("Manifest:  {}", digest);
+    println!("Config:    {}", config_digest);
+    println!("Platform:  {}/{}", config.os(), config.architecture());
+    if let Some(created) = config.created() {
+        println!("Created:   {}", created);
+    }
+
+    if let Some(runtime) = config.config() {
+        let show = |name: &str, values: &Option<Vec<String>>| {
+            if let Some(values) = values.as_ref().filter(|v| !v.is_empty()) {
+                println!("{:<10} {}", format!("{}:", name), values.join(" "));
+            }
+        };
+        show("Entrypoint", runtime.entrypoint());
+        show("Cmd", runtime.cmd());
+        if let Some(dir) = runtime.working_dir() {
+            println!("WorkDir:   {}", dir);
+        }
+        if let Some(user) = runtime.user() {
+            println!("User:      {}", user);
+        }
+        for var in runtime.env().iter().flatten() {
+            println!("Env:       {}", var);
+        }
+    }
+
+    // History entries without `empty_layer` pair up with layers in order
+    let mut history = config
+        .history()
+        .iter()
+        .filter(|h| !h.empty_layer().unwrap_or(false));
+    let total: u64 = manifest.layers().iter().map(|layer| layer.size()).sum();
+
+    println!(
+        "Layers:    {} ({})",
+        manifest.layers().len(),
+        format_size(total)
+    );
+    for layer in manifest.layers() {
+        let created_by = history
+            .next()
+            .and_then(|h| h.created_by().clone())
+            .unwrap_or_default();
+        println!(
+            "  {}  {:>10}  {}",
+            short_digest(layer.digest().as_ref()),
+            format_size(layer.size()),
+            created_by.lines().next().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+async fn cache_command(command: CacheCommand, cache_dir: &Path) -> Result<()> {
+    let mut cache = Cache::new(cache_dir.to_path_buf()).await?;
+
+    match command {
+        CacheCommand::List => {
+            println!(
+                "{:<12}  {:<12}  {:<12}  {:>9}  {:>8}  {:>9}  SOURCE",
+                "KEY", "BLOB", "TYPE", "SIZE", "AGE", "LAST USED"
+            );
+            for entry in cache.entries().await {
+                println!(
+                    "{:<12}  {:<12}  {:<12}  {:>9}  {:>8}  {:>9}  {}",
+                    short_digest(&entry.key),
+                    short_digest(&entry.digest),
+                    entry.kind,
+                    format_size(entry.size),
+                    format_age(entry.age),
+                    format_age(entry.last_used),
+                    entry.source_hash.as_deref().map_or("-", short_digest)
+                );
+            }
+        }
+        CacheCommand::Prune { max_age, max_size } => {
+            let report = cache
+                .with_limits(CacheLimits { max_age, max_size })
+                .cleanup()
+                .await?;
+            println!(
+                "Removed {} entries and {} blobs, freeing {}",
+                report.entries,
+                report.blobs,
+                format_size(report.bytes)
+            );
+        }
+        CacheCommand::Verify => {
+            let report = cache.verify().await?;
+            for digest in &report.corrupt {
+                println!("corrupt: {}", digest);
+            }
+            println!(
+                "Checked {} blobs: {} corrupt, {} entries dropped",
+                report.blobs,
+                report.corrupt.len(),
+                report.entries
+            );
+        }
+        CacheCommand::Export { tarball } => {
+            let blobs = cache.export(&tarball).await?;
+            println!("Exported {} blobs to {}", blobs, tarball.display());
+        }
+        CacheCommand::Import { tarball } => {
+            let report = cache.import(&tarball).await?;
+            println!(
+                "Imported {} entries and {} new blobs from {}",
+                report.entries,
+                report.blobs,
+                tarball.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn short_digest(digest: &str) -> &str {
+    let hex = digest.trim_start_matches("sha256:");
+    &hex[..hex.len().min(12)]
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
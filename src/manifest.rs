@@ -1,43 +1,47 @@
 use anyhow::Result;
-use oci_spec::image::Config as OCIConfig;
+use oci_spec::image::ImageManifest;
 use std::collections::HashMap;
 
 use crate::layer::Layer;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct ConfigDescriptor {
-    pub config: OCIConfig,
     pub media_type: String,
     pub size: u64,
     pub digest: String,
 }
 
-#[derive(Serialize, Clone)]
+/// Describes a layer blob. The compressed payload itself is never embedded
+/// here; it is written to (or pushed as) a blob of its own.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct LayerDescriptor {
     pub media_type: String,
     pub size: u64,
     pub digest: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub annotations: Option<HashMap<String, String>>,
-    pub data: Option<Vec<u8>>,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Manifest {
-    pub schema_version: i32,
+    pub schema_version: u32,
     pub media_type: String,
     pub config: ConfigDescriptor,
     pub layers: Vec<LayerDescriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub annotations: Option<HashMap<String, String>>,
 }
 
 impl Manifest {
-    pub fn new(config: OCIConfig, layers: Vec<Layer>, size: u64, digest: String) -> Result<Self> {
+    pub fn new(layers: &[Layer], config_size: u64, config_digest: String) -> Result<Self> {
         let config_descriptor = ConfigDescriptor {
-            config,
             media_type: "application/vnd.oci.image.config.v1+json".to_string(),
-            size,
-            digest,
+            size: config_size,
+            digest: config_digest,
         };
 
         let layer_descriptors = layers
@@ -46,18 +50,22 @@ impl Manifest {
                 media_type: layer.media_type.clone(),
                 size: layer.compressed_size,
                 digest: layer.digest.clone(),
-                annotations: Some(layer.annotations.clone()),
-                data: Some(layer.data.clone()),
+                annotations: Some(layer.annotations.clone()).filter(|a| !a.is_empty()),
             })
             .collect();
 
-        Ok(Self {
+        let manifest = Self {
             schema_version: 2,
             media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
             config: config_descriptor,
             layers: layer_descriptors,
             annotations: None,
-        })
+        };
+
+        // Make sure what we emit is something other OCI tooling can read back
+        manifest.to_oci()?;
+
+        Ok(manifest)
     }
 
     pub fn to_json(&self) -> Result<String> {
@@ -75,4 +83,8 @@ impl Manifest {
     pub fn to_writer<W: std::io::Write>(&self, writer: W) -> Result<()> {
         serde_json::to_writer(writer, self).map_err(Into::into)
     }
+
+    pub fn to_oci(&self) -> Result<ImageManifest> {
+        ImageManifest::from_reader(self.to_bytes()?.as_slice()).map_err(Into::into)
+    }
 }
@@ -0,0 +1,474 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+/// The interpreter inside the image that dependencies are installed for,
+/// which is generally not the Python running on the build host.
+#[derive(Debug, Clone)]
+pub struct TargetPython {
+    /// `major.minor`, e.g. `3.9`
+    pub version: String,
+    /// Full version when known, e.g. `3.9.18`
+    pub full_version: String,
+    /// Wheel platform tags accepted by the image, most specific first
+    pub platforms: Vec<String>,
+    /// CPython ABI tag, e.g. `cp39`
+    pub abi: String,
+    /// Directory holding the base image's interpreter
+    pub home: String,
+    /// `platform.machine()` inside the image, e.g. `x86_64`
+    pub machine: String,
+}
+
+impl TargetPython {
+    /// Resolves the target interpreter. An explicit `version` wins, then the
+    /// `PYTHON_VERSION` variable the official images set in their config,
+    /// then the version prefix of the base image tag (`3.9-slim`).
+    pub fn resolve(
+        version: Option<&str>,
+        platform: Option<&str>,
+        base_tag: &str,
+        base_env: &[String],
+        docker_arch: &str,
+    ) -> Result<Self> {
+        let version = version
+            .map(str::to_string)
+            .or_else(|| Self::version_from_env(base_env))
+            .or_else(|| Self::version_from_tag(base_tag))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Could not determine the target Python version from base image tag '{}'; \
+                     set `python_version` in [tool.spacejar]",
+                    base_tag
+                )
+            })?;
+
+        let (major, minor) = Self::parse_version(&version)?;
+        let machine = Self::machine(docker_arch)?;
+        let home =
+            Self::home_from_env(base_env, &format!("{}.{}", major, minor)).ok_or_else(|| {
+                anyhow!(
+                    "Could not locate python{}.{} in the base image: no PATH entry names it \
+                     and the image sets no matching PYTHON_VERSION",
+                    major,
+                    minor
+                )
+            })?;
+
+        let platforms = match platform {
+            Some(platform) => vec![platform.to_string()],
+            None => Self::default_platforms(machine, base_tag.contains("alpine")),
+        };
+
+        Ok(Self {
+            version: format!("{}.{}", major, minor),
+            full_version: Self::full_version(version.trim(), base_env, major, minor),
+            platforms,
+            abi: format!("cp{}{}", major, minor),
+            home,
+            machine: machine.to_string(),
+        })
+    }
+
+    /// PEP 508 marker variables describing the image's interpreter. The
+    /// kernel release and version aren't known until the image runs, so
+    /// they are empty.
+    pub fn marker_env(&self) -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("python_version", self.version.clone()),
+            ("python_full_version", self.full_version.clone()),
+            ("implementation_name", "cpython".to_string()),
+            ("implementation_version", self.full_version.clone()),
+            ("platform_python_implementation", "CPython".to_string()),
+            ("sys_platform", "linux".to_string()),
+            ("platform_system", "Linux".to_string()),
+            ("os_name", "posix".to_string()),
+            ("platform_machine", self.machine.clone()),
+            ("platform_release", String::new()),
+            ("platform_version", String::new()),
+        ])
+    }
+
+    /// Whether a wheel tagged `python-abi-platform` (each part possibly a
+    /// `.`-separated set) can be installed for this interpreter.
+    pub fn supports_wheel_tags(&self, python: &str, abi: &str, platform: &str) -> bool {
+        let Ok((major, minor)) = Self::parse_version(&self.version) else {
+            return false;
+        };
+
+        let python_ok = python.split('.').any(|tag| {
+            let Some(digits) = tag.strip_prefix("py").or_else(|| tag.strip_prefix("cp")) else {
+                return false;
+            };
+            let cpython_only = tag.starts_with("cp");
+            match (digits.get(..1), digits.get(1..)) {
+                (Some(tag_major), Some("")) => !cpython_only && tag_major == major.to_string(),
+                (Some(tag_major), Some(tag_minor)) => {
+                    tag_major == major.to_string()
+                        && tag_minor.parse::<u32>().is_ok_and(|tag_minor| {
+                            // Pure `py3X` wheels and abi3 wheels run on every later
+                            // minor version too, as pip's tag ordering allows
+                            tag_minor == minor
+                                || (tag_minor < minor && (!cpython_only || abi.contains("abi3")))
+                        })
+                }
+                _ => false,
+            }
+        });
+
+        let abi_ok = abi
+            .split('.')
+            .any(|tag| tag == "none" || tag == "abi3" || tag == self.abi);
+
+        let platform_ok = platform.split('.').any(|tag| {
+            tag == "any"
+                || self.platforms.iter().any(|p| p == tag)
+                || Self::libc_tag(tag).is_some_and(|(family, version, arch)| {
+                    self.platforms.iter().filter_map(|p| Self::libc_tag(p)).any(
+                        |(target_family, target_version, target_arch)| {
+                            family == target_family
+                                && arch == target_arch
+                                && version <= target_version
+                        },
+                    )
+                })
+        });
+
+        python_ok && abi_ok && platform_ok
+    }
+
+    /// Splits a `manylinux`/`musllinux` platform tag into family, libc
+    /// version and architecture, resolving the legacy manylinux aliases.
+    fn libc_tag(tag: &str) -> Option<(&'static str, (u32, u32), &str)> {
+        for (alias, version) in [
+            ("manylinux1_", (2, 5)),
+            ("manylinux2010_", (2, 12)),
+            ("manylinux2014_", (2, 17)),
+        ] {
+            if let Some(arch) = tag.strip_prefix(alias) {
+                return Some(("manylinux", version, arch));
+            }
+        }
+
+        let (family, rest) = if let Some(rest) = tag.strip_prefix("manylinux_") {
+            ("manylinux", rest)
+        } else {
+            ("musllinux", tag.strip_prefix("musllinux_")?)
+        };
+
+        let mut parts = rest.splitn(3, '_');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some((family, (major, minor), parts.next()?))
+    }
+
+    /// The `python3.X` executable name
+    pub fn interpreter(&self) -> String {
+        format!("python{}", self.version)
+    }
+
+    /// `site-packages` relative to a virtualenv root
+    pub fn site_packages(&self) -> String {
+        format!("lib/python{}/site-packages", self.version)
+    }
+
+    /// Arguments restricting `pip install` to binary wheels built for this
+    /// interpreter and platform.
+    pub fn pip_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--python-version".to_string(),
+            self.version.clone(),
+            "--implementation".to_string(),
+            "cp".to_string(),
+            "--abi".to_string(),
+            self.abi.clone(),
+        ];
+        for platform in &self.platforms {
+            args.push("--platform".to_string());
+            args.push(platform.clone());
+        }
+        args.push("--only-binary=:all:".to_string());
+        args
+    }
+
+    /// `version` when it has a patch level, else the image's matching
+    /// `PYTHON_VERSION`. Without either, `python_full_version` markers can
+    /// only be compared against `major.minor`, which is reported.
+    fn full_version(version: &str, env: &[String], major: u32, minor: u32) -> String {
+        if version.split('.').count() > 2 {
+            return version.to_string();
+        }
+
+        let prefix = format!("{}.{}.", major, minor);
+        match Self::version_from_env(env).filter(|full| full.starts_with(&prefix)) {
+            Some(full) => full,
+            None => {
+                tracing::warn!(
+                    "Only the major.minor version {} of the image's Python is known;                      python_full_version markers will be evaluated against it. Set                      `python_version` in [tool.spacejar] to the full version to avoid this",
+                    version
+                );
+                version.to_string()
+            }
+        }
+    }
+
+    fn version_from_env(env: &[String]) -> Option<String> {
+        env.iter()
+            .find_map(|var| var.strip_prefix("PYTHON_VERSION="))
+            .map(str::to_string)
+    }
+
+    /// The directory holding `python<version>`: a `PATH` entry named after
+    /// that version (`/opt/python3.11/bin`), else `/usr/local/bin` for images
+    /// whose `PYTHON_VERSION` matches, as the official images build Python
+    /// there.
+    fn home_from_env(env: &[String], version: &str) -> Option<String> {
+        let interpreter = format!("python{}", version);
+        let named = env
+            .iter()
+            .find_map(|var| var.strip_prefix("PATH="))
+            .into_iter()
+            .flat_map(|path| path.split(':'))
+            .find(|dir| {
+                dir.split('/').any(|component| {
+                    component
+                        .strip_prefix(&interpreter)
+                        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_digit()))
+                })
+            });
+        if let Some(dir) = named {
+            return Some(dir.to_string());
+        }
+
+        Self::version_from_env(env)
+            .and_then(|full| Self::parse_version(&full).ok())
+            .filter(|(major, minor)| format!("{}.{}", major, minor) == version)
+            .map(|_| "/usr/local/bin".to_string())
+    }
+
+    fn version_from_tag(tag: &str) -> Option<String> {
+        let version: String = tag
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+        Self::parse_version(&version).ok().map(|_| version)
+    }
+
+    fn parse_version(version: &str) -> Result<(u32, u32)> {
+        let mut parts = version.trim().split('.');
+        match (
+            parts.next().and_then(|p| p.parse().ok()),
+            parts.next().and_then(|p| p.parse().ok()),
+        ) {
+            (Some(major), Some(minor)) => Ok((major, minor)),
+            _ => Err(anyhow!("Invalid Python version: {}", version)),
+        }
+    }
+
+    fn machine(docker_arch: &str) -> Result<&'static str> {
+        match docker_arch {
+            "amd64" => Ok("x86_64"),
+            "arm64" => Ok("aarch64"),
+            "arm" => Ok("armv7l"),
+            "386" => Ok("i686"),
+            "ppc64le" => Ok("ppc64le"),
+            "s390x" => Ok("s390x"),
+            _ => Err(anyhow!("Unsupported architecture: {}", docker_arch)),
+        }
+    }
+
+    fn default_platforms(machine: &str, musl: bool) -> Vec<String> {
+        let tags: &[&str] = if musl {
+            &["musllinux_1_2", "musllinux_1_1"]
+        } else {
+            &["manylinux_2_28", "manylinux_2_17", "manylinux2014"]
+        };
+
+        tags.iter()
+            .map(|tag| format!("{}_{}", tag, machine))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A platform tag, then its expected family, libc version and architecture
+    type LibcCase<'a> = (&'a str, Option<(&'a str, (u32, u32), &'a str)>);
+
+    fn target(version: &str, platforms: &[&str]) -> TargetPython {
+        let (major, minor) = TargetPython::parse_version(version).unwrap();
+        TargetPython {
+            version: format!("{}.{}", major, minor),
+            full_version: version.to_string(),
+            platforms: platforms.iter().map(|p| p.to_string()).collect(),
+            abi: format!("cp{}{}", major, minor),
+            home: "/usr/local/bin".to_string(),
+            machine: "x86_64".to_string(),
+        }
+    }
+
+    #[test]
+    fn matches_wheel_tags() {
+        let glibc = target("3.9.18", &["manylinux_2_28_x86_64", "manylinux2014_x86_64"]);
+        let musl = target("3.12", &["musllinux_1_2_aarch64"]);
+
+        // Target, then the wheel's python, abi and platform tags and whether
+        // they install
+        let cases: &[(&TargetPython, &str, &str, &str, bool)] = &[
+            (&glibc, "py3", "none", "any", true),
+            (&glibc, "py2.py3", "none", "any", true),
+            (&glibc, "py2", "none", "any", false),
+            (&glibc, "py39", "none", "any", true),
+            (&glibc, "py38", "none", "any", true),
+            (&glibc, "py30", "none", "any", true),
+            (&glibc, "py310", "none", "any", false),
+            (&glibc, "cp3", "none", "any", false),
+            (&glibc, "cp39", "cp39", "manylinux_2_17_x86_64", true),
+            (&glibc, "cp39", "cp39", "manylinux2014_x86_64", true),
+            (&glibc, "cp39", "cp39", "manylinux1_x86_64", true),
+            (&glibc, "cp39", "cp39", "manylinux_2_28_x86_64", true),
+            (&glibc, "cp39", "cp39", "manylinux_2_34_x86_64", false),
+            (&glibc, "cp39", "cp39", "manylinux_2_17_aarch64", false),
+            (&glibc, "cp39", "cp39", "musllinux_1_1_x86_64", false),
+            (&glibc, "cp39", "cp39", "win_amd64", false),
+            (
+                &glibc,
+                "cp39",
+                "cp39",
+                "manylinux_2_5_x86_64.manylinux1_x86_64",
+                true,
+            ),
+            (&glibc, "cp38", "cp38", "manylinux2014_x86_64", false),
+            (&glibc, "cp310", "cp310", "manylinux2014_x86_64", false),
+            (&glibc, "cp37", "abi3", "manylinux2014_x86_64", true),
+            (&glibc, "cp39", "abi3", "manylinux2014_x86_64", true),
+            (&glibc, "cp310", "abi3", "manylinux2014_x86_64", false),
+            (&glibc, "cp39", "none", "any", true),
+            (&glibc, "cp39", "cp38", "manylinux2014_x86_64", false),
+            (&musl, "cp312", "cp312", "musllinux_1_1_aarch64", true),
+            (&musl, "cp312", "cp312", "musllinux_1_2_aarch64", true),
+            (&musl, "cp312", "cp312", "musllinux_1_3_aarch64", false),
+            (&musl, "cp312", "cp312", "manylinux2014_aarch64", false),
+            (&musl, "py311", "none", "any", true),
+        ];
+
+        for (target, python, abi, platform, expected) in cases {
+            assert_eq!(
+                target.supports_wheel_tags(python, abi, platform),
+                *expected,
+                "{}-{}-{} on {}",
+                python,
+                abi,
+                platform,
+                target.version
+            );
+        }
+    }
+
+    #[test]
+    fn splits_libc_tags() {
+        let cases: &[LibcCase] = &[
+            ("manylinux1_x86_64", Some(("manylinux", (2, 5), "x86_64"))),
+            ("manylinux2010_i686", Some(("manylinux", (2, 12), "i686"))),
+            (
+                "manylinux2014_aarch64",
+                Some(("manylinux", (2, 17), "aarch64")),
+            ),
+            (
+                "manylinux_2_28_x86_64",
+                Some(("manylinux", (2, 28), "x86_64")),
+            ),
+            (
+                "musllinux_1_2_armv7l",
+                Some(("musllinux", (1, 2), "armv7l")),
+            ),
+            ("manylinux_2_x86_64", None),
+            ("linux_x86_64", None),
+            ("any", None),
+            ("macosx_11_0_arm64", None),
+        ];
+
+        for (tag, expected) in cases {
+            assert_eq!(TargetPython::libc_tag(tag), *expected, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn pip_args_pin_interpreter_and_platforms() {
+        let target = target("3.11.4", &["manylinux_2_28_x86_64", "manylinux2014_x86_64"]);
+
+        assert_eq!(
+            target.pip_args(),
+            [
+                "--python-version",
+                "3.11",
+                "--implementation",
+                "cp",
+                "--abi",
+                "cp311",
+                "--platform",
+                "manylinux_2_28_x86_64",
+                "--platform",
+                "manylinux2014_x86_64",
+                "--only-binary=:all:",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_version_and_platforms() {
+        let from_env = TargetPython::resolve(
+            None,
+            None,
+            "3.9-slim",
+            &["PYTHON_VERSION=3.11.2".to_string()],
+            "arm64",
+        )
+        .unwrap();
+        assert_eq!(from_env.version, "3.11");
+        assert_eq!(from_env.full_version, "3.11.2");
+        assert_eq!(from_env.abi, "cp311");
+        assert_eq!(from_env.platforms[0], "manylinux_2_28_aarch64");
+        assert_eq!(from_env.home, "/usr/local/bin");
+
+        let from_tag = TargetPython::resolve(
+            None,
+            None,
+            "3.12-alpine",
+            &["PATH=/opt/python3.12/bin:/usr/bin".to_string()],
+            "amd64",
+        )
+        .unwrap();
+        assert_eq!(from_tag.version, "3.12");
+        assert_eq!(from_tag.platforms[0], "musllinux_1_2_x86_64");
+        assert_eq!(from_tag.home, "/opt/python3.12/bin");
+        // Nothing says which 3.12.x it is
+        assert_eq!(from_tag.full_version, "3.12");
+
+        let explicit = TargetPython::resolve(
+            Some("3.10"),
+            Some("linux_x86_64"),
+            "latest",
+            &["PYTHON_VERSION=3.10.13".to_string()],
+            "amd64",
+        )
+        .unwrap();
+        assert_eq!(explicit.platforms, ["linux_x86_64"]);
+        // The patch level comes from the image when only major.minor is given
+        assert_eq!(explicit.full_version, "3.10.13");
+
+        // A distro base keeps its interpreter out of sight of the config, and
+        // an official image's /usr/local/bin holds a different version
+        let distro = ["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin".to_string()];
+        let error = TargetPython::resolve(Some("3.11"), None, "22.04", &distro, "amd64")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("python3.11"), "{}", error);
+        let official = ["PYTHON_VERSION=3.12.1".to_string()];
+        assert!(TargetPython::resolve(Some("3.1"), None, "3.12", &official, "amd64").is_err());
+
+        assert!(TargetPython::resolve(None, None, "latest", &[], "amd64").is_err());
+        assert!(TargetPython::resolve(Some("3.12"), None, "latest", &official, "mips").is_err());
+    }
+}
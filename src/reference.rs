@@ -0,0 +1,461 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Registry API host that references without a registry (and `docker.io`
+/// ones) resolve to.
+pub const DOCKER_HUB: &str = "registry-1.docker.io";
+
+const NAME_MAX_LENGTH: usize = 255;
+const TAG_MAX_LENGTH: usize = 128;
+
+/// An image reference following the distribution reference grammar:
+/// `[registry[:port]/]path[/path...][:tag][@algorithm:hex]`.
+///
+/// Docker Hub names are normalized the way `docker pull` does, so
+/// `python:3.12` is `registry-1.docker.io/library/python:3.12`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    /// What to ask the registry's `manifests/` endpoint for: the digest when
+    /// pinned, otherwise the tag (`latest` when neither is given).
+    pub fn manifest_reference(&self) -> &str {
+        self.digest
+            .as_deref()
+            .or(self.tag.as_deref())
+            .unwrap_or("latest")
+    }
+
+    /// Splits `name` into registry and repository. The first component is a
+    /// registry only if it looks like a host: it has a `.` or a port, or is
+    /// `localhost`.
+    fn split_name(name: &str) -> Result<(String, String)> {
+        let (registry, path) = match name.split_once('/') {
+            Some((first, rest))
+                if first.contains('.') || first.contains(':') || first == "localhost" =>
+            {
+                Self::validate_registry(first)?;
+                (first.to_string(), rest.to_string())
+            }
+            _ => (DOCKER_HUB.to_string(), name.to_string()),
+        };
+
+        for component in path.split('/') {
+            Self::validate_path_component(component)
+                .map_err(|e| anyhow!("Invalid repository '{}': {}", path, e))?;
+        }
+
+        match registry.as_str() {
+            "docker.io" | "index.docker.io" | DOCKER_HUB => {
+                let repository = if path.contains('/') {
+                    path
+                } else {
+                    format!("library/{}", path)
+                };
+                Ok((DOCKER_HUB.to_string(), repository))
+            }
+            _ => Ok((registry, path)),
+        }
+    }
+
+    /// `host[:port]`, where the host is dot-separated labels of letters,
+    /// digits and inner hyphens, or a bracketed IPv6 address.
+    fn validate_registry(registry: &str) -> Result<()> {
+        let (host, port) = if let Some(rest) = registry.strip_prefix('[') {
+            let (address, after) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow!("Invalid registry '{}'", registry))?;
+            if address.is_empty() || !address.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+                return Err(anyhow!("Invalid registry address '{}'", registry));
+            }
+            match after {
+                "" => (None, None),
+                _ => (
+                    None,
+                    Some(
+                        after
+                            .strip_prefix(':')
+                            .ok_or_else(|| anyhow!("Invalid registry '{}'", registry))?,
+                    ),
+                ),
+            }
+        } else {
+            match registry.split_once(':') {
+                Some((host, port)) => (Some(host), Some(port)),
+                None => (Some(registry), None),
+            }
+        };
+
+        if let Some(host) = host {
+            let valid_label = |label: &str| {
+                !label.is_empty()
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+            };
+            if !host.split('.').all(valid_label) {
+                return Err(anyhow!("Invalid registry host '{}'", registry));
+            }
+        }
+
+        if let Some(port) = port {
+            if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+                return Err(anyhow!("Invalid registry port in '{}'", registry));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lowercase alphanumerics, joined by single separators: `.`, `_`, `__`
+    /// or any run of `-`.
+    fn validate_path_component(component: &str) -> Result<()> {
+        if component.is_empty() {
+            return Err(anyhow!("empty path component"));
+        }
+
+        let is_alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+        let chars: Vec<char> = component.chars().collect();
+        if !is_alnum(chars[0]) || !is_alnum(chars[chars.len() - 1]) {
+            return Err(anyhow!(
+                "'{}' must start and end with a lowercase letter or digit",
+                component
+            ));
+        }
+
+        let mut separator = String::new();
+        for &c in &chars {
+            if is_alnum(c) {
+                let valid = matches!(separator.as_str(), "" | "." | "_" | "__")
+                    || separator.chars().all(|s| s == '-');
+                if !valid {
+                    return Err(anyhow!(
+                        "invalid separator '{}' in '{}'",
+                        separator,
+                        component
+                    ));
+                }
+                separator.clear();
+            } else if matches!(c, '.' | '_' | '-') {
+                separator.push(c);
+            } else {
+                return Err(anyhow!("invalid character '{}' in '{}'", c, component));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Word characters, `.` and `-`, not starting with either of those two.
+    fn validate_tag(tag: &str) -> Result<()> {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let valid = tag.len() <= TAG_MAX_LENGTH
+            && tag.starts_with(is_word)
+            && tag.chars().all(|c| is_word(c) || c == '.' || c == '-');
+        if valid {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid tag '{}'", tag))
+        }
+    }
+
+    /// `algorithm:hex`; sha256 and sha512 digests must have the right
+    /// length of lowercase hex.
+    fn validate_digest(digest: &str) -> Result<()> {
+        let (algorithm, hex) = digest
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid digest '{}'", digest))?;
+
+        let valid_algorithm = !algorithm.is_empty()
+            && algorithm.split(['+', '.', '_', '-']).all(|part| {
+                !part.is_empty()
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            });
+        let expected_length = match algorithm {
+            "sha256" => Some(64),
+            "sha512" => Some(128),
+            _ => None,
+        };
+        let valid_hex = hex.len() >= 32
+            && expected_length.is_none_or(|length| hex.len() == length)
+            && hex
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+
+        if valid_algorithm && valid_hex {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid digest '{}'", digest))
+        }
+    }
+}
+
+impl FromStr for Reference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (rest, digest) = match s.split_once('@') {
+            Some((rest, digest)) => {
+                Self::validate_digest(digest)?;
+                (rest, Some(digest.to_string()))
+            }
+            None => (s, None),
+        };
+
+        // A `:` after the last `/` separates the tag; one before it is a port
+        let (name, tag) = match rest.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => {
+                Self::validate_tag(tag)?;
+                (name, Some(tag.to_string()))
+            }
+            _ => (rest, None),
+        };
+
+        if name.is_empty() {
+            return Err(anyhow!("Invalid image reference '{}': missing name", s));
+        }
+        if name.len() > NAME_MAX_LENGTH {
+            return Err(anyhow!(
+                "Invalid image reference '{}': name longer than {} characters",
+                s,
+                NAME_MAX_LENGTH
+            ));
+        }
+
+        let (registry, repository) = Self::split_name(name)
+            .map_err(|e| anyhow!("Invalid image reference '{}': {}", s, e))?;
+
+        Ok(Self {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input, then the expected registry, repository, tag and digest
+    type Case<'a> = (&'a str, &'a str, &'a str, Option<&'a str>, Option<&'a str>);
+
+    const DIGEST: &str = "sha256:4c1f7fbb4e5a1a8e0d1ef6b0d6b3e9c1b6e5b2a8f0c3d9e7a6b5c4d3e2f1a0b9";
+
+    #[test]
+    fn parses_valid_references() {
+        let digest = Some(DIGEST);
+        let cases: &[Case] = &[
+            ("python", DOCKER_HUB, "library/python", None, None),
+            (
+                "python:3.12-slim",
+                DOCKER_HUB,
+                "library/python",
+                Some("3.12-slim"),
+                None,
+            ),
+            (
+                "bitnami/python:3.11",
+                DOCKER_HUB,
+                "bitnami/python",
+                Some("3.11"),
+                None,
+            ),
+            ("docker.io/python", DOCKER_HUB, "library/python", None, None),
+            (
+                "docker.io/library/python:3",
+                DOCKER_HUB,
+                "library/python",
+                Some("3"),
+                None,
+            ),
+            ("index.docker.io/org/app", DOCKER_HUB, "org/app", None, None),
+            ("ghcr.io/org/app:v1", "ghcr.io", "org/app", Some("v1"), None),
+            (
+                "ghcr.io/org/team/app:v1",
+                "ghcr.io",
+                "org/team/app",
+                Some("v1"),
+                None,
+            ),
+            ("localhost/app", "localhost", "app", None, None),
+            ("localhost:5000/app", "localhost:5000", "app", None, None),
+            (
+                "localhost:5000/app:1.0",
+                "localhost:5000",
+                "app",
+                Some("1.0"),
+                None,
+            ),
+            (
+                "registry:5000/team/app:tag",
+                "registry:5000",
+                "team/app",
+                Some("tag"),
+                None,
+            ),
+            ("10.0.0.1:5000/app", "10.0.0.1:5000", "app", None, None),
+            ("[::1]:5000/app:dev", "[::1]:5000", "app", Some("dev"), None),
+            (
+                "my-registry.example.com/a/b/c",
+                "my-registry.example.com",
+                "a/b/c",
+                None,
+                None,
+            ),
+            (
+                "org/my_app__x.y-z---w",
+                DOCKER_HUB,
+                "org/my_app__x.y-z---w",
+                None,
+                None,
+            ),
+            (
+                "app:Latest_1.0-rc",
+                DOCKER_HUB,
+                "library/app",
+                Some("Latest_1.0-rc"),
+                None,
+            ),
+            (
+                &format!("python@{}", DIGEST),
+                DOCKER_HUB,
+                "library/python",
+                None,
+                digest,
+            ),
+            (
+                &format!("python:3.12@{}", DIGEST),
+                DOCKER_HUB,
+                "library/python",
+                Some("3.12"),
+                digest,
+            ),
+            (
+                &format!("localhost:5000/app@{}", DIGEST),
+                "localhost:5000",
+                "app",
+                None,
+                digest,
+            ),
+            (
+                &format!("registry:5000/team/app:v2@{}", DIGEST),
+                "registry:5000",
+                "team/app",
+                Some("v2"),
+                digest,
+            ),
+        ];
+
+        for (input, registry, repository, tag, digest) in cases {
+            let reference: Reference = input
+                .parse()
+                .unwrap_or_else(|e| panic!("{} should parse: {}", input, e));
+            assert_eq!(reference.registry, *registry, "registry of {}", input);
+            assert_eq!(reference.repository, *repository, "repository of {}", input);
+            assert_eq!(reference.tag.as_deref(), *tag, "tag of {}", input);
+            assert_eq!(reference.digest.as_deref(), *digest, "digest of {}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_references() {
+        let long_tag = format!("app:{}", "a".repeat(129));
+        let long_name = format!("org/{}", "a".repeat(252));
+        let cases: &[&str] = &[
+            "",
+            ":tag",
+            "Python",
+            "org/App",
+            "app:",
+            "app:-tag",
+            "app:.tag",
+            "app:ta g",
+            "app@",
+            "app@sha256:",
+            "app@sha256:abc",
+            "app@sha256:4C1F7FBB4E5A1A8E0D1EF6B0D6B3E9C1B6E5B2A8F0C3D9E7A6B5C4D3E2F1A0B9",
+            "app@sha256:4c1f7fbb4e5a1a8e0d1ef6b0d6b3e9c1b6e5b2a8f0c3d9e7a6b5c4d3e2f1a0b",
+            "app@md5",
+            "-app",
+            "app-",
+            "app..x",
+            "app___x",
+            "app._x",
+            "org//app",
+            "org/app/",
+            "/app",
+            "localhost:port/app",
+            "registry:/app",
+            "-registry.io/app",
+            "reg_istry.io/app",
+            "[::1/app",
+            "[]:5000/app",
+            &long_tag,
+            &long_name,
+        ];
+
+        for input in cases {
+            assert!(
+                input.parse::<Reference>().is_err(),
+                "{:?} should be rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn manifest_reference_prefers_digest() {
+        let cases = [
+            ("python", "latest"),
+            ("python:3.12", "3.12"),
+            (&*format!("python@{}", DIGEST), DIGEST),
+            (&*format!("python:3.12@{}", DIGEST), DIGEST),
+        ];
+
+        for (input, expected) in cases {
+            let reference: Reference = input.parse().unwrap();
+            assert_eq!(reference.manifest_reference(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let cases = [
+            "registry-1.docker.io/library/python:3.12",
+            "localhost:5000/team/app",
+            &*format!("ghcr.io/org/app:v1@{}", DIGEST),
+        ];
+
+        for input in cases {
+            let reference: Reference = input.parse().unwrap();
+            assert_eq!(reference.to_string(), input);
+            assert_eq!(
+                reference.to_string().parse::<Reference>().unwrap(),
+                reference
+            );
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::reference::DOCKER_HUB;
+
+static DEFAULT_OPTIONS: RegistryOptions = RegistryOptions {
+    insecure: None,
+    ca: Vec::new(),
+    client_cert: None,
+    client_key: None,
+};
+
+/// How to connect to one registry host.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistryOptions {
+    /// Use plain HTTP instead of HTTPS; when unset, loopback registries
+    /// use HTTP and every other registry HTTPS
+    pub insecure: Option<bool>,
+    /// PEM bundles of CAs to trust in addition to the built-in roots
+    pub ca: Vec<PathBuf>,
+    /// PEM client certificate chain for mutual TLS
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key for `client_cert`, which it requires; may be left out
+    /// when the certificate file holds the key as well
+    pub client_key: Option<PathBuf>,
+}
+
+impl RegistryOptions {
+    /// `http` for insecure registries and, unless `insecure = false` says
+    /// otherwise, for loopback ones such as a throwaway `registry:2`
+    /// container, as Docker does by default.
+    pub fn scheme(&self, registry: &str) -> &'static str {
+        let host = registry
+            .rsplit_once(':')
+            .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+            .map_or(registry, |(host, _)| host);
+
+        match (self.insecure, host) {
+            (Some(true), _) => "http",
+            (None, "localhost" | "127.0.0.1" | "[::1]") => "http",
+            _ => "https",
+        }
+    }
+
+    fn resolve_paths(&mut self, dir: &Path) {
+        for path in self
+            .ca
+            .iter_mut()
+            .chain(&mut self.client_cert)
+            .chain(&mut self.client_key)
+        {
+            *path = dir.join(&*path);
+        }
+    }
+}
+
+/// Per-registry connection settings, keyed by `host[:port]`, as read from a
+/// `registries.toml`:
+///
+/// ```toml
+/// [registries."registry.lan:5000"]
+/// insecure = true
+///
+/// [registries."localhost:5443"]
+/// insecure = false
+/// ca = ["local-ca.pem"]
+///
+/// [registries."registry.internal"]
+/// ca = ["internal-ca.pem"]
+/// client_cert = "client.pem"
+/// client_key = "client-key.pem"
+/// ```
+///
+/// Relative paths are resolved against the file's directory.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registries {
+    #[serde(default)]
+    registries: HashMap<String, RegistryOptions>,
+}
+
+impl Registries {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        let mut registries = HashMap::new();
+        for (host, mut options) in config.registries {
+            if host.contains('/') {
+                return Err(anyhow!(
+                    "Invalid registry '{}' in {}, expected host[:port]",
+                    host,
+                    path.display()
+                ));
+            }
+            if options.client_key.is_some() && options.client_cert.is_none() {
+                return Err(anyhow!(
+                    "Registry '{}' in {} sets client_key without client_cert",
+                    host,
+                    path.display()
+                ));
+            }
+            options.resolve_paths(dir);
+            registries.insert(Self::normalize(host), options);
+        }
+
+        Ok(Self { registries })
+    }
+
+    /// Serves `host` over plain HTTP, on top of any other settings for it.
+    pub fn with_insecure(mut self, host: impl Into<String>) -> Self {
+        self.registries
+            .entry(Self::normalize(host.into()))
+            .or_default()
+            .insecure = Some(true);
+        self
+    }
+
+    /// Settings for `registry`; HTTPS with the built-in roots when it has none.
+    pub fn options(&self, registry: &str) -> &RegistryOptions {
+        self.registries.get(registry).unwrap_or(&DEFAULT_OPTIONS)
+    }
+
+    /// Docker Hub goes by several names; references resolve to `DOCKER_HUB`.
+    fn normalize(host: String) -> String {
+        match host.as_str() {
+            "docker.io" | "index.docker.io" => DOCKER_HUB.to_string(),
+            _ => host,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(content: &str) -> Result<Registries> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registries.toml");
+        std::fs::write(&path, content).unwrap();
+        Registries::load(&path)
+    }
+
+    fn error(content: &str) -> String {
+        format!("{:#}", load(content).unwrap_err())
+    }
+
+    #[test]
+    fn loads_registry_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registries.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [registries."localhost:5000"]
+            insecure = true
+
+            [registries."registry.internal"]
+            ca = ["certs/internal-ca.pem", "/etc/ssl/extra.pem"]
+            client_cert = "client.pem"
+            client_key = "client-key.pem"
+
+            [registries."combined.internal"]
+            client_cert = "combined.pem"
+
+            [registries."docker.io"]
+            ca = ["hub-proxy.pem"]
+            "#,
+        )
+        .unwrap();
+        let registries = Registries::load(&path).unwrap();
+
+        let local = registries.options("localhost:5000");
+        assert_eq!(local.insecure, Some(true));
+        assert!(local.ca.is_empty());
+
+        let internal = registries.options("registry.internal");
+        assert_eq!(internal.insecure, None);
+        assert_eq!(
+            internal.ca,
+            [
+                dir.path().join("certs/internal-ca.pem"),
+                PathBuf::from("/etc/ssl/extra.pem"),
+            ]
+        );
+        assert_eq!(internal.client_cert, Some(dir.path().join("client.pem")));
+        assert_eq!(internal.client_key, Some(dir.path().join("client-key.pem")));
+
+        // The certificate file may hold the key as well
+        let combined = registries.options("combined.internal");
+        assert_eq!(combined.client_cert, Some(dir.path().join("combined.pem")));
+        assert_eq!(combined.client_key, None);
+
+        assert_eq!(
+            registries.options(DOCKER_HUB).ca,
+            [dir.path().join("hub-proxy.pem")]
+        );
+
+        let unknown = registries.options("ghcr.io");
+        assert!(
+            unknown.insecure.is_none() && unknown.ca.is_empty() && unknown.client_cert.is_none()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_registry_options() {
+        let message = error(
+            r#"
+            [registries."registry.internal"]
+            client_key = "client-key.pem"
+            "#,
+        );
+        assert!(
+            message.contains("Registry 'registry.internal'")
+                && message.contains("sets client_key without client_cert"),
+            "{}",
+            message
+        );
+
+        let message = error("[registries.\"ghcr.io/org\"]\ninsecure = true\n");
+        assert!(message.contains("expected host[:port]"), "{}", message);
+
+        let message = error("[registries.\"ghcr.io\"]\ninsecure = \"yes\"\n");
+        assert!(message.contains("Failed to parse"), "{}", message);
+
+        let message = error("[registries.\"ghcr.io\"]\nclient_certificate = \"c.pem\"\n");
+        assert!(message.contains("unknown field"), "{}", message);
+
+        let message = error("[mirrors]\n");
+        assert!(message.contains("unknown field"), "{}", message);
+    }
+
+    #[test]
+    fn uses_plain_http_for_insecure_and_loopback_registries() {
+        let registries = load("[registries.\"localhost:5443\"]\ninsecure = false\n")
+            .unwrap()
+            .with_insecure("registry.lan:5000")
+            .with_insecure("docker.io");
+
+        for (registry, scheme) in [
+            ("registry.lan:5000", "http"),
+            ("registry.lan", "https"),
+            (DOCKER_HUB, "http"),
+            ("localhost", "http"),
+            ("localhost:5000", "http"),
+            ("127.0.0.1:5000", "http"),
+            ("[::1]:5000", "http"),
+            // Loopback registries serving TLS opt back into HTTPS
+            ("localhost:5443", "https"),
+            ("localhost.example.com", "https"),
+            ("ghcr.io", "https"),
+        ] {
+            assert_eq!(
+                registries.options(registry).scheme(registry),
+                scheme,
+                "{}",
+                registry
+            );
+        }
+    }
+}
@@ -0,0 +1,969 @@
+use anyhow::{anyhow, Context, Result};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A PEP 508 dependency specifier such as `requests[socks]>=2.31; python_version >= "3.8"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requirement {
+    pub name: String,
+    pub extras: Vec<String>,
+    pub specifiers: Vec<Specifier>,
+    pub marker: Option<String>,
+    /// Direct reference (`name @ https://...`) or a bare path/URL
+    pub url: Option<String>,
+}
+
+/// A single PEP 440 version clause, e.g. `>=2.31`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Specifier {
+    pub operator: String,
+    pub version: String,
+}
+
+/// A PEP 440 version, reduced to what ordering needs.
+#[derive(Debug, Clone)]
+pub struct Version {
+    epoch: u64,
+    release: Vec<u64>,
+    pre: Option<(u8, u64)>,
+    post: Option<u64>,
+    dev: Option<u64>,
+}
+
+impl Requirement {
+    pub fn parse(line: &str) -> Result<Self> {
+        let (requirement, marker) = match line.split_once(';') {
+            Some((req, marker)) => (req.trim(), Some(marker.trim().to_string())),
+            None => (line.trim(), None),
+        };
+
+        if requirement.is_empty() {
+            return Err(anyhow!("Empty requirement"));
+        }
+
+        let name_end = requirement
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+            .unwrap_or(requirement.len());
+        let name = &requirement[..name_end];
+        if name.is_empty() || requirement.contains("://") && !requirement.contains('@') {
+            return Ok(Self {
+                name: String::new(),
+                extras: Vec::new(),
+                specifiers: Vec::new(),
+                marker,
+                url: Some(requirement.to_string()),
+            });
+        }
+
+        let mut rest = requirement[name_end..].trim_start();
+
+        let mut extras = Vec::new();
+        if let Some(stripped) = rest.strip_prefix('[') {
+            let end = stripped
+                .find(']')
+                .ok_or_else(|| anyhow!("Unclosed extras in requirement: {}", line))?;
+            extras = stripped[..end]
+                .split(',')
+                .map(|e| normalize_name(e.trim()))
+                .filter(|e| !e.is_empty())
+                .collect();
+            rest = stripped[end + 1..].trim_start();
+        }
+
+        if let Some(url) = rest.strip_prefix('@') {
+            return Ok(Self {
+                name: normalize_name(name),
+                extras,
+                specifiers: Vec::new(),
+                marker,
+                url: Some(url.trim().to_string()),
+            });
+        }
+
+        let rest = rest.trim_start_matches('(').trim_end_matches(')');
+        let specifiers = Specifier::parse_list(rest)
+            .with_context(|| format!("Invalid version specifier in requirement: {}", line))?;
+
+        Ok(Self {
+            name: normalize_name(name),
+            extras,
+            specifiers,
+            marker,
+            url: None,
+        })
+    }
+
+    /// Whether `version` satisfies every specifier clause.
+    pub fn matches_version(&self, version: &str) -> bool {
+        self.specifiers.iter().all(|s| s.matches(version))
+    }
+
+    /// Whether the requirement applies in `env`; requirements without a
+    /// marker always apply.
+    pub fn applies(&self, env: &HashMap<&str, String>) -> Result<bool> {
+        match &self.marker {
+            Some(marker) => evaluate_marker(marker, env),
+            None => Ok(true),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(url) = &self.url {
+            if self.name.is_empty() {
+                return write!(f, "{}", url);
+            }
+        }
+        write!(f, "{}", self.name)?;
+        if !self.extras.is_empty() {
+            write!(f, "[{}]", self.extras.join(","))?;
+        }
+        let specifiers: Vec<String> = self
+            .specifiers
+            .iter()
+            .map(|s| format!("{}{}", s.operator, s.version))
+            .collect();
+        write!(f, "{}", specifiers.join(","))?;
+        if let Some(url) = &self.url {
+            write!(f, " @ {}", url)?;
+        }
+        if let Some(marker) = &self.marker {
+            // A marker after a URL must be separated by whitespace
+            let separator = if self.url.is_some() { " ;" } else { ";" };
+            write!(f, "{} {}", separator, marker)?;
+        }
+        Ok(())
+    }
+}
+
+impl Specifier {
+    pub fn parse_list(specifiers: &str) -> Result<Vec<Self>> {
+        specifiers
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    pub fn parse(specifier: &str) -> Result<Self> {
+        const OPERATORS: [&str; 8] = ["===", "~=", "==", "!=", "<=", ">=", "<", ">"];
+
+        let operator = OPERATORS
+            .iter()
+            .find(|op| specifier.starts_with(*op))
+            .ok_or_else(|| anyhow!("Unknown version operator in '{}'", specifier))?;
+        let version = specifier[operator.len()..].trim();
+        if version.is_empty() {
+            return Err(anyhow!("Missing version in '{}'", specifier));
+        }
+
+        Ok(Self {
+            operator: operator.to_string(),
+            version: version.to_string(),
+        })
+    }
+
+    pub fn matches(&self, candidate: &str) -> bool {
+        if self.operator == "===" {
+            return candidate == self.version;
+        }
+
+        let Some(candidate) = Version::parse(candidate) else {
+            return false;
+        };
+
+        if let Some(prefix) = self.version.strip_suffix(".*") {
+            let Some(prefix) = Version::parse(prefix) else {
+                return false;
+            };
+            let is_prefix = candidate.epoch == prefix.epoch
+                && prefix
+                    .release
+                    .iter()
+                    .enumerate()
+                    .all(|(i, part)| candidate.release.get(i).copied().unwrap_or(0) == *part);
+            return match self.operator.as_str() {
+                "==" => is_prefix,
+                "!=" => !is_prefix,
+                _ => false,
+            };
+        }
+
+        let Some(version) = Version::parse(&self.version) else {
+            return false;
+        };
+
+        match self.operator.as_str() {
+            "==" => candidate == version,
+            "!=" => candidate != version,
+            "<=" => candidate <= version,
+            ">=" => candidate >= version,
+            "<" => candidate < version,
+            ">" => candidate > version,
+            "~=" => {
+                // ~=X.Y.Z means >=X.Y.Z, ==X.Y.*
+                let mut upper = version.release.clone();
+                upper.pop();
+                candidate >= version
+                    && !upper.is_empty()
+                    && upper
+                        .iter()
+                        .enumerate()
+                        .all(|(i, part)| candidate.release.get(i).copied().unwrap_or(0) == *part)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().trim_start_matches('v').to_ascii_lowercase();
+        let version = version.split('+').next()?;
+
+        let (epoch, version) = match version.split_once('!') {
+            Some((epoch, rest)) => (epoch.parse().ok()?, rest),
+            None => (0, version),
+        };
+
+        let release_end = version
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(version.len());
+        let release: Vec<u64> = version[..release_end]
+            .trim_end_matches('.')
+            .split('.')
+            .map(|p| p.parse().ok())
+            .collect::<Option<_>>()?;
+
+        let separators = ['.', '-', '_'];
+        let mut rest = &version[release_end..];
+        let mut pre = None;
+        let mut post = None;
+        let mut dev = None;
+
+        let take_number = |s: &str| -> (u64, usize) {
+            let s2 = s.trim_start_matches(separators);
+            let skipped = s.len() - s2.len();
+            let end = s2.find(|c: char| !c.is_ascii_digit()).unwrap_or(s2.len());
+            (s2[..end].parse().unwrap_or(0), skipped + end)
+        };
+
+        for (labels, kind) in [
+            (&["alpha", "a"][..], 0u8),
+            (&["beta", "b"][..], 1),
+            (&["preview", "pre", "rc", "c"][..], 2),
+        ] {
+            let trimmed = rest.trim_start_matches(separators);
+            if let Some(label) = labels.iter().find(|l| trimmed.starts_with(*l)) {
+                let (n, used) = take_number(&trimmed[label.len()..]);
+                pre = Some((kind, n));
+                rest = &trimmed[label.len() + used..];
+                break;
+            }
+        }
+
+        // `1.0-1` is the implicit form of `1.0.post1`
+        let implicit_post = rest
+            .strip_prefix('-')
+            .filter(|s| s.starts_with(|c: char| c.is_ascii_digit()));
+        if let Some(stripped) = implicit_post {
+            let (n, used) = take_number(stripped);
+            post = Some(n);
+            rest = &stripped[used..];
+        } else {
+            let trimmed = rest.trim_start_matches(separators);
+            for label in ["post", "rev", "r"] {
+                if let Some(stripped) = trimmed.strip_prefix(label) {
+                    let (n, used) = take_number(stripped);
+                    post = Some(n);
+                    rest = &stripped[used..];
+                    break;
+                }
+            }
+        }
+
+        let trimmed = rest.trim_start_matches(separators);
+        if let Some(stripped) = trimmed.strip_prefix("dev") {
+            let (n, used) = take_number(stripped);
+            dev = Some(n);
+            rest = &stripped[used..];
+        }
+
+        if !rest.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            epoch,
+            release,
+            pre,
+            post,
+            dev,
+        })
+    }
+
+    fn sort_key(&self) -> (u64, Vec<u64>, (i64, i64), i64, i64) {
+        let mut release = self.release.clone();
+        while release.len() > 1 && release.last() == Some(&0) {
+            release.pop();
+        }
+
+        // Development releases of a final version sort before its pre-releases
+        let pre = match (self.pre, self.post, self.dev) {
+            (None, None, Some(_)) => (-1, 0),
+            (Some((kind, n)), _, _) => (kind as i64, n as i64),
+            _ => (i64::MAX, 0),
+        };
+        let post = self.post.map_or(-1, |n| n as i64);
+        let dev = self.dev.map_or(i64::MAX, |n| n as i64);
+
+        (self.epoch, release, pre, post, dev)
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.sort_key().cmp(&other.sort_key()))
+    }
+}
+
+/// PEP 503 name normalization: lowercase, runs of `-_.` become `-`.
+pub fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut last_was_separator = false;
+    for c in name.trim().chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !last_was_separator {
+                normalized.push('-');
+            }
+            last_was_separator = true;
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+            last_was_separator = false;
+        }
+    }
+    normalized
+}
+
+/// Reads a pip requirements file, following `-r` includes. Options such as
+/// `--hash` and `--index-url` are dropped; editable (`-e`) requirements are
+/// an error.
+pub fn parse_requirements_file(path: &Path) -> Result<Vec<Requirement>> {
+    parse_included_file(path, &mut HashSet::new())
+}
+
+/// `parse_requirements_file` for a file reached through the includes in
+/// `including`, the canonical paths of the files currently being read. A
+/// file may be included more than once, but not from within itself.
+fn parse_included_file(path: &Path, including: &mut HashSet<PathBuf>) -> Result<Vec<Requirement>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", path.display()))?;
+    if !including.insert(canonical.clone()) {
+        return Err(anyhow!(
+            "Requirements include cycle: {} includes itself",
+            path.display()
+        ));
+    }
+
+    let requirements = parse_requirements(path, &content, including);
+    including.remove(&canonical);
+    requirements
+}
+
+fn parse_requirements(
+    path: &Path,
+    content: &str,
+    including: &mut HashSet<PathBuf>,
+) -> Result<Vec<Requirement>> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut requirements = Vec::new();
+    for line in content.replace("\\\n", " ").lines() {
+        let line = match line.find(" #") {
+            Some(pos) => &line[..pos],
+            None => line,
+        }
+        .trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(include) = line
+            .strip_prefix("-r ")
+            .or_else(|| line.strip_prefix("--requirement "))
+        {
+            requirements.extend(parse_included_file(
+                &base_dir.join(include.trim()),
+                including,
+            )?);
+            continue;
+        }
+
+        if line.starts_with("-e ") || line.starts_with("--editable") {
+            return Err(anyhow!(
+                "Editable requirement '{}' in {} cannot be installed into an image; \
+                 list the package by name instead",
+                line,
+                path.display()
+            ));
+        }
+
+        if line.starts_with('-') {
+            continue;
+        }
+
+        let requirement = line.split(" --").next().unwrap_or(line);
+        requirements.push(
+            Requirement::parse(requirement)
+                .with_context(|| format!("Invalid requirement in {}", path.display()))?,
+        );
+    }
+
+    Ok(requirements)
+}
+
+/// Every environment marker variable PEP 508 defines besides `extra`.
+const MARKER_VARIABLES: [&str; 11] = [
+    "os_name",
+    "sys_platform",
+    "platform_machine",
+    "platform_python_implementation",
+    "platform_release",
+    "platform_system",
+    "platform_version",
+    "python_version",
+    "python_full_version",
+    "implementation_name",
+    "implementation_version",
+];
+
+/// Evaluates a PEP 508 environment marker against `env`. Variables PEP 508
+/// defines but `env` lacks evaluate as the empty string.
+pub fn evaluate_marker(marker: &str, env: &HashMap<&str, String>) -> Result<bool> {
+    let tokens = tokenize_marker(marker)?;
+    let mut parser = MarkerParser {
+        tokens: &tokens,
+        pos: 0,
+        env,
+    };
+    let result = parser.or_expr()?;
+    if parser.pos != tokens.len() {
+        return Err(anyhow!("Unexpected trailing input in marker: {}", marker));
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum MarkerToken {
+    Ident(String),
+    Quoted(String),
+    Op(String),
+    Open,
+    Close,
+}
+
+fn tokenize_marker(marker: &str) -> Result<Vec<MarkerToken>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = marker.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(MarkerToken::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(MarkerToken::Close);
+                i += 1;
+            }
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&q| q == c)
+                    .ok_or_else(|| anyhow!("Unterminated string in marker: {}", marker))?;
+                tokens.push(MarkerToken::Quoted(
+                    chars[i + 1..i + 1 + end].iter().collect(),
+                ));
+                i += end + 2;
+            }
+            '<' | '>' | '=' | '!' | '~' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| matches!(c, '<' | '>' | '=' | '!' | '~'))
+                    .count();
+                tokens.push(MarkerToken::Op(chars[i..i + len].iter().collect()));
+                i += len;
+            }
+            _ => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.'))
+                    .count();
+                if len == 0 {
+                    return Err(anyhow!("Unexpected '{}' in marker: {}", c, marker));
+                }
+                let word: String = chars[i..i + len].iter().collect();
+                i += len;
+                match word.as_str() {
+                    "in" => tokens.push(MarkerToken::Op("in".to_string())),
+                    "not" => tokens.push(MarkerToken::Op("not".to_string())),
+                    _ => tokens.push(MarkerToken::Ident(word)),
+                }
+            }
+        }
+    }
+
+    // Fold `not in` into a single operator
+    let mut folded: Vec<MarkerToken> = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        if token == MarkerToken::Op("in".to_string())
+            && folded.last() == Some(&MarkerToken::Op("not".to_string()))
+        {
+            folded.pop();
+            folded.push(MarkerToken::Op("not in".to_string()));
+        } else {
+            folded.push(token);
+        }
+    }
+
+    Ok(folded)
+}
+
+struct MarkerParser<'a> {
+    tokens: &'a [MarkerToken],
+    pos: usize,
+    env: &'a HashMap<&'a str, String>,
+}
+
+impl MarkerParser<'_> {
+    fn or_expr(&mut self) -> Result<bool> {
+        let mut result = self.and_expr()?;
+        while self.keyword("or") {
+            let rhs = self.and_expr()?;
+            result = result || rhs;
+        }
+        Ok(result)
+    }
+
+    fn and_expr(&mut self) -> Result<bool> {
+        let mut result = self.atom()?;
+        while self.keyword("and") {
+            let rhs = self.atom()?;
+            result = result && rhs;
+        }
+        Ok(result)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if let Some(MarkerToken::Ident(word)) = self.tokens.get(self.pos) {
+            if word == keyword {
+                self.pos += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn atom(&mut self) -> Result<bool> {
+        if self.tokens.get(self.pos) == Some(&MarkerToken::Open) {
+            self.pos += 1;
+            let result = self.or_expr()?;
+            if self.tokens.get(self.pos) != Some(&MarkerToken::Close) {
+                return Err(anyhow!("Unbalanced parentheses in marker"));
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        let (lhs, lhs_is_extra) = self.value()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(MarkerToken::Op(op)) => op.clone(),
+            other => return Err(anyhow!("Expected marker operator, found {:?}", other)),
+        };
+        self.pos += 1;
+        let (rhs, rhs_is_extra) = self.value()?;
+
+        // `extra` is compared on normalized names
+        if lhs_is_extra || rhs_is_extra {
+            return Ok(compare_marker_values(
+                &normalize_name(&lhs),
+                &op,
+                &normalize_name(&rhs),
+            ));
+        }
+
+        Ok(compare_marker_values(&lhs, &op, &rhs))
+    }
+
+    /// Returns the resolved value and whether it is the `extra` variable.
+    fn value(&mut self) -> Result<(String, bool)> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Unexpected end of marker"))?;
+        self.pos += 1;
+        match token {
+            MarkerToken::Quoted(value) => Ok((value.clone(), false)),
+            // Outside an extra's dependencies `extra` is simply unset
+            MarkerToken::Ident(name) if name == "extra" => {
+                Ok((self.env.get("extra").cloned().unwrap_or_default(), true))
+            }
+            MarkerToken::Ident(name) => match self.env.get(name.as_str()) {
+                Some(value) => Ok((value.clone(), false)),
+                // Known to PEP 508 but not to the environment: compares as ""
+                None if MARKER_VARIABLES.contains(&name.as_str()) => Ok((String::new(), false)),
+                None => Err(anyhow!("Unknown marker variable: {}", name)),
+            },
+            other => Err(anyhow!("Expected marker value, found {:?}", other)),
+        }
+    }
+}
+
+fn compare_marker_values(lhs: &str, op: &str, rhs: &str) -> bool {
+    match op {
+        "in" => rhs.contains(lhs),
+        "not in" => !rhs.contains(lhs),
+        _ => {
+            if let (Some(_), Some(_)) = (Version::parse(lhs), Version::parse(rhs)) {
+                return Specifier {
+                    operator: op.to_string(),
+                    version: rhs.to_string(),
+                }
+                .matches(lhs);
+            }
+            match op {
+                "==" | "===" => lhs == rhs,
+                "!=" => lhs != rhs,
+                "<" => lhs < rhs,
+                "<=" => lhs <= rhs,
+                ">" => lhs > rhs,
+                ">=" => lhs >= rhs,
+                _ => false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lower version, then the higher one
+    type OrderCase<'a> = (&'a str, &'a str);
+
+    /// Specifier, candidate, then whether it matches
+    type MatchCase<'a> = (&'a str, &'a str, bool);
+
+    /// Marker, then the expected result with and without the `socks` extra
+    type MarkerCase<'a> = (&'a str, bool, bool);
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap_or_else(|| panic!("{} should parse", version))
+    }
+
+    #[test]
+    fn orders_versions() {
+        let cases: &[OrderCase] = &[
+            ("1.0", "1.1"),
+            ("1.9", "1.10"),
+            ("2.0", "1!1.0"),
+            ("1!2.0", "2!0.1"),
+            ("1.0.dev0", "1.0a1"),
+            ("1.0a1.dev1", "1.0a1"),
+            ("1.0a1", "1.0a2"),
+            ("1.0a2", "1.0b1"),
+            ("1.0b1", "1.0rc1"),
+            ("1.0rc1", "1.0"),
+            ("1.0", "1.0.post1"),
+            ("1.0.post1.dev1", "1.0.post1"),
+            ("1.0.post1", "1.0.post2"),
+            ("1.0.post2", "1.0.1"),
+        ];
+
+        for (lower, higher) in cases {
+            assert!(
+                version(lower) < version(higher),
+                "{} should sort before {}",
+                lower,
+                higher
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_equivalent_versions() {
+        let cases: &[OrderCase] = &[
+            ("1.0", "1.0.0"),
+            ("0!1.0", "1.0"),
+            ("v1.0", "1.0"),
+            ("1.0-alpha.1", "1.0a1"),
+            ("1.0.beta2", "1.0b2"),
+            ("1.0c1", "1.0rc1"),
+            ("1.0pre1", "1.0rc1"),
+            ("1.0-1", "1.0.post1"),
+            ("1.0.rev2", "1.0.post2"),
+            ("1.0-dev", "1.0.dev0"),
+            ("1.0RC1", "1.0rc1"),
+            ("1.0+cpu", "1.0"),
+            ("2.1.0+cu121.local", "2.1"),
+        ];
+
+        for (a, b) in cases {
+            assert!(version(a) == version(b), "{} should equal {}", a, b);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for invalid in ["", "abc", "1.0foo", "1..0", "x!1.0", "1.0.post1.foo"] {
+            assert!(
+                Version::parse(invalid).is_none(),
+                "{} should not parse",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn matches_specifiers() {
+        let cases: &[MatchCase] = &[
+            ("~=2.2", "2.2", true),
+            ("~=2.2", "2.9.1", true),
+            ("~=2.2", "3.0", false),
+            ("~=2.2", "2.1", false),
+            ("~=1.4.5", "1.4.9", true),
+            ("~=1.4.5", "1.4.4", false),
+            ("~=1.4.5", "1.5.0", false),
+            ("~=2.2.post3", "2.2.post4", true),
+            ("~=1", "1.0", false),
+            ("==1.4.*", "1.4", true),
+            ("==1.4.*", "1.4.7", true),
+            ("==1.4.*", "1.5.0", false),
+            ("==1.4.*", "1.40", false),
+            ("==1.*", "1.0rc1", true),
+            ("!=1.4.*", "1.4.2", false),
+            ("!=1.4.*", "1.5", true),
+            ("!=1.0", "1.0.0", false),
+            ("!=1.0", "1.0.1", true),
+            ("==1.0", "1.0+cpu", true),
+            ("==1!1.0", "1.0", false),
+            (">=2.31", "2.31.0", true),
+            (">=2.31", "2.4", false),
+            ("<2.0", "2.0rc1", true),
+            ("<=2.0", "2.0.post1", false),
+            (">1.0", "1.0.post1", true),
+            ("===1.0", "1.0", true),
+            ("===1.0", "1.0.0", false),
+            (">=1.0", "not-a-version", false),
+        ];
+
+        for (specifier, candidate, expected) in cases {
+            let parsed = Specifier::parse(specifier).unwrap();
+            assert_eq!(
+                parsed.matches(candidate),
+                *expected,
+                "{} against {}",
+                specifier,
+                candidate
+            );
+        }
+    }
+
+    #[test]
+    fn evaluates_markers() {
+        let cases: &[MarkerCase] = &[
+            (r#"python_version >= "3.8""#, true, true),
+            (r#"python_version > "3.9""#, true, true),
+            (r#"python_version < "3.10""#, false, false),
+            (r#"python_full_version == "3.12.1""#, true, true),
+            (r#"sys_platform == 'win32'"#, false, false),
+            (r#"'linux' in sys_platform"#, true, true),
+            (r#"platform_machine not in "aarch64 arm64""#, true, true),
+            // `and` binds tighter than `or`
+            (
+                r#"sys_platform == "win32" and python_version >= "3.8" or extra == "socks""#,
+                true,
+                false,
+            ),
+            (
+                r#"extra == "socks" or sys_platform == "win32" and python_version >= "3.8""#,
+                true,
+                false,
+            ),
+            (
+                r#"python_version < "3.8" or python_version >= "3.10" and sys_platform == "linux""#,
+                true,
+                true,
+            ),
+            (
+                r#"(sys_platform == "linux" or sys_platform == "win32") and extra == "socks""#,
+                true,
+                false,
+            ),
+            (
+                r#"sys_platform == "linux" and (python_version < "3.8" or extra == 'socks')"#,
+                true,
+                false,
+            ),
+            // Extras compare on normalized names
+            (r#"extra == "Socks""#, true, false),
+            (r#"extra != "socks""#, false, true),
+            (r#""socks" == extra"#, true, false),
+        ];
+
+        let mut env: HashMap<&str, String> = HashMap::from([
+            ("python_version", "3.12".to_string()),
+            ("python_full_version", "3.12.1".to_string()),
+            ("sys_platform", "linux".to_string()),
+            ("platform_machine", "x86_64".to_string()),
+        ]);
+
+        for (marker, with_extra, without_extra) in cases {
+            env.insert("extra", "socks".to_string());
+            assert_eq!(
+                evaluate_marker(marker, &env).unwrap(),
+                *with_extra,
+                "{} with extra",
+                marker
+            );
+            env.remove("extra");
+            assert_eq!(
+                evaluate_marker(marker, &env).unwrap(),
+                *without_extra,
+                "{} without extra",
+                marker
+            );
+        }
+
+        // A PEP 508 variable the environment doesn't know is an empty string
+        assert!(!evaluate_marker(r#"platform_release >= "5""#, &env).unwrap());
+        assert!(evaluate_marker(r#"platform_version == """#, &env).unwrap());
+
+        // A misspelt variable is an error, not an empty string
+        let error = evaluate_marker(r#"python_verison >= "3.8""#, &env).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown marker variable: python_verison");
+    }
+
+    #[test]
+    fn rejects_malformed_markers() {
+        let env = HashMap::new();
+        for marker in [
+            r#"python_version >= "3.8"#,
+            r#"(python_version >= "3.8""#,
+            r#"python_version "3.8""#,
+            r#"python_version >= "3.8" and"#,
+            r#"python_version >= "3.8" sys_platform"#,
+        ] {
+            assert!(
+                evaluate_marker(marker, &env).is_err(),
+                "{} should not evaluate",
+                marker
+            );
+        }
+    }
+
+    #[test]
+    fn parses_requirements_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("constraints")).unwrap();
+        std::fs::write(
+            dir.path().join("constraints/base.txt"),
+            "Six==1.16.0\n-r ../common.txt\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("common.txt"), "attrs>=23\n").unwrap();
+        let path = dir.path().join("requirements.txt");
+        std::fs::write(
+            &path,
+            concat!(
+                "# Pinned by pip-compile\n",
+                "--index-url https://pypi.example/simple\n",
+                "\n",
+                "requests[socks]>=2.31 \\\n",
+                "    --hash=sha256:aaaa \\\n",
+                "    --hash=sha256:bbbb\n",
+                "-r constraints/base.txt\n",
+                "urllib3==2.0.7  # via requests\n",
+                "--requirement common.txt\n",
+                "pywin32==306 ; sys_platform == \"win32\"\n",
+                "mypkg @ https://example.com/mypkg-1.0.tar.gz\n",
+            ),
+        )
+        .unwrap();
+
+        let requirements: Vec<String> = parse_requirements_file(&path)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            requirements,
+            [
+                "requests[socks]>=2.31",
+                "six==1.16.0",
+                "attrs>=23",
+                "urllib3==2.0.7",
+                "attrs>=23",
+                "pywin32==306; sys_platform == \"win32\"",
+                "mypkg @ https://example.com/mypkg-1.0.tar.gz",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requirements.txt");
+        std::fs::write(&path, "-r missing.txt\n").unwrap();
+
+        let error = format!("{:#}", parse_requirements_file(&path).unwrap_err());
+        assert!(error.contains("missing.txt"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_include_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requirements.txt");
+        std::fs::write(&path, "attrs>=23\n-r requirements.txt\n").unwrap();
+        let error = parse_requirements_file(&path).unwrap_err().to_string();
+        assert!(error.contains("include cycle"), "{}", error);
+
+        std::fs::write(&path, "-r other.txt\n").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "-r ./requirements.txt\n").unwrap();
+        let error = parse_requirements_file(&path).unwrap_err().to_string();
+        assert!(error.contains("include cycle"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_editable_requirements() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requirements.txt");
+        for line in [
+            "-e ./local-package",
+            "--editable=git+https://example.com/pkg.git",
+        ] {
+            std::fs::write(&path, format!("attrs>=23\n{}\n", line)).unwrap();
+
+            let error = parse_requirements_file(&path).unwrap_err().to_string();
+            assert!(error.contains(line), "{}", error);
+        }
+    }
+}
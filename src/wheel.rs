@@ -0,0 +1,1078 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::layout::sha256_digest;
+use crate::lockfile::LockedPackage;
+use crate::python::TargetPython;
+use crate::requirements::{normalize_name, Requirement, Version};
+
+/// Recorded in each installed distribution's `.dist-info/INSTALLER`
+const INSTALLER_NAME: &str = "python-oci-packager";
+
+/// How the dependencies layer is populated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Installer {
+    /// `pip install --target`, using the host's pip
+    #[default]
+    Pip,
+    /// Unpack wheels from the wheelhouse directly, without running Python;
+    /// a build without a wheelhouse is rejected before it starts
+    Native,
+}
+
+impl FromStr for Installer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "pip" => Ok(Installer::Pip),
+            "native" => Ok(Installer::Native),
+            _ => Err(anyhow!(
+                "Unknown installer '{}', expected one of: pip, native",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Installer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Installer::Pip => write!(f, "pip"),
+            Installer::Native => write!(f, "native"),
+        }
+    }
+}
+
+/// Install locations for the wheel scheme keys (`purelib`, `platlib`,
+/// `scripts`, `data`, `headers`).
+#[derive(Debug, Clone)]
+pub struct InstallScheme {
+    pub purelib: PathBuf,
+    pub platlib: PathBuf,
+    pub scripts: PathBuf,
+    pub data: PathBuf,
+    pub headers: PathBuf,
+}
+
+impl InstallScheme {
+    /// The flat layout `pip install --target <root>` produces: libraries at
+    /// the root and scripts in `bin/`.
+    pub fn target(root: &Path) -> Self {
+        Self {
+            purelib: root.to_path_buf(),
+            platlib: root.to_path_buf(),
+            scripts: root.join("bin"),
+            data: root.to_path_buf(),
+            headers: root.join("include"),
+        }
+    }
+
+    fn dir(&self, key: &str) -> Option<&Path> {
+        match key {
+            "purelib" => Some(&self.purelib),
+            "platlib" => Some(&self.platlib),
+            "scripts" => Some(&self.scripts),
+            "data" => Some(&self.data),
+            "headers" => Some(&self.headers),
+            _ => None,
+        }
+    }
+}
+
+/// A `.whl` file, identified by its PEP 427 filename
+/// `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`.
+#[derive(Debug, Clone)]
+pub struct Wheel {
+    pub path: PathBuf,
+    /// PEP 503 normalized distribution name
+    pub name: String,
+    pub version: String,
+    pub python_tag: String,
+    pub abi_tag: String,
+    pub platform_tag: String,
+}
+
+impl Wheel {
+    /// Parses a wheel filename; returns `None` for anything that isn't one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let stem = path.file_name()?.to_str()?.strip_suffix(".whl")?;
+        let parts: Vec<&str> = stem.split('-').collect();
+        let (name, version, tags) = match parts.len() {
+            5 => (parts[0], parts[1], &parts[2..]),
+            6 => (parts[0], parts[1], &parts[3..]),
+            _ => return None,
+        };
+
+        Some(Self {
+            path: path.to_path_buf(),
+            name: normalize_name(name),
+            version: version.to_string(),
+            python_tag: tags[0].to_string(),
+            abi_tag: tags[1].to_string(),
+            platform_tag: tags[2].to_string(),
+        })
+    }
+
+    pub fn is_compatible(&self, target: &TargetPython) -> bool {
+        target.supports_wheel_tags(&self.python_tag, &self.abi_tag, &self.platform_tag)
+    }
+
+    /// `Requires-Dist` entries from the wheel's `.dist-info/METADATA`.
+    pub fn dependencies(&self) -> Result<Vec<Requirement>> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open wheel {}", self.path.display()))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("Invalid wheel archive {}", self.path.display()))?;
+
+        let metadata_name = archive
+            .file_names()
+            .find(|name| {
+                name.strip_suffix("/METADATA")
+                    .is_some_and(|dir| dir.ends_with(".dist-info") && !dir.contains('/'))
+            })
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("No METADATA in wheel {}", self.path.display()))?;
+
+        let mut metadata = String::new();
+        archive
+            .by_name(&metadata_name)?
+            .read_to_string(&mut metadata)?;
+
+        metadata
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.strip_prefix("Requires-Dist:"))
+            .map(|requirement| {
+                Requirement::parse(requirement.trim())
+                    .with_context(|| format!("Invalid Requires-Dist in {}", self.path.display()))
+            })
+            .collect()
+    }
+
+    /// Unpacks the wheel into `scheme`: verifies every file against
+    /// `RECORD`, routes `.data/<key>/` entries to their scheme directory,
+    /// points `#!python` scripts and generated console scripts at
+    /// `interpreter`, and writes `INSTALLER` and a `RECORD` of what was
+    /// installed.
+    pub fn install(&self, scheme: &InstallScheme, interpreter: &str) -> Result<()> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open wheel {}", self.path.display()))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("Invalid wheel archive {}", self.path.display()))?;
+
+        let dist_info = archive
+            .file_names()
+            .filter_map(|name| name.split('/').next())
+            .find(|dir| {
+                dir.strip_suffix(".dist-info")
+                    .and_then(|stem| stem.split('-').next())
+                    .is_some_and(|name| normalize_name(name) == self.name)
+            })
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("No .dist-info directory in {}", self.path.display()))?;
+        let data_dir = format!("{}.data", dist_info.trim_end_matches(".dist-info"));
+
+        let wheel_metadata = Self::read_entry(&mut archive, &format!("{}/WHEEL", dist_info))?;
+        let root = if Self::root_is_purelib(&wheel_metadata)? {
+            &scheme.purelib
+        } else {
+            &scheme.platlib
+        };
+        let headers = scheme.headers.join(&self.name);
+
+        let record_name = format!("{}/RECORD", dist_info);
+        let expected = parse_record(&Self::read_entry(&mut archive, &record_name)?)?;
+
+        let mut installed = Vec::new();
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+
+            let name = entry.name().to_string();
+            if entry.enclosed_name().is_none() {
+                return Err(anyhow!("Unsafe path '{}' in {}", name, self.path.display()));
+            }
+            if name == record_name
+                || name == format!("{}/INSTALLER", dist_info)
+                || name == format!("{}.jws", record_name)
+                || name == format!("{}.p7s", record_name)
+            {
+                continue;
+            }
+
+            let mut contents = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut contents)?;
+            let executable = entry.unix_mode().is_some_and(|mode| mode & 0o111 != 0);
+            drop(entry);
+
+            let hash = expected.get(&name).ok_or_else(|| {
+                anyhow!(
+                    "{} is not listed in RECORD of {}",
+                    name,
+                    self.path.display()
+                )
+            })?;
+            // Only RECORD and its signatures, skipped above, may go unhashed
+            let hash = hash.as_deref().ok_or_else(|| {
+                anyhow!("{} has no hash in RECORD of {}", name, self.path.display())
+            })?;
+            verify_record_hash(&name, hash, &contents)?;
+
+            let (dest, is_script) = match name.strip_prefix(&format!("{}/", data_dir)) {
+                Some(rest) => {
+                    let (key, path) = rest
+                        .split_once('/')
+                        .ok_or_else(|| anyhow!("Malformed data path '{}'", name))?;
+                    let dir = match key {
+                        "headers" => headers.as_path(),
+                        _ => scheme
+                            .dir(key)
+                            .ok_or_else(|| anyhow!("Unknown wheel scheme '{}' in {}", key, name))?,
+                    };
+                    (dir.join(path), key == "scripts")
+                }
+                None => (root.join(&name), false),
+            };
+
+            if is_script {
+                contents = rewrite_shebang(contents, interpreter);
+            }
+            write_file(&dest, &contents, is_script || executable)?;
+            installed.push((dest, Some(record_hash(&contents)), contents.len()));
+        }
+
+        let entry_points = format!("{}/entry_points.txt", dist_info);
+        if archive.index_for_name(&entry_points).is_some() {
+            let entry_points = Self::read_entry(&mut archive, &entry_points)?;
+            for (script, target) in console_scripts(&entry_points)? {
+                let contents = script_launcher(interpreter, &target)?.into_bytes();
+                let dest = scheme.scripts.join(script);
+                write_file(&dest, &contents, true)?;
+                installed.push((dest, Some(record_hash(&contents)), contents.len()));
+            }
+        }
+
+        let installer = format!("{}\n", INSTALLER_NAME).into_bytes();
+        let dest = root.join(&dist_info).join("INSTALLER");
+        write_file(&dest, &installer, false)?;
+        installed.push((dest, Some(record_hash(&installer)), installer.len()));
+
+        let mut record = String::new();
+        for (path, hash, size) in &installed {
+            record.push_str(&format!(
+                "{},{},{}\n",
+                csv_field(&record_path(path, root)),
+                hash.as_deref().unwrap_or_default(),
+                size
+            ));
+        }
+        record.push_str(&format!("{},,\n", csv_field(&record_name)));
+        write_file(&root.join(&record_name), record.as_bytes(), false)
+    }
+
+    fn read_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Result<String> {
+        let mut contents = String::new();
+        archive
+            .by_name(name)
+            .with_context(|| format!("Missing {} in wheel", name))?
+            .read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn root_is_purelib(wheel_metadata: &str) -> Result<bool> {
+        let mut purelib = false;
+        for line in wheel_metadata.lines() {
+            match line.split_once(':') {
+                Some(("Wheel-Version", version)) if !version.trim().starts_with("1.") => {
+                    return Err(anyhow!("Unsupported Wheel-Version {}", version.trim()));
+                }
+                Some(("Root-Is-Purelib", value)) => {
+                    purelib = value.trim().eq_ignore_ascii_case("true");
+                }
+                _ => {}
+            }
+        }
+        Ok(purelib)
+    }
+}
+
+/// Parses a `RECORD` file into path → optional `algorithm=digest`.
+fn parse_record(record: &str) -> Result<HashMap<String, Option<String>>> {
+    record
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields = split_csv_line(line);
+            let path = fields
+                .first()
+                .ok_or_else(|| anyhow!("Malformed RECORD line: {}", line))?;
+            let hash = fields.get(1).filter(|hash| !hash.is_empty()).cloned();
+            Ok((path.clone(), hash))
+        })
+        .collect()
+}
+
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn record_hash(contents: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        URL_SAFE_NO_PAD.encode(Sha256::digest(contents))
+    )
+}
+
+fn verify_record_hash(name: &str, expected: &str, contents: &[u8]) -> Result<()> {
+    let (algorithm, digest) = expected
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Malformed RECORD hash for {}: {}", name, expected))?;
+    let calculated = match algorithm {
+        "sha256" => URL_SAFE_NO_PAD.encode(Sha256::digest(contents)),
+        "sha384" => URL_SAFE_NO_PAD.encode(Sha384::digest(contents)),
+        "sha512" => URL_SAFE_NO_PAD.encode(Sha512::digest(contents)),
+        _ => {
+            return Err(anyhow!(
+                "Unsupported RECORD hash algorithm '{}' for {}",
+                algorithm,
+                name
+            ))
+        }
+    };
+    if calculated != digest.trim_end_matches('=') {
+        return Err(anyhow!("RECORD hash mismatch for {}", name));
+    }
+    Ok(())
+}
+
+/// Replaces the `#!python` / `#!pythonw` placeholder wheels use for scripts.
+fn rewrite_shebang(contents: Vec<u8>, interpreter: &str) -> Vec<u8> {
+    let Some(rest) = contents
+        .strip_prefix(b"#!pythonw")
+        .or_else(|| contents.strip_prefix(b"#!python"))
+    else {
+        return contents;
+    };
+    let rest = match rest.iter().position(|&b| b == b'\n') {
+        Some(newline) => &rest[newline..],
+        None => &[],
+    };
+    let mut rewritten = format!("#!{}", interpreter).into_bytes();
+    rewritten.extend_from_slice(rest);
+    rewritten
+}
+
+/// `[console_scripts]` and `[gui_scripts]` entries as `(name, module:attr)`.
+fn console_scripts(entry_points: &str) -> Result<Vec<(String, String)>> {
+    let mut scripts = Vec::new();
+    let mut in_scripts = false;
+    for line in entry_points.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_scripts = matches!(section.trim(), "console_scripts" | "gui_scripts");
+            continue;
+        }
+        if !in_scripts {
+            continue;
+        }
+        let (name, target) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Malformed entry point: {}", line))?;
+        let target = target.split('[').next().unwrap_or(target).trim();
+        scripts.push((name.trim().to_string(), target.to_string()));
+    }
+    Ok(scripts)
+}
+
+/// `path` as `RECORD` lists it: relative to the directory holding the
+/// `.dist-info`, with `..` for files installed elsewhere in the scheme, so
+/// the entry doesn't depend on where the layer was staged.
+fn record_path(path: &Path, root: &Path) -> String {
+    let path: Vec<_> = path.components().collect();
+    let root: Vec<_> = root.components().collect();
+    let common = path
+        .iter()
+        .zip(&root)
+        .take_while(|(path, root)| path == root)
+        .count();
+
+    std::iter::repeat_n("..".to_string(), root.len() - common)
+        .chain(
+            path[common..]
+                .iter()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The launcher pip generates for a `module:attr` entry point.
+fn script_launcher(interpreter: &str, target: &str) -> Result<String> {
+    let (module, attr) = target
+        .split_once(':')
+        .map(|(module, attr)| (module.trim(), attr.trim()))
+        .ok_or_else(|| anyhow!("Entry point '{}' has no callable", target))?;
+    let import = attr.split('.').next().unwrap_or(attr);
+
+    Ok(format!(
+        "#!{interpreter}\n\
+         # -*- coding: utf-8 -*-\n\
+         import re\n\
+         import sys\n\
+         from {module} import {import}\n\
+         if __name__ == \"__main__\":\n    \
+         sys.argv[0] = re.sub(r\"(-script\\.pyw|\\.exe)?$\", \"\", sys.argv[0])\n    \
+         sys.exit({attr}())\n"
+    ))
+}
+
+fn write_file(dest: &Path, contents: &[u8], executable: bool) -> Result<()> {
+    if dest
+        .components()
+        .any(|component| matches!(component, Component::ParentDir))
+    {
+        return Err(anyhow!(
+            "Refusing to write outside the install scheme: {}",
+            dest.display()
+        ));
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(dest, contents)
+        .with_context(|| format!("Failed to write {}", dest.display()))?;
+    let mode = if executable { 0o755 } else { 0o644 };
+    std::fs::set_permissions(dest, std::fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+/// A directory of pre-built wheels that dependencies are installed from
+/// instead of an index.
+pub struct Wheelhouse {
+    dir: PathBuf,
+    wheels: Vec<Wheel>,
+}
+
+impl Wheelhouse {
+    pub fn scan(dir: &Path) -> Result<Self> {
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read wheelhouse {}", dir.display()))?;
+
+        let mut wheels = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            match Wheel::from_path(&path) {
+                Some(wheel) => wheels.push(wheel),
+                None => tracing::debug!("Ignoring non-wheel file {}", path.display()),
+            }
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            wheels,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Sorted `filename=sha256:<hex>` entries, identifying exactly what the
+    /// wheelhouse can provide even when a wheel is rebuilt under its old name.
+    pub fn fingerprints(&self) -> Result<Vec<String>> {
+        let mut fingerprints = self
+            .wheels
+            .iter()
+            .map(|wheel| {
+                let mut file = File::open(&wheel.path)
+                    .with_context(|| format!("Failed to open wheel {}", wheel.path.display()))?;
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut file, &mut hasher)?;
+                let name = wheel.path.file_name().unwrap_or_default().to_string_lossy();
+                Ok(format!("{}=sha256:{:x}", name, hasher.finalize()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        fingerprints.sort();
+        Ok(fingerprints)
+    }
+
+    /// The newest wheel compatible with `target` that satisfies `requirement`.
+    pub fn find(&self, requirement: &Requirement, target: &TargetPython) -> Option<&Wheel> {
+        let name = normalize_name(&requirement.name);
+        self.wheels
+            .iter()
+            .filter(|wheel| wheel.name == name)
+            .filter(|wheel| requirement.matches_version(&wheel.version))
+            .filter(|wheel| wheel.is_compatible(target))
+            .max_by(|a, b| {
+                Version::parse(&a.version)
+                    .partial_cmp(&Version::parse(&b.version))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    }
+
+    /// Selects a wheel for each of `requirements` and, transitively, for the
+    /// `Requires-Dist` of every selected wheel. Fails with a single error
+    /// listing each requirement the wheelhouse cannot satisfy for `target`.
+    pub fn resolve(
+        &self,
+        requirements: &[Requirement],
+        target: &TargetPython,
+    ) -> Result<Vec<&Wheel>> {
+        let env = target.marker_env();
+        let mut queue: VecDeque<(Requirement, Vec<String>)> = requirements
+            .iter()
+            .map(|requirement| (requirement.clone(), Vec::new()))
+            .collect();
+        let mut selected: Vec<&Wheel> = Vec::new();
+        let mut installed_extras: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut missing = Vec::new();
+
+        while let Some((requirement, parent_extras)) = queue.pop_front() {
+            if !Self::applies(&requirement, &env, &parent_extras)? {
+                continue;
+            }
+
+            if requirement.name.is_empty() || requirement.url.is_some() {
+                missing.push(format!("{} (direct references need an index)", requirement));
+                continue;
+            }
+
+            let name = normalize_name(&requirement.name);
+            let wheel = match selected.iter().find(|wheel| wheel.name == name) {
+                Some(wheel) if requirement.matches_version(&wheel.version) => *wheel,
+                Some(wheel) => {
+                    missing.push(format!(
+                        "{} (conflicts with {} {})",
+                        self.describe_missing(&requirement),
+                        wheel.name,
+                        wheel.version
+                    ));
+                    continue;
+                }
+                None => match self.find(&requirement, target) {
+                    Some(wheel) => {
+                        selected.push(wheel);
+                        wheel
+                    }
+                    None => {
+                        missing.push(self.describe_missing(&requirement));
+                        continue;
+                    }
+                },
+            };
+
+            // Only walk dependencies for extras not already expanded; the
+            // empty extra stands for the wheel's unconditional dependencies.
+            let done = installed_extras.entry(name).or_default();
+            let extras: Vec<String> = std::iter::once(String::new())
+                .chain(requirement.extras.iter().map(|extra| normalize_name(extra)))
+                .filter(|extra| done.insert(extra.clone()))
+                .collect();
+            if extras.is_empty() {
+                continue;
+            }
+
+            for dependency in wheel.dependencies()? {
+                queue.push_back((dependency, extras.clone()));
+            }
+        }
+
+        if !missing.is_empty() {
+            missing.sort();
+            missing.dedup();
+            return Err(anyhow!(
+                "Wheelhouse {} cannot satisfy {} requirement(s) for Python {} ({}):\n  {}",
+                self.dir.display(),
+                missing.len(),
+                target.version,
+                target.platforms.join(", "),
+                missing.join("\n  ")
+            ));
+        }
+
+        Ok(selected)
+    }
+
+    /// Selects the wheel for each locked package, checking the file against
+    /// the hashes recorded in the lock. Fails with a single error listing
+    /// every package without a compatible wheel or with a hash mismatch.
+    pub fn locked(&self, packages: &[LockedPackage], target: &TargetPython) -> Result<Vec<&Wheel>> {
+        let mut selected = Vec::new();
+        let mut missing = Vec::new();
+
+        for package in packages {
+            let candidates: Vec<&Wheel> = self
+                .wheels
+                .iter()
+                .filter(|wheel| wheel.name == package.name)
+                .filter(|wheel| {
+                    Version::parse(&wheel.version).is_some()
+                        && Version::parse(&wheel.version) == Version::parse(&package.version)
+                })
+                .filter(|wheel| wheel.is_compatible(target))
+                .collect();
+            if candidates.is_empty() {
+                missing.push(format!("{} =={}", package.name, package.version));
+                continue;
+            }
+
+            let mut matched = None;
+            for wheel in candidates.iter().copied() {
+                let data = std::fs::read(&wheel.path)
+                    .with_context(|| format!("Failed to read {}", wheel.path.display()))?;
+                if package.hashes.contains(&sha256_digest(&data)) {
+                    matched = Some(wheel);
+                    break;
+                }
+            }
+            match matched {
+                Some(wheel) => selected.push(wheel),
+                None => missing.push(format!(
+                    "{} =={} (hash of {} not in the lock)",
+                    package.name,
+                    package.version,
+                    candidates
+                        .iter()
+                        .filter_map(|wheel| wheel.path.file_name()?.to_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Wheelhouse {} cannot satisfy {} locked package(s) for Python {} ({}):\n  {}",
+                self.dir.display(),
+                missing.len(),
+                target.version,
+                target.platforms.join(", "),
+                missing.join("\n  ")
+            ));
+        }
+
+        Ok(selected)
+    }
+
+    /// Dependency markers may test `extra`, so a dependency applies when its
+    /// marker holds for any of the extras its parent is being expanded for.
+    fn applies(
+        requirement: &Requirement,
+        env: &HashMap<&'static str, String>,
+        extras: &[String],
+    ) -> Result<bool> {
+        if extras.is_empty() {
+            return requirement.applies(env);
+        }
+        let mut env = env.clone();
+        for extra in extras {
+            env.insert("extra", extra.clone());
+            if requirement.applies(&env)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn describe_missing(&self, requirement: &Requirement) -> String {
+        let specifiers: Vec<String> = requirement
+            .specifiers
+            .iter()
+            .map(|s| format!("{}{}", s.operator, s.version))
+            .collect();
+        let mut description = if specifiers.is_empty() {
+            format!("{} (any version)", requirement.name)
+        } else {
+            format!("{} {}", requirement.name, specifiers.join(","))
+        };
+
+        let name = normalize_name(&requirement.name);
+        let incompatible: Vec<&str> = self
+            .wheels
+            .iter()
+            .filter(|wheel| wheel.name == name && requirement.matches_version(&wheel.version))
+            .filter_map(|wheel| wheel.path.file_name()?.to_str())
+            .collect();
+        if !incompatible.is_empty() {
+            description.push_str(&format!(
+                " (incompatible with target: {})",
+                incompatible.join(", ")
+            ));
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const INTERPRETER: &str = "/opt/venv/bin/python3";
+
+    /// Archive path, then contents
+    type Entry<'a> = (&'a str, &'a [u8]);
+
+    const WHEEL: &[u8] =
+        b"Wheel-Version: 1.0\nGenerator: test\nRoot-Is-Purelib: true\nTag: py3-none-any\n";
+
+    /// Writes `demo-1.0-py3-none-any.whl` holding `entries` plus the
+    /// `.dist-info` metadata, with a RECORD hashing `recorded` in place of
+    /// each entry's real contents where given.
+    fn build_wheel(dir: &Path, entries: &[Entry], recorded: &[Entry]) -> Wheel {
+        let path = dir.join("demo-1.0-py3-none-any.whl");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default().unix_permissions(0o644);
+
+        let metadata: &[Entry] = &[
+            ("demo-1.0.dist-info/METADATA", b"Name: demo\nVersion: 1.0\n"),
+            ("demo-1.0.dist-info/WHEEL", WHEEL),
+            (
+                "demo-1.0.dist-info/entry_points.txt",
+                b"[console_scripts]\ndemo = demo.cli:main [extra]\n\n[demo.plugins]\nignored = demo:plugin\n",
+            ),
+        ];
+
+        let mut record = String::new();
+        for (name, contents) in entries.iter().chain(metadata) {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents).unwrap();
+
+            let hashed = recorded
+                .iter()
+                .find(|(recorded, _)| recorded == name)
+                .map_or(*contents, |(_, contents)| *contents);
+            record.push_str(&format!(
+                "{},{},{}\n",
+                csv_field(name),
+                record_hash(hashed),
+                contents.len()
+            ));
+        }
+        record.push_str("demo-1.0.dist-info/RECORD,,\n");
+        zip.start_file("demo-1.0.dist-info/RECORD", options)
+            .unwrap();
+        zip.write_all(record.as_bytes()).unwrap();
+        zip.finish().unwrap();
+
+        Wheel::from_path(&path).unwrap()
+    }
+
+    fn scheme(root: &Path) -> InstallScheme {
+        InstallScheme {
+            purelib: root.join("purelib"),
+            platlib: root.join("platlib"),
+            scripts: root.join("bin"),
+            data: root.join("data"),
+            headers: root.join("include"),
+        }
+    }
+
+    fn install_error(entries: &[Entry], recorded: &[Entry]) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let wheel = build_wheel(dir.path(), entries, recorded);
+        let root = dir.path().join("site");
+        format!(
+            "{:#}",
+            wheel.install(&scheme(&root), INTERPRETER).unwrap_err()
+        )
+    }
+
+    #[test]
+    fn parses_wheel_filenames() {
+        let wheel = Wheel::from_path(Path::new(
+            "/wheels/Typing_Extensions-4.9.0-1-py3-none-any.whl",
+        ))
+        .unwrap();
+        assert_eq!(wheel.name, "typing-extensions");
+        assert_eq!(wheel.version, "4.9.0");
+        assert_eq!(
+            (
+                wheel.python_tag.as_str(),
+                wheel.abi_tag.as_str(),
+                wheel.platform_tag.as_str()
+            ),
+            ("py3", "none", "any")
+        );
+
+        for invalid in ["demo-1.0.tar.gz", "demo-1.0-py3.whl", "a-b-c-d-e-f-g.whl"] {
+            assert!(
+                Wheel::from_path(Path::new(invalid)).is_none(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn installs_into_scheme_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let wheel = build_wheel(
+            dir.path(),
+            &[
+                ("demo/__init__.py", b"VERSION = '1.0'\n"),
+                ("demo/cli.py", b"def main():\n    pass\n"),
+                (
+                    "demo-1.0.data/scripts/demo-tool",
+                    b"#!python\nimport demo\n",
+                ),
+                ("demo-1.0.data/scripts/demo-gui", b"#!pythonw -E\npass\n"),
+                ("demo-1.0.data/scripts/demo.sh", b"#!/bin/sh\necho demo\n"),
+                ("demo-1.0.data/purelib/demo_extra.py", b"extra = True\n"),
+                ("demo-1.0.data/platlib/_demo.so", b"\x7fELF"),
+                ("demo-1.0.data/headers/demo.h", b"#define DEMO 1\n"),
+                ("demo-1.0.data/data/share/demo.txt", b"shared\n"),
+            ],
+            &[],
+        );
+        let root = dir.path().join("site");
+        let scheme = scheme(&root);
+        wheel.install(&scheme, INTERPRETER).unwrap();
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        assert_eq!(
+            read(&root.join("purelib/demo/__init__.py")),
+            "VERSION = '1.0'\n"
+        );
+        assert_eq!(read(&root.join("purelib/demo_extra.py")), "extra = True\n");
+        assert!(root.join("platlib/_demo.so").exists());
+        assert_eq!(read(&root.join("include/demo/demo.h")), "#define DEMO 1\n");
+        assert_eq!(read(&root.join("data/share/demo.txt")), "shared\n");
+        assert_eq!(mode(&root.join("purelib/demo/__init__.py")), 0o644);
+
+        // `#!python` placeholders point at the target interpreter
+        assert_eq!(
+            read(&root.join("bin/demo-tool")),
+            format!("#!{}\nimport demo\n", INTERPRETER)
+        );
+        assert_eq!(
+            read(&root.join("bin/demo-gui")),
+            format!("#!{}\npass\n", INTERPRETER)
+        );
+        assert_eq!(read(&root.join("bin/demo.sh")), "#!/bin/sh\necho demo\n");
+        assert_eq!(mode(&root.join("bin/demo-tool")), 0o755);
+
+        // Only console and GUI scripts get launchers
+        let launcher = read(&root.join("bin/demo"));
+        assert!(launcher.starts_with(&format!("#!{}\n", INTERPRETER)));
+        assert!(launcher.contains("from demo.cli import main\n"));
+        assert!(launcher.contains("sys.exit(main())"));
+        assert_eq!(mode(&root.join("bin/demo")), 0o755);
+        assert!(!root.join("bin/ignored").exists());
+
+        let dist_info = root.join("purelib/demo-1.0.dist-info");
+        assert_eq!(
+            read(&dist_info.join("INSTALLER")),
+            format!("{}\n", INSTALLER_NAME)
+        );
+
+        // RECORD lists what was installed, with hashes of the rewritten
+        // contents, and itself without a hash
+        let record = parse_record(&read(&dist_info.join("RECORD"))).unwrap();
+        assert_eq!(record["demo-1.0.dist-info/RECORD"], None);
+        for (path, hash) in record.iter().filter(|(_, hash)| hash.is_some()) {
+            let contents = std::fs::read(root.join("purelib").join(path)).unwrap();
+            verify_record_hash(path, hash.as_deref().unwrap(), &contents).unwrap();
+        }
+        for installed in [
+            "demo/__init__.py",
+            "demo_extra.py",
+            "demo-1.0.dist-info/INSTALLER",
+            "demo-1.0.dist-info/METADATA",
+        ] {
+            assert!(
+                record.contains_key(installed),
+                "{} not in RECORD",
+                installed
+            );
+        }
+        // Files outside purelib are relative to it, never absolute
+        assert!(record.keys().all(|path| !path.starts_with('/')));
+        for installed in ["bin/demo", "bin/demo-tool", "include/demo/demo.h"] {
+            assert!(
+                record.contains_key(&format!("../{}", installed)),
+                "{} not in RECORD",
+                installed
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_files_that_do_not_match_record() {
+        let error = install_error(
+            &[("demo/__init__.py", b"import os; os.system('evil')\n")],
+            &[("demo/__init__.py", b"VERSION = '1.0'\n")],
+        );
+        assert!(
+            error.contains("RECORD hash mismatch for demo/__init__.py"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_files_missing_from_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let wheel = build_wheel(dir.path(), &[("demo/__init__.py", b"")], &[]);
+
+        // Append an entry after RECORD was written
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&wheel.path)
+            .unwrap();
+        let mut zip = zip::ZipWriter::new_append(file).unwrap();
+        zip.start_file("demo/extra.py", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"extra = True\n").unwrap();
+        zip.finish().unwrap();
+
+        let error = format!(
+            "{:#}",
+            wheel
+                .install(&scheme(&dir.path().join("site")), INTERPRETER)
+                .unwrap_err()
+        );
+        assert!(
+            error.contains("demo/extra.py is not listed in RECORD"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_files_without_a_record_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("demo-1.0-py3-none-any.whl");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        let entries: &[Entry] = &[
+            ("demo/__init__.py", b"import os; os.system('evil')\n"),
+            ("demo-1.0.dist-info/WHEEL", WHEEL),
+        ];
+        let mut record = String::new();
+        for (name, contents) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents).unwrap();
+            let hash = if name.ends_with("WHEEL") {
+                record_hash(contents)
+            } else {
+                String::new()
+            };
+            record.push_str(&format!("{},{},{}\n", name, hash, contents.len()));
+        }
+        record.push_str("demo-1.0.dist-info/RECORD,,\n");
+        zip.start_file("demo-1.0.dist-info/RECORD", options)
+            .unwrap();
+        zip.write_all(record.as_bytes()).unwrap();
+        zip.finish().unwrap();
+
+        let root = dir.path().join("site");
+        let error = format!(
+            "{:#}",
+            Wheel::from_path(&path)
+                .unwrap()
+                .install(&scheme(&root), INTERPRETER)
+                .unwrap_err()
+        );
+        assert!(
+            error.contains("demo/__init__.py has no hash in RECORD"),
+            "{}",
+            error
+        );
+        assert!(!root.join("purelib/demo/__init__.py").exists());
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_scheme() {
+        for path in ["../evil.py", "/etc/evil.py", "demo/../../evil.py"] {
+            let error = install_error(&[(path, b"evil = True\n")], &[]);
+            assert!(error.contains("Unsafe path"), "{}: {}", path, error);
+        }
+
+        let error = install_error(
+            &[("demo-1.0.data/purelib/../../evil.py", b"evil = True\n")],
+            &[],
+        );
+        assert!(error.contains("Refusing to write outside"), "{}", error);
+
+        let error = install_error(&[("demo-1.0.data/bogus/evil.py", b"")], &[]);
+        assert!(error.contains("Unknown wheel scheme 'bogus'"), "{}", error);
+    }
+
+    #[test]
+    fn fingerprints_change_when_a_wheel_is_rebuilt_under_the_same_name() {
+        let dir = tempfile::tempdir().unwrap();
+        build_wheel(dir.path(), &[("demo/__init__.py", b"VERSION = 1\n")], &[]);
+        let before = Wheelhouse::scan(dir.path())
+            .unwrap()
+            .fingerprints()
+            .unwrap();
+
+        build_wheel(dir.path(), &[("demo/__init__.py", b"VERSION = 2\n")], &[]);
+        let after = Wheelhouse::scan(dir.path())
+            .unwrap()
+            .fingerprints()
+            .unwrap();
+
+        assert_eq!(before.len(), 1);
+        assert!(before[0].starts_with("demo-1.0-py3-none-any.whl=sha256:"));
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn records_paths_relative_to_site_packages() {
+        let site_packages = Path::new("/tmp/build/opt/venv/lib/python3.12/site-packages");
+        assert_eq!(
+            record_path(&site_packages.join("demo/__init__.py"), site_packages),
+            "demo/__init__.py"
+        );
+        assert_eq!(
+            record_path(Path::new("/tmp/build/opt/venv/bin/demo"), site_packages),
+            "../../../bin/demo"
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Pushes an OCI layout to an in-process stand-in for a Distribution API
+//! registry and checks the request sequence it sees.
+
+use python_oci_packager::builder::PythonImageBuilder;
+use python_oci_packager::layer::Layer;
+use python_oci_packager::layout::{sha256_digest, ImageLayout};
+use python_oci_packager::manifest::Manifest;
+use python_oci_packager::registries::Registries;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const CHUNK: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    query: Option<String>,
+    content_range: Option<String>,
+    body_len: usize,
+}
+
+#[derive(Default)]
+struct Registry {
+    requests: Vec<Request>,
+    blobs: HashMap<String, Vec<u8>>,
+    uploads: HashMap<String, Vec<u8>>,
+    upload_count: usize,
+    manifests: HashMap<String, Vec<u8>>,
+}
+
+type Shared = Arc<Mutex<Registry>>;
+
+/// Serves one request per connection: enough of the Distribution API for
+/// an anonymous push (`/v2/` ping, blob HEAD, POST/PATCH/PUT uploads and
+/// manifest PUT).
+async fn serve(listener: TcpListener, registry: Shared) {
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        let registry = registry.clone();
+        tokio::spawn(async move { handle(stream, registry).await });
+    }
+}
+
+async fn handle(stream: TcpStream, registry: Shared) {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.unwrap();
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap().to_string();
+    let target = parts.next().unwrap().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .map_or(0, |l| l.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.unwrap();
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target, None),
+    };
+
+    let (status, location, response) = {
+        let mut registry = registry.lock().unwrap();
+        registry.requests.push(Request {
+            method: method.clone(),
+            path: path.clone(),
+            query: query.clone(),
+            content_range: headers.get("content-range").cloned(),
+            body_len: body.len(),
+        });
+        route(&mut registry, &method, &path, query.as_deref(), body)
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n",
+        status
+    );
+    if let Some(location) = location {
+        head.push_str(&format!("Location: {}\r\n", location));
+    }
+    head.push_str("\r\n");
+    let stream = reader.get_mut();
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(response.as_bytes()).await.unwrap();
+    stream.shutdown().await.ok();
+}
+
+fn route(
+    registry: &mut Registry,
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+    body: Vec<u8>,
+) -> (&'static str, Option<String>, String) {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        ("GET", ["v2", ""]) => ("200 OK", None, String::new()),
+        ("HEAD", ["v2", "app", "blobs", digest]) => {
+            if registry.blobs.contains_key(*digest) {
+                ("200 OK", None, String::new())
+            } else {
+                ("404 Not Found", None, String::new())
+            }
+        }
+        ("POST", ["v2", "app", "blobs", "uploads", ""]) => {
+            let id = format!("upload-{}", registry.upload_count);
+            registry.upload_count += 1;
+            registry.uploads.insert(id.clone(), Vec::new());
+            (
+                "202 Accepted",
+                Some(format!("/v2/app/blobs/uploads/{}", id)),
+                String::new(),
+            )
+        }
+        ("PATCH", ["v2", "app", "blobs", "uploads", id]) => {
+            registry.uploads.get_mut(*id).unwrap().extend(body);
+            ("202 Accepted", Some(path.to_string()), String::new())
+        }
+        ("PUT", ["v2", "app", "blobs", "uploads", id]) => {
+            let mut data = registry.uploads.remove(*id).unwrap();
+            data.extend(body);
+            let digest = query
+                .and_then(|q| q.strip_prefix("digest="))
+                .unwrap()
+                .replace("%3A", ":");
+            if sha256_digest(&data) != digest {
+                return ("400 Bad Request", None, String::new());
+            }
+            registry.blobs.insert(digest, data);
+            ("201 Created", None, String::new())
+        }
+        ("PUT", ["v2", "app", "manifests", tag]) => {
+            registry.manifests.insert(tag.to_string(), body);
+            ("201 Created", None, String::new())
+        }
+        _ => ("404 Not Found", None, String::new()),
+    }
+}
+
+fn layer(data: Vec<u8>) -> Layer {
+    Layer {
+        media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+        digest: sha256_digest(&data),
+        size: data.len() as u64,
+        compressed_size: data.len() as u64,
+        diff_id: sha256_digest(&[data.as_slice(), b"diff"].concat()),
+        data,
+        annotations: HashMap::new(),
+    }
+}
+
+/// Writes an image with `layers` to a fresh layout tagged `latest`.
+async fn write_layout(dir: &std::path::Path, layers: &[Layer]) -> Vec<u8> {
+    let diff_ids: Vec<String> = layers.iter().map(|l| l.diff_id.clone()).collect();
+    write_layout_with_diff_ids(dir, layers, &diff_ids).await
+}
+
+/// As `write_layout`, with `diff_ids` as the config's `rootfs.diff_ids`.
+async fn write_layout_with_diff_ids(
+    dir: &std::path::Path,
+    layers: &[Layer],
+    diff_ids: &[String],
+) -> Vec<u8> {
+    let layout = ImageLayout::new(dir);
+    let config = serde_json::json!({
+        "architecture": "amd64",
+        "os": "linux",
+        "history": [],
+        "rootfs": {
+            "type": "layers",
+            "diff_ids": diff_ids,
+        },
+    });
+    let config = serde_json::to_vec(&config).unwrap();
+    let config_digest = layout.write_blob(&config).await.unwrap();
+
+    for layer in layers {
+        layout.write_blob(&layer.data).await.unwrap();
+    }
+
+    let manifest = Manifest::new(layers, config.len() as u64, config_digest)
+        .unwrap()
+        .to_bytes()
+        .unwrap();
+    let manifest_digest = layout.write_blob(&manifest).await.unwrap();
+    layout
+        .write_index(&manifest_digest, manifest.len() as u64, "latest")
+        .await
+        .unwrap();
+
+    manifest
+}
+
+#[tokio::test]
+async fn pushes_blobs_in_chunks_and_skips_existing_ones() {
+    let dir = tempfile::tempdir().unwrap();
+    let layers = [
+        layer(b"already in the registry".to_vec()),
+        layer((0..2 * CHUNK + 123).map(|i| (i % 251) as u8).collect()),
+    ];
+    let manifest = write_layout(dir.path(), &layers).await;
+    let [present, large] = &layers;
+
+    let registry = Shared::default();
+    registry
+        .lock()
+        .unwrap()
+        .blobs
+        .insert(present.digest.clone(), present.data.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = format!("{}/app:1.0", listener.local_addr().unwrap());
+    tokio::spawn(serve(listener, registry.clone()));
+
+    PythonImageBuilder::push_layout(dir.path(), "latest", &target, None, &Registries::default())
+        .await
+        .unwrap();
+
+    let registry = registry.lock().unwrap();
+    let config_digest = registry
+        .requests
+        .iter()
+        .find(|r| r.method == "HEAD")
+        .map(|r| r.path.rsplit('/').next().unwrap().to_string())
+        .unwrap();
+    let upload = |n: usize| format!("/v2/app/blobs/uploads/upload-{}", n);
+    let expected = [
+        ("GET", "/v2/".to_string(), None, 0),
+        // Config: absent, so a monolithic upload
+        ("HEAD", format!("/v2/app/blobs/{}", config_digest), None, 0),
+        ("POST", "/v2/app/blobs/uploads/".to_string(), None, 0),
+        ("PUT", upload(0), None, registry.blobs[&config_digest].len()),
+        // Already present: nothing but the existence check
+        ("HEAD", format!("/v2/app/blobs/{}", present.digest), None, 0),
+        // Larger than a chunk: PATCHed in 8 MiB pieces, then committed
+        ("HEAD", format!("/v2/app/blobs/{}", large.digest), None, 0),
+        ("POST", "/v2/app/blobs/uploads/".to_string(), None, 0),
+        ("PATCH", upload(1), Some(format!("0-{}", CHUNK - 1)), CHUNK),
+        (
+            "PATCH",
+            upload(1),
+            Some(format!("{}-{}", CHUNK, 2 * CHUNK - 1)),
+            CHUNK,
+        ),
+        (
+            "PATCH",
+            upload(1),
+            Some(format!("{}-{}", 2 * CHUNK, 2 * CHUNK + 122)),
+            123,
+        ),
+        ("PUT", upload(1), None, 0),
+        (
+            "PUT",
+            "/v2/app/manifests/1.0".to_string(),
+            None,
+            manifest.len(),
+        ),
+    ];
+
+    let seen: Vec<_> = registry
+        .requests
+        .iter()
+        .map(|r| {
+            (
+                r.method.as_str(),
+                r.path.clone(),
+                r.content_range.clone(),
+                r.body_len,
+            )
+        })
+        .collect();
+    assert_eq!(seen, expected);
+
+    let commits: Vec<_> = registry
+        .requests
+        .iter()
+        .filter(|r| r.method == "PUT" && r.path.contains("/uploads/"))
+        .map(|r| r.query.clone().unwrap())
+        .collect();
+    assert_eq!(commits.len(), 2);
+    assert!(commits[1].ends_with(large.digest.trim_start_matches("sha256:")));
+
+    assert_eq!(registry.blobs[&large.digest], large.data);
+    assert_eq!(registry.manifests["1.0"], manifest);
+}
+
+#[tokio::test]
+async fn refuses_layouts_whose_config_does_not_match_the_layers() {
+    let dir = tempfile::tempdir().unwrap();
+    let layers = [layer(b"one".to_vec()), layer(b"two".to_vec())];
+    write_layout_with_diff_ids(dir.path(), &layers, &[layers[0].diff_id.clone()]).await;
+
+    let registry = Shared::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = format!("{}/app:1.0", listener.local_addr().unwrap());
+    tokio::spawn(serve(listener, registry.clone()));
+
+    let error = PythonImageBuilder::push_layout(
+        dir.path(),
+        "latest",
+        &target,
+        None,
+        &Registries::default(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Image config lists 1 diff_ids for 2 layers"
+    );
+    // Nothing reaches the registry
+    assert!(registry.lock().unwrap().requests.is_empty());
+}
+
+#[tokio::test]
+async fn pushes_repeated_layers_once_and_keeps_every_descriptor() {
+    let dir = tempfile::tempdir().unwrap();
+    let layers = [
+        layer(b"empty".to_vec()),
+        layer(b"app".to_vec()),
+        layer(b"empty".to_vec()),
+    ];
+    let empty = &layers[0];
+    let manifest = write_layout(dir.path(), &layers).await;
+
+    let registry = Shared::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = format!("{}/app:1.0", listener.local_addr().unwrap());
+    tokio::spawn(serve(listener, registry.clone()));
+
+    PythonImageBuilder::push_layout(dir.path(), "latest", &target, None, &Registries::default())
+        .await
+        .unwrap();
+
+    let registry = registry.lock().unwrap();
+    let checks = registry
+        .requests
+        .iter()
+        .filter(|r| r.method == "HEAD" && r.path.ends_with(&empty.digest))
+        .count();
+    assert_eq!(checks, 1);
+    assert_eq!(registry.manifests["1.0"], manifest);
+    let pushed: Manifest = serde_json::from_slice(&manifest).unwrap();
+    assert_eq!(pushed.layers.len(), 3);
+}